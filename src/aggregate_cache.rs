@@ -0,0 +1,138 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Context;
+use gluesql::core::ast::{
+    Aggregate, CountArgExpr, Expr, SelectItem, SetExpr, Statement, TableFactor,
+};
+use gluesql::prelude::Payload;
+use serde::{Deserialize, Serialize};
+
+use crate::glue::FileStamp;
+
+/// Directory within the data directory holding cached results
+pub const AGGREGATE_DIR: &str = ".feet-aggregates";
+
+/// Identifies a cached result: what was asked of which version of a table
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// The statement, and everything else which decides its result
+    /// besides the table's contents
+    pub shape: String,
+    pub stamp: FileStamp,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    key: CacheKey,
+    payload: Payload,
+}
+
+/// Results of simple aggregate queries, each in a file named by a hash
+/// of its shape, so a repeated query over an unchanged table isn't
+/// computed again. An entry is replaced once its table changes.
+#[derive(Debug)]
+pub struct AggregateCache {
+    dir: PathBuf,
+    /// Number of results served from the cache
+    hits: AtomicUsize,
+}
+
+impl AggregateCache {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join(AGGREGATE_DIR),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// The cached result for this key, if there's one. An unreadable
+    /// entry is treated as missing, to be overwritten.
+    pub fn get(&self, key: &CacheKey) -> Option<Payload> {
+        let text = std::fs::read_to_string(self.entry_path(key)).ok()?;
+        let entry: Entry = serde_json::from_str(&text).ok()?;
+        if entry.key != *key {
+            return None;
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.payload)
+    }
+
+    pub fn put(&self, key: CacheKey, payload: &Payload) -> anyhow::Result<()> {
+        let path = self.entry_path(&key);
+        let entry = serde_json::json!({ "key": key, "payload": payload });
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating aggregate cache {:?}", self.dir))?;
+        // Written aside then renamed, so a concurrent reader never sees half of it
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, entry.to_string())
+            .with_context(|| format!("writing {:?}", tmp_path))?;
+        std::fs::rename(&tmp_path, &path).with_context(|| format!("writing {:?}", path))?;
+        Ok(())
+    }
+
+    /// Number of results served from the cache so far
+    #[cfg(test)]
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        key.shape.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+}
+
+/// The table a statement aggregates, if it's simple enough to cache:
+/// a SELECT of only aggregates of columns, or `COUNT(*)`, from one table,
+/// without WHERE, GROUP BY, ORDER BY, LIMIT or OFFSET
+pub fn aggregated_table(statement: &Statement) -> Option<&str> {
+    let query = match statement {
+        Statement::Query(query) => query,
+        _ => return None,
+    };
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        SetExpr::Values(_) => return None,
+    };
+    let simple = query.limit.is_none()
+        && query.offset.is_none()
+        && select.from.joins.is_empty()
+        && select.selection.is_none()
+        && select.group_by.is_empty()
+        && select.having.is_none()
+        && select.order_by.is_empty()
+        && !select.projection.is_empty()
+        && select.projection.iter().all(is_column_aggregate);
+    if !simple {
+        return None;
+    }
+    match &select.from.relation {
+        TableFactor::Table { name, .. } => name.0.last().map(String::as_str),
+        _ => None,
+    }
+}
+
+fn is_column_aggregate(item: &SelectItem) -> bool {
+    let aggregate = match item {
+        SelectItem::Expr {
+            expr: Expr::Aggregate(aggregate),
+            ..
+        } => aggregate,
+        _ => return false,
+    };
+    let arg = match aggregate.as_ref() {
+        Aggregate::Count(CountArgExpr::Wildcard) => return true,
+        Aggregate::Count(CountArgExpr::Expr(arg))
+        | Aggregate::Sum(arg)
+        | Aggregate::Max(arg)
+        | Aggregate::Min(arg)
+        | Aggregate::Avg(arg)
+        | Aggregate::Variance(arg)
+        | Aggregate::Stdev(arg) => arg,
+    };
+    matches!(arg, Expr::Identifier(_))
+}
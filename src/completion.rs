@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::glue::{CsvStore, TableData};
+use crate::names::{TableIdentifier, TableName};
+
+/// Offered when completing anything but a table name
+const KEYWORDS: &[&str] = &[
+    "ALTER", "AND", "AS", "ASC", "AVG", "BETWEEN", "BY", "CASE", "CAST", "COUNT", "CREATE",
+    "DELETE", "DESC", "DISTINCT", "DROP", "ELSE", "END", "EXISTS", "FALSE", "FROM", "GROUP",
+    "HAVING", "IN", "INNER", "INSERT", "INTO", "IS", "JOIN", "LEFT", "LIKE", "LIMIT", "MAX", "MIN",
+    "NOT", "NULL", "OFFSET", "ON", "OR", "ORDER", "OUTER", "SELECT", "SET", "SUM", "TABLE", "THEN",
+    "TRUE", "UPDATE", "VALUES", "WHEN", "WHERE",
+];
+
+/// Keywords after which a table name is expected
+const TABLE_KEYWORDS: &[&str] = &["FROM", "JOIN", "INTO", "UPDATE"];
+
+/// Tab completion of SQL keywords and table names in the repl.
+/// The editor can't reach the store while a query runs, so the
+/// table names are refreshed by the repl between statements.
+#[derive(Default)]
+pub struct SqlHelper {
+    pub tables: Vec<String>,
+}
+
+impl Completer for SqlHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(completions(&line[..pos], &self.tables))
+    }
+}
+
+impl Hinter for SqlHelper {
+    type Hint = String;
+}
+
+impl Highlighter for SqlHelper {}
+
+impl Validator for SqlHelper {}
+
+impl Helper for SqlHelper {}
+
+/// The identifiers of all tables within the data directory, recursively,
+/// leaving out anything matching `ignores`
+pub fn table_identifiers(store: &CsvStore) -> anyhow::Result<Vec<String>> {
+    let mut identifiers = Vec::new();
+    let root = TableIdentifier::new(String::new(), store.data_dir.clone());
+    // Each directory comes with the canonical paths of its ancestors
+    let mut dirs: Vec<(TableName, Vec<PathBuf>)> = vec![(root.try_into()?, Vec::new())];
+    while let Some((dir, mut ancestors)) = dirs.pop() {
+        let Some((canonical, nodes)) = store.list_within(dir, &ancestors)? else {
+            continue;
+        };
+        ancestors.push(canonical);
+        for node in nodes {
+            match node.data {
+                TableData::Table(_) => {
+                    let table_id: TableIdentifier = node.name.try_into()?;
+                    identifiers.push(table_id.to_string());
+                }
+                TableData::Dir => dirs.push((node.name, ancestors.clone())),
+            }
+        }
+    }
+    identifiers.sort();
+
+    Ok(identifiers)
+}
+
+/// Where the word ending at the end of `before` starts, and its
+/// completions: table names after e.g. `FROM`, and keywords otherwise
+fn completions(before: &str, tables: &[String]) -> (usize, Vec<String>) {
+    let start = before
+        .rfind(|c: char| c.is_whitespace() || matches!(c, ',' | '(' | ')' | ';'))
+        .map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let previous = before[..start]
+        .trim_end()
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+
+    if TABLE_KEYWORDS.contains(&previous.to_uppercase().as_str()) {
+        let prefix = word.strip_prefix('"').unwrap_or(word);
+        let candidates = tables
+            .iter()
+            .filter(|table| table.starts_with(prefix))
+            .map(|table| quote_table(table))
+            .collect();
+        return (start, candidates);
+    }
+
+    if word.is_empty() || word.starts_with('"') {
+        return (start, Vec::new());
+    }
+    let upper = word.to_uppercase();
+    let lowercase = word == word.to_lowercase();
+    let candidates = KEYWORDS
+        .iter()
+        .filter(|keyword| keyword.starts_with(&upper))
+        .map(|keyword| match lowercase {
+            true => keyword.to_lowercase(),
+            false => keyword.to_string(),
+        })
+        .collect();
+    (start, candidates)
+}
+
+/// A table name as written in SQL, quoted unless it's a plain identifier
+fn quote_table(table: &str) -> String {
+    let plain = table.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match plain {
+        true => table.to_string(),
+        false => format!("\"{}\"", table),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_completions() {
+        let tables = vec!["logs/2024".to_string(), "orders".to_string()];
+
+        assert_eq!(completions("sel", &tables), (0, vec!["select".to_string()]));
+        assert_eq!(
+            completions("SELECT a, COU", &tables),
+            (10, vec!["COUNT".to_string()])
+        );
+        assert_eq!(
+            completions("SELECT * FROM o", &tables),
+            (14, vec!["orders".to_string()])
+        );
+        assert_eq!(
+            completions("select * from orders join \"lo", &tables),
+            (26, vec!["\"logs/2024\"".to_string()])
+        );
+        assert_eq!(completions("INSERT INTO ", &tables).1.len(), 2);
+        assert!(completions("SELECT 'a ", &tables).1.is_empty());
+    }
+
+    #[test]
+    fn test_table_identifiers() {
+        let tmpdir = tempdir::TempDir::new("feet-completion").expect("tmpdir");
+        std::fs::create_dir(tmpdir.path().join("logs")).expect("mkdir");
+        std::fs::write(tmpdir.path().join("logs/2024.csv"), "a\n1\n").expect("write");
+        std::fs::write(tmpdir.path().join("orders.csv"), "a\n1\n").expect("write");
+        std::fs::write(tmpdir.path().join(".hidden.csv"), "a\n1\n").expect("write");
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path").to_owned(),
+            ignores: vec![".*".to_string()],
+            ..Config::default()
+        };
+        let store = CsvStore::try_new(config).expect("CsvStore::try_new");
+
+        let tables = table_identifiers(&store).expect("tables");
+        assert_eq!(tables, vec!["logs/2024", "orders"]);
+    }
+}
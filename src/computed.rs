@@ -0,0 +1,97 @@
+// GlueSQL's error type is large, but it's what the evaluator returns
+#![allow(clippy::result_large_err)]
+
+use anyhow::{anyhow, Context};
+use gluesql::core::ast::{ColumnDef, ColumnOption, ColumnOptionDef, DataType, Expr};
+use gluesql::core::data::Row;
+use gluesql::core::executor::evaluate_stateless;
+use gluesql::core::parse_sql::{parse_data_type, parse_expr};
+use gluesql::core::result::Result as GlueResult;
+use gluesql::core::translate::{translate_data_type, translate_expr};
+use gluesql::prelude::Value;
+
+use crate::config::ComputedColumn;
+
+/// A computed column, parsed and ready to evaluate
+#[derive(Clone, Debug)]
+pub struct Computed {
+    name: String,
+    data_type: DataType,
+    /// The configured expression, cast to `data_type`
+    expr: Expr,
+}
+
+impl Computed {
+    pub fn parse(spec: &ComputedColumn) -> anyhow::Result<Self> {
+        let context = || format!("computed column {:?}", spec.name);
+
+        let data_type = parse_data_type(&spec.data_type)
+            .and_then(|sql_type| translate_data_type(&sql_type))
+            .map_err(|err| anyhow!("{}", err))
+            .with_context(context)?;
+        let expr = parse_expr(&spec.expr)
+            .and_then(|sql_expr| translate_expr(&sql_expr))
+            .map_err(|err| anyhow!("{}", err))
+            .with_context(context)?;
+
+        Ok(Self {
+            name: spec.name.clone(),
+            data_type: data_type.clone(),
+            expr: Expr::Cast {
+                expr: Box::new(expr),
+                data_type,
+            },
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn column_def(&self) -> ColumnDef {
+        ColumnDef {
+            name: self.name.clone(),
+            data_type: self.data_type.clone(),
+            options: vec![ColumnOptionDef {
+                name: None,
+                option: ColumnOption::Null,
+            }],
+        }
+    }
+}
+
+/// Append the values of computed columns to a row,
+/// whose columns are named by `columns`
+pub fn append_computed(columns: &[String], row: Row, computed: &[Computed]) -> GlueResult<Row> {
+    let mut values = Vec::with_capacity(row.0.len() + computed.len());
+    for column in computed {
+        let evaluated = evaluate_stateless(Some((columns, &row)), &column.expr)?;
+        values.push(Value::try_from(evaluated)?);
+    }
+
+    let Row(mut row_values) = row;
+    row_values.extend(values);
+
+    Ok(Row(row_values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_computed() {
+        let spec = ComputedColumn {
+            name: "total".to_string(),
+            expr: "qty * price".to_string(),
+            data_type: "FLOAT".to_string(),
+        };
+        let computed = Computed::parse(&spec).expect("parse");
+
+        let columns = vec!["qty".to_string(), "price".to_string()];
+        let row = Row(vec![Value::I32(3), Value::F64(1.5)]);
+        let row = append_computed(&columns, row, &[computed]).expect("evaluate");
+
+        assert_eq!(row.0[2], Value::F64(4.5));
+    }
+}
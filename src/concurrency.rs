@@ -0,0 +1,64 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The number of threads to use when none is configured
+pub fn default_max_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Apply `f` to each item on up to `max_concurrency` threads, returning
+/// the results in the same order as the items. With a limit of 1 (or a
+/// single item), everything runs on the calling thread.
+pub fn map_bounded<T, R, F>(items: &[T], max_concurrency: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let nthreads = max_concurrency.clamp(1, items.len().max(1));
+    if nthreads == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..nthreads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = f(item);
+                *results[i].lock().expect("result lock poisoned") = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| {
+            result
+                .into_inner()
+                .expect("result lock poisoned")
+                .expect("every item is mapped")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_bounded_keeps_order() {
+        let items: Vec<u64> = (0..100).collect();
+        let squares: Vec<_> = items.iter().map(|x| x * x).collect();
+
+        for max_concurrency in [1, 4, 1000] {
+            assert_eq!(map_bounded(&items, max_concurrency, |x| x * x), squares);
+        }
+        assert!(map_bounded(&[] as &[u64], 4, |x| *x).is_empty());
+    }
+}
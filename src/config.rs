@@ -1,14 +1,70 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use chrono::format::{Item, StrftimeItems};
+use globset::Glob;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::computed::Computed;
+use crate::glue::{expand_path, type_detector};
+use crate::output::delimiter_byte;
+use crate::views::Views;
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             data_dir: "~/feet".into(),
             ignores: vec![".git".to_string()],
+            writable_paths: Vec::new(),
+            normalize_unicode: false,
+            output_formats: HashMap::new(),
+            fixed_width: HashMap::new(),
+            overlay: false,
+            regex_delimiter: None,
+            write_batch_size: None,
+            cache_dir: None,
+            quarantine: None,
+            age_identity_file: None,
+            partitions: HashMap::new(),
+            lenient_parsing: false,
+            literal_paths: false,
+            null_string: default_null_string(),
+            transpose_rows: TransposeRows::default(),
+            display_order: HashMap::new(),
+            column_aliases: HashMap::new(),
+            has_headers: true,
+            sanitize_column_names: false,
+            drop_trailing_empty_column: false,
+            modelines: false,
+            progress: false,
+            wal: false,
+            durable_writes: false,
+            filename_policy: FilenamePolicy::default(),
+            max_concurrency: None,
+            inference_sample_rows: default_inference_sample_rows(),
+            date_formats: Vec::new(),
+            type_detectors: Vec::new(),
+            float_to_int: FloatToInt::default(),
+            comment: None,
+            delimiter: default_delimiter(),
+            extensions: default_extensions(),
+            infer_not_null: default_infer_not_null(),
+            delete_mode: DeleteMode::default(),
+            computed_columns: HashMap::new(),
+            views: HashMap::new(),
+            aggregate_cache: false,
+            require_confirm_destructive: false,
         }
     }
 }
 
+/// Settings, read from `config.toml`.
+///
+/// NULL is stored as an empty cell, whatever `null_string` is: it only
+/// changes how NULLs are displayed, so a cell holding the text `NULL`
+/// can be told apart from a missing value by setting it to e.g. `∅`.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     // TODO: data_dir should be a shell-expanded and canonicalized PathBuf
@@ -19,4 +75,560 @@ pub struct Config {
     /// File patterns to ignore when listing files/directories.
     /// Interpreted by globset.
     pub ignores: Vec<String>,
+
+    /// If non-empty, only tables whose paths (relative to `data_dir`,
+    /// without extension, e.g. `scratch/t`) match one of these globs
+    /// can be modified. Interpreted by globset.
+    #[serde(default)]
+    pub writable_paths: Vec<String>,
+
+    /// Compare file, directory and column names by their
+    /// Unicode NFC form, so e.g. NFD filenames on macOS
+    /// can be queried with NFC identifiers.
+    #[serde(default)]
+    pub normalize_unicode: bool,
+
+    /// chrono format strings used when writing dates and timestamps,
+    /// keyed by table identifier and then column name.
+    /// Text values in those columns that parse as ISO-8601
+    /// dates or timestamps are reformatted as well.
+    #[serde(default)]
+    pub output_formats: HashMap<String, HashMap<String, String>>,
+
+    /// Column layouts of read-only fixed-width tables, keyed by
+    /// table identifier. The table is read from a `.fw` or `.txt` file.
+    #[serde(default)]
+    pub fixed_width: HashMap<String, FixedWidthSpec>,
+
+    /// Hold edits in memory, visible to queries,
+    /// until they're explicitly flushed to disk.
+    #[serde(default)]
+    pub overlay: bool,
+
+    /// Split fields on matches of this regex (e.g. `\s*::\s*`)
+    /// instead of parsing CSV. Fields are taken verbatim, since quoting
+    /// isn't supported, and tables are read-only in this mode.
+    #[serde(default)]
+    pub regex_delimiter: Option<String>,
+
+    /// When appending rows, flush to disk after every this many records
+    /// so that write errors surface early, along with how many rows
+    /// were written. By default, rows are flushed once at the end.
+    #[serde(default)]
+    pub write_batch_size: Option<usize>,
+
+    /// Local directory in which to keep copies of tables read from
+    /// `data_dir` (e.g. on a slow network mount). Copies are refreshed
+    /// whenever the original's modification time or size changes.
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+
+    /// File to which rows with values that can't be read as their column's
+    /// type are appended while scanning, as CSV with the table, line
+    /// number and error before the row's fields. The rows are skipped,
+    /// rather than failing the query.
+    #[serde(default)]
+    pub quarantine: Option<String>,
+
+    /// File holding the age identity (`AGE-SECRET-KEY-1...`) with which
+    /// `.csv.age` tables are decrypted, unless `$FEET_AGE_KEY` is set.
+    /// Encrypted tables are read-only, and are only decrypted in memory.
+    /// Requires the `encryption` feature.
+    #[serde(default)]
+    pub age_identity_file: Option<String>,
+
+    /// Directories whose CSV files are partitions of a single read-only
+    /// table, mapped to the name of a virtual column holding each row's
+    /// file name (e.g. `events = "month"` for `events/2023-01.csv`).
+    /// String comparisons on that column in a query's WHERE clause
+    /// limit which files are read.
+    #[serde(default)]
+    pub partitions: HashMap<String, String>,
+
+    /// Read CSV files a line at a time, so that a malformed row
+    /// (e.g. with a stray `"`) is recovered with a warning
+    /// rather than failing the whole scan
+    #[serde(default)]
+    pub lenient_parsing: bool,
+
+    /// Take `data_dir`, `cache_dir`, `quarantine` and `age_identity_file` literally,
+    /// rather than expanding a leading `~` to the home directory
+    #[serde(default)]
+    pub literal_paths: bool,
+
+    /// Shown for NULL values in query results (see above)
+    #[serde(default = "default_null_string")]
+    pub null_string: String,
+
+    /// How `--transpose` shows results with more than one row
+    #[serde(default)]
+    pub transpose_rows: TransposeRows,
+
+    /// Columns to show first when displaying the results of `SELECT *`
+    /// from a table, keyed by table identifier. Other columns follow in
+    /// their usual order; explicitly selected columns keep their order.
+    /// This only affects display, not the file or SQL semantics.
+    #[serde(default)]
+    pub display_order: HashMap<String, Vec<String>>,
+
+    /// Names to show columns by in query results and exports, keyed by
+    /// table identifier and then column name. Queries still use the
+    /// column's own name. Overridden by `--rename`.
+    #[serde(default)]
+    pub column_aliases: HashMap<String, HashMap<String, String>>,
+
+    /// Whether the first line of each CSV file is a header row.
+    /// If not, columns are named `col1..colN`, and writes never add
+    /// a header, so a table created by `CREATE TABLE` starts out empty
+    /// and takes its columns from the first rows appended.
+    #[serde(default = "default_has_headers")]
+    pub has_headers: bool,
+
+    /// Give columns names which can be used in SQL without quoting,
+    /// made from their headers: e.g. `First Name` becomes `First_Name`
+    /// (see `names::column_identifiers` for the rules). Results are
+    /// shown, and files written, with the original headers.
+    #[serde(default)]
+    pub sanitize_column_names: bool,
+
+    /// Drop a CSV file's last column if its header is empty, as when every
+    /// line ends with a trailing comma. Its values must all be empty,
+    /// and rows written to the file get an empty value for it.
+    #[serde(default)]
+    pub drop_trailing_empty_column: bool,
+
+    /// Read parsing options for a CSV file from its first line, if it
+    /// looks like `# feet: delimiter=\t has_headers=false`, overriding
+    /// the options here. Files with a modeline are read-only.
+    #[serde(default)]
+    pub modelines: bool,
+
+    /// Show a progress bar on stderr while reading large files,
+    /// if it's a terminal
+    #[serde(default)]
+    pub progress: bool,
+
+    /// Keep a write-ahead log in the data directory, saving a copy of
+    /// each file before it's modified, so that `feet recover` can roll
+    /// back mutations interrupted by a crash
+    #[serde(default)]
+    pub wal: bool,
+
+    /// Sync each modified file to disk (and the directory of a file
+    /// replaced by renaming) before a write is reported as done,
+    /// so it isn't lost on power failure. Slower, so off by default.
+    #[serde(default)]
+    pub durable_writes: bool,
+
+    /// Most threads to read or write tables with at once, in
+    /// `feet inventory` and `feet export-all`. Defaults to the number of
+    /// CPUs; lower it to avoid overwhelming e.g. a network mount, or set
+    /// it to 1 to do everything in order on one thread.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+
+    /// Number of rows read to infer each table's column types, or 0 to
+    /// read them all. Sampling makes opening large tables much faster,
+    /// but a value later on which doesn't fit the inferred type is an
+    /// error when the table is read.
+    #[serde(default = "default_inference_sample_rows")]
+    pub inference_sample_rows: usize,
+
+    /// Treat columns with a value in every sampled row as NOT NULL, so
+    /// inserting a NULL into one fails. Set to false to allow NULL in
+    /// every column. Columns of tables with a schema file can be NULL.
+    #[serde(default = "default_infer_not_null")]
+    pub infer_not_null: bool,
+
+    /// chrono format strings of dates and timestamps which aren't ISO-8601,
+    /// e.g. `%d/%m/%Y`, so columns of them are typed rather than text.
+    /// A value is read with the first format it fits, so put the likelier
+    /// of two ambiguous formats first. Such columns are written back in
+    /// their format, unless `output_formats` says otherwise.
+    #[serde(default)]
+    pub date_formats: Vec<String>,
+
+    /// Custom column types, e.g. to keep zip codes as text by reading
+    /// values matching `\d{5}` as `TEXT`. Each value is checked against
+    /// the patterns in order, before the built-in inference; a column
+    /// with values of different types is read as text.
+    #[serde(default)]
+    pub type_detectors: Vec<TypeDetectorSpec>,
+
+    /// What to do with file names which aren't valid UTF-8
+    #[serde(default)]
+    pub filename_policy: FilenamePolicy,
+
+    /// What to do with a fractional number in an integer column, whether
+    /// inserted, loaded or imported from CSV, or read from a table's file
+    #[serde(default)]
+    pub float_to_int: FloatToInt,
+
+    /// Skip lines of CSV files starting with this (ASCII) character,
+    /// e.g. `#`. Comment lines aren't rows, so they have no row ids.
+    #[serde(default)]
+    pub comment: Option<char>,
+
+    /// Character between the fields of CSV files, e.g. `"\t"` for
+    /// tab-separated or `"|"` for pipe-separated tables. A file's
+    /// modeline overrides it.
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+
+    /// Extensions of the files which are tables, e.g. `["csv", "tsv", "txt"]`,
+    /// in order of preference when a table has files with several of them.
+    /// New tables get the first. `.tsv` files are tab-separated regardless
+    /// of `delimiter`.
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+
+    /// What `DELETE` does with rows. Tombstoning requires `comment`.
+    #[serde(default)]
+    pub delete_mode: DeleteMode,
+
+    /// Read-only columns computed from each row with a SQL expression,
+    /// keyed by table identifier. They're queryable like stored columns,
+    /// but never written to disk: writes only set the stored columns.
+    #[serde(default)]
+    pub computed_columns: HashMap<String, Vec<ComputedColumn>>,
+
+    /// Named SELECT queries which can be queried like tables, e.g.
+    /// `big_orders = "SELECT * FROM orders WHERE total > 100"`.
+    /// Views may use other views, but not themselves.
+    #[serde(default)]
+    pub views: HashMap<String, String>,
+
+    /// Keep the results of simple aggregate queries, e.g.
+    /// `SELECT COUNT(*), MAX(ts) FROM big`, in a `.feet-aggregates`
+    /// directory within `data_dir`, so repeating one is instant until the
+    /// table's file changes. Only aggregates of the columns of a single
+    /// CSV table, without WHERE or GROUP BY, are cached.
+    #[serde(default)]
+    pub aggregate_cache: bool,
+
+    /// Refuse to run statements which delete or overwrite data (DELETE,
+    /// DROP, TRUNCATE, UPDATE without WHERE and ALTER TABLE ... DROP
+    /// COLUMN), the `replace` command, or `--force` overwriting an
+    /// existing file, without `--confirm`. The repl asks before running
+    /// such statements instead.
+    #[serde(default)]
+    pub require_confirm_destructive: bool,
+}
+
+impl Config {
+    /// Every problem with these settings which can be found without
+    /// reading any tables: missing directories and files, invalid
+    /// patterns and expressions, and options which conflict
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |result: anyhow::Result<()>| {
+            if let Err(err) = result {
+                problems.push(format!("{:#}", err));
+            }
+        };
+        let literal = self.literal_paths;
+
+        check(
+            expand_path(&self.data_dir, literal)
+                .and_then(|dir| check_dir(&dir, true))
+                .with_context(|| format!("data_dir {:?}", self.data_dir)),
+        );
+        if let Some(dir) = &self.cache_dir {
+            check(
+                expand_path(dir, literal)
+                    .and_then(|dir| check_dir(&dir, false))
+                    .with_context(|| format!("cache_dir {:?}", dir)),
+            );
+        }
+        if let Some(path) = &self.quarantine {
+            check(
+                expand_path(path, literal)
+                    .and_then(|path| match path.parent() {
+                        Some(parent) if !parent.as_os_str().is_empty() => check_dir(parent, true),
+                        _ => Ok(()),
+                    })
+                    .with_context(|| format!("quarantine {:?}", path)),
+            );
+        }
+        if let Some(path) = &self.age_identity_file {
+            check(
+                expand_path(path, literal)
+                    .and_then(|path| Ok(std::fs::metadata(path).map(drop)?))
+                    .with_context(|| format!("age_identity_file {:?}", path)),
+            );
+        }
+
+        for pattern in &self.ignores {
+            check(
+                Glob::new(pattern)
+                    .map(drop)
+                    .with_context(|| format!("ignore {:?}", pattern)),
+            );
+        }
+        if let Some(pattern) = &self.regex_delimiter {
+            check(Regex::new(pattern).map(drop).context("regex_delimiter"));
+            if self.lenient_parsing {
+                check(Err(anyhow!(
+                    "lenient_parsing has no effect when regex_delimiter is set"
+                )));
+            }
+            if self.delimiter != default_delimiter() {
+                check(Err(anyhow!(
+                    "delimiter has no effect when regex_delimiter is set"
+                )));
+            }
+        }
+        check(
+            delimiter_byte(self.delimiter)
+                .map(drop)
+                .context("delimiter"),
+        );
+        if self.extensions.is_empty() {
+            check(Err(anyhow!("extensions: at least one is needed")));
+        }
+        for ext in &self.extensions {
+            let ext = ext.trim_start_matches('.');
+            if ext.is_empty() || ext.contains(std::path::is_separator) {
+                check(Err(anyhow!("extension {:?} is invalid", ext)));
+            }
+        }
+        match self.comment {
+            Some(c) if !c.is_ascii() => {
+                check(Err(anyhow!("comment {:?} isn't an ASCII character", c)))
+            }
+            Some(c) if c == self.delimiter => {
+                check(Err(anyhow!("comment {:?} is the same as the delimiter", c)))
+            }
+            None if self.delete_mode == DeleteMode::Tombstone => check(Err(anyhow!(
+                "delete_mode = \"tombstone\" requires a comment character"
+            ))),
+            _ => {}
+        }
+
+        for format in &self.date_formats {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                check(Err(anyhow!("date format {:?} is invalid", format)));
+            }
+        }
+        for spec in &self.type_detectors {
+            check(type_detector(spec).map(drop));
+        }
+        for table in self.fixed_width.keys() {
+            if self.partitions.contains_key(table) {
+                check(Err(anyhow!(
+                    "table {:?} is both fixed-width and partitioned",
+                    table
+                )));
+            }
+        }
+        for (table, specs) in &self.computed_columns {
+            for spec in specs {
+                check(
+                    Computed::parse(spec)
+                        .map(drop)
+                        .with_context(|| format!("table {:?}", table)),
+                );
+            }
+        }
+        check(Views::parse(&self.views).map(drop));
+
+        problems
+    }
+}
+
+/// Fail unless `path` is a directory, or (if it needn't exist) is missing
+fn check_dir(path: &Path, must_exist: bool) -> anyhow::Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(anyhow!("not a directory")),
+        Err(err) if must_exist || err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// e.g. `{ name = "total", expr = "qty * price", type = "FLOAT" }`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ComputedColumn {
+    pub name: String,
+    /// SQL expression over the table's stored columns
+    pub expr: String,
+    /// SQL data type, which the expression's value is cast to
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+/// e.g. `{ pattern = '\d{5}', type = "TEXT" }`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TypeDetectorSpec {
+    /// Regex which must match a whole value
+    pub pattern: String,
+    /// SQL data type to read matching values as
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+/// Handling of file names which aren't valid UTF-8
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilenamePolicy {
+    /// Fail
+    #[default]
+    Strict,
+    /// Leave the file out, with a warning
+    Skip,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+}
+
+/// Display of several rows with `--transpose`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransposeRows {
+    /// As the usual grid, with a column per field
+    #[default]
+    Grid,
+    /// As a `field | value` table per row, one after another
+    Each,
+}
+
+/// Handling of fractional numbers going into integer columns
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FloatToInt {
+    /// Fail, unless the number is whole (e.g. `2.0`)
+    #[default]
+    Error,
+    /// Round towards zero
+    Truncate,
+    /// Round to the nearest integer, with halves away from zero
+    Round,
+}
+
+/// How deleted rows are dropped from a CSV file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+    /// Remove the rows' lines
+    #[default]
+    Remove,
+    /// Comment the rows' lines out with the `comment` character, so
+    /// they're hidden from queries but can be recovered by hand
+    Tombstone,
+}
+
+fn default_has_headers() -> bool {
+    true
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_extensions() -> Vec<String> {
+    vec!["csv".to_string()]
+}
+
+fn default_infer_not_null() -> bool {
+    true
+}
+
+fn default_null_string() -> String {
+    "NULL".to_string()
+}
+
+fn default_inference_sample_rows() -> usize {
+    1000
+}
+
+/// Layout of a fixed-width (column-aligned) text file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FixedWidthSpec {
+    pub columns: Vec<FixedWidthColumn>,
+
+    /// Number of leading lines (e.g. a title or header) to skip
+    #[serde(default)]
+    pub skip_lines: usize,
+}
+
+/// A named byte range `start..end` within each line
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FixedWidthColumn {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let tmpdir = tempdir::TempDir::new("feet-config").expect("tmpdir");
+        let data_dir = tmpdir.path().to_str().expect("path").to_owned();
+        let config = Config {
+            data_dir: data_dir.clone(),
+            ..Config::default()
+        };
+        assert_eq!(config.validate(), Vec::<String>::new());
+
+        let file = tmpdir.path().join("file");
+        std::fs::write(&file, "").expect("write");
+        let config = Config {
+            data_dir: tmpdir
+                .path()
+                .join("missing")
+                .to_str()
+                .expect("path")
+                .to_owned(),
+            cache_dir: Some(file.to_str().expect("path").to_owned()),
+            ignores: vec!["[abc".to_string()],
+            regex_delimiter: Some("(".to_string()),
+            lenient_parsing: true,
+            delimiter: '"',
+            extensions: vec!["csv".to_string(), ".".to_string()],
+            delete_mode: DeleteMode::Tombstone,
+            date_formats: vec!["%d/%m/%Y".to_string(), "%Q".to_string()],
+            type_detectors: vec![TypeDetectorSpec {
+                pattern: "\\d{5}".to_string(),
+                data_type: "ZIP".to_string(),
+            }],
+            computed_columns: [(
+                "t".to_string(),
+                vec![ComputedColumn {
+                    name: "c".to_string(),
+                    expr: "a +".to_string(),
+                    data_type: "INT".to_string(),
+                }],
+            )]
+            .into(),
+            views: [("v".to_string(), "DELETE FROM t".to_string())].into(),
+            ..Config::default()
+        };
+        let problems = config.validate();
+        let expected = [
+            "data_dir",
+            "cache_dir",
+            "ignore \"[abc\"",
+            "regex_delimiter",
+            "lenient_parsing has no effect",
+            "delimiter has no effect",
+            "delimiter: the delimiter can't be the quote character",
+            "extension \"\" is invalid",
+            "requires a comment character",
+            "date format \"%Q\"",
+            "type detector \"\\\\d{5}\"",
+            "computed column \"c\"",
+            "view \"v\"",
+        ];
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(
+                problem.contains(expected),
+                "{:?} in {:?}",
+                expected,
+                problem
+            );
+        }
+        assert_eq!(problems.len(), expected.len(), "{:#?}", problems);
+    }
 }
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::{bail, Context};
+use csv::StringRecord;
+
+use crate::glue::{CsvStore, RecordIter};
+
+/// Numbers of rows which differ between two tables
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffCounts {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+/// Compare two tables, writing a line to `out` for each difference as it's found:
+/// `-column c` / `+column c` for columns only in the left / right table, then
+/// `-<row>: values` for removed rows, `+<row>: values` for added rows
+/// and `~<row>: col: old -> new, ...` for changed rows.
+///
+/// Rows are matched by position (`row 1` being the first data row),
+/// or by the value of their `key` column (e.g. `id=3`), in which case
+/// the right table is held in memory. Rows are only compared on the
+/// columns both tables share.
+pub fn diff_tables<W: Write>(
+    store: &CsvStore,
+    left: &str,
+    right: &str,
+    key: Option<&str>,
+    out: &mut W,
+) -> anyhow::Result<DiffCounts> {
+    let (left_headers, left_records) = store.table_records(left)?;
+    let (right_headers, right_records) = store.table_records(right)?;
+
+    let mut differ = Differ {
+        shared: shared_columns(&left_headers, &right_headers),
+        left_headers,
+        right_headers,
+        out,
+        counts: DiffCounts::default(),
+    };
+    differ.diff_schema()?;
+    match key {
+        Some(key) => differ.diff_keyed(left_records, right_records, key)?,
+        None => differ.diff_positional(left_records, right_records)?,
+    }
+
+    Ok(differ.counts)
+}
+
+/// Indices in the left and right headers of each column they share
+fn shared_columns(left: &[String], right: &[String]) -> Vec<(usize, usize)> {
+    left.iter()
+        .enumerate()
+        .filter_map(|(i, name)| Some((i, right.iter().position(|other| other == name)?)))
+        .collect()
+}
+
+/// A record's values as a line of CSV
+fn csv_line(record: &StringRecord) -> anyhow::Result<String> {
+    let mut writer = csv::WriterBuilder::new().from_writer(Vec::new());
+    writer.write_record(record)?;
+    let line = String::from_utf8(writer.into_inner()?)?;
+
+    Ok(line.trim_end().to_string())
+}
+
+struct Differ<'a, W> {
+    left_headers: Vec<String>,
+    right_headers: Vec<String>,
+    shared: Vec<(usize, usize)>,
+    out: &'a mut W,
+    counts: DiffCounts,
+}
+
+impl<W: Write> Differ<'_, W> {
+    fn diff_schema(&mut self) -> anyhow::Result<()> {
+        for name in &self.left_headers {
+            if !self.right_headers.contains(name) {
+                writeln!(self.out, "-column {}", name)?;
+            }
+        }
+        for name in &self.right_headers {
+            if !self.left_headers.contains(name) {
+                writeln!(self.out, "+column {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn removed(&mut self, label: &str, record: &StringRecord) -> anyhow::Result<()> {
+        writeln!(self.out, "-{}: {}", label, csv_line(record)?)?;
+        self.counts.removed += 1;
+        Ok(())
+    }
+
+    fn added(&mut self, label: &str, record: &StringRecord) -> anyhow::Result<()> {
+        writeln!(self.out, "+{}: {}", label, csv_line(record)?)?;
+        self.counts.added += 1;
+        Ok(())
+    }
+
+    fn compare(
+        &mut self,
+        label: &str,
+        left: &StringRecord,
+        right: &StringRecord,
+    ) -> anyhow::Result<()> {
+        let changes: Vec<_> = self
+            .shared
+            .iter()
+            .filter_map(|&(i, j)| {
+                let old = left.get(i).unwrap_or_default();
+                let new = right.get(j).unwrap_or_default();
+                (old != new).then(|| format!("{}: {} -> {}", self.left_headers[i], old, new))
+            })
+            .collect();
+
+        if !changes.is_empty() {
+            writeln!(self.out, "~{}: {}", label, changes.join(", "))?;
+            self.counts.changed += 1;
+        }
+
+        Ok(())
+    }
+
+    fn diff_positional(
+        &mut self,
+        mut left: RecordIter,
+        mut right: RecordIter,
+    ) -> anyhow::Result<()> {
+        for row_num in 1.. {
+            let label = format!("row {}", row_num);
+            match (left.next().transpose()?, right.next().transpose()?) {
+                (Some(l), Some(r)) => self.compare(&label, &l, &r)?,
+                (Some(l), None) => self.removed(&label, &l)?,
+                (None, Some(r)) => self.added(&label, &r)?,
+                (None, None) => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn diff_keyed(&mut self, left: RecordIter, right: RecordIter, key: &str) -> anyhow::Result<()> {
+        let key_index = |headers: &[String], side: &str| {
+            headers
+                .iter()
+                .position(|name| name == key)
+                .with_context(|| format!("no key column {:?} in {} table", key, side))
+        };
+        let left_key = key_index(&self.left_headers, "left")?;
+        let right_key = key_index(&self.right_headers, "right")?;
+
+        // Right rows in their original order, taken as they're matched
+        let mut right_rows = Vec::new();
+        let mut right_indices = HashMap::new();
+        for record_res in right {
+            let record = record_res?;
+            let value = record.get(right_key).unwrap_or_default().to_owned();
+            if right_indices
+                .insert(value.clone(), right_rows.len())
+                .is_some()
+            {
+                bail!("duplicate key {}={:?} in right table", key, value);
+            }
+            right_rows.push(Some((value, record)));
+        }
+
+        let mut seen = HashSet::new();
+        for record_res in left {
+            let record = record_res?;
+            let value = record.get(left_key).unwrap_or_default();
+            if !seen.insert(value.to_owned()) {
+                bail!("duplicate key {}={:?} in left table", key, value);
+            }
+
+            let label = format!("{}={}", key, value);
+            let matched = right_indices
+                .get(value)
+                .and_then(|&index| right_rows[index].take());
+            match matched {
+                Some((_, right_record)) => self.compare(&label, &record, &right_record)?,
+                None => self.removed(&label, &record)?,
+            }
+        }
+
+        for (value, record) in right_rows.into_iter().flatten() {
+            self.added(&format!("{}={}", key, value), &record)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn diff_files(left: &str, right: &str, key: Option<&str>) -> (DiffCounts, String) {
+        let tmpdir = tempdir::TempDir::new("feet-diff").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("left.csv"), left).expect("write left");
+        std::fs::write(tmpdir.path().join("right.csv"), right).expect("write right");
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
+            ..Config::default()
+        };
+        let store = CsvStore::try_new(config).expect("CsvStore::try_new");
+
+        let mut out = Vec::new();
+        let counts = diff_tables(&store, "left", "right", key, &mut out).expect("diff");
+        (counts, String::from_utf8(out).expect("utf-8 output"))
+    }
+
+    #[test]
+    fn test_diff_positional() {
+        let left = "id,name,price\n1,apple,0.5\n2,pear,1.5\n";
+        let right = "id,name,price\n1,apple,0.5\n2,pear,2\n3,plum,0.25\n";
+
+        let (counts, out) = diff_files(left, right, None);
+        assert_eq!(
+            counts,
+            DiffCounts {
+                added: 1,
+                removed: 0,
+                changed: 1
+            }
+        );
+        assert_eq!(out, "~row 2: price: 1.5 -> 2\n+row 3: 3,plum,0.25\n");
+    }
+
+    #[test]
+    fn test_diff_keyed() {
+        let left = "id,name,price\n1,apple,0.5\n2,pear,1.5\n";
+        let right = "id,price,stock\n3,0.25,9\n2,2,4\n1,0.5,7\n";
+
+        let (counts, out) = diff_files(left, right, Some("id"));
+        assert_eq!(
+            counts,
+            DiffCounts {
+                added: 1,
+                removed: 0,
+                changed: 1
+            }
+        );
+        assert_eq!(
+            out,
+            "-column name\n+column stock\n~id=2: price: 1.5 -> 2\n+id=3: 3,0.25,9\n"
+        );
+    }
+}
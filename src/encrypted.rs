@@ -0,0 +1,77 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use age::IdentityFile;
+use anyhow::{bail, Context};
+
+use crate::glue::RecordIter;
+
+/// Suffix of a table file encrypted with age, e.g. `secrets.csv.age`
+pub const SUFFIX: &str = ".csv.age";
+
+/// Environment variable holding an age identity (`AGE-SECRET-KEY-1...`)
+/// to decrypt tables with, which takes precedence over
+/// `Config::age_identity_file`
+pub const KEY_ENV: &str = "FEET_AGE_KEY";
+
+/// The path a table would have without the suffix,
+/// if this is an encrypted table's file
+pub fn table_stem(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name.strip_suffix(SUFFIX).filter(|stem| !stem.is_empty())?;
+    Some(path.with_file_name(stem))
+}
+
+/// The identities to decrypt tables with, from `KEY_ENV` or else
+/// the identity file
+fn identities(identity_file: Option<&Path>) -> anyhow::Result<Vec<Box<dyn age::Identity>>> {
+    let file = match (std::env::var(KEY_ENV), identity_file) {
+        (Ok(key), _) => IdentityFile::from_buffer(key.as_bytes())
+            .with_context(|| format!("reading identity from ${}", KEY_ENV))?,
+        (Err(_), Some(path)) => IdentityFile::from_buffer(
+            std::fs::read(path)
+                .with_context(|| format!("reading identity file {:?}", path))?
+                .as_slice(),
+        )?,
+        (Err(_), None) => bail!(
+            "set ${} or age_identity_file to read encrypted tables",
+            KEY_ENV
+        ),
+    };
+
+    Ok(file.into_identities()?)
+}
+
+/// Decrypt a table's file in memory, returning its column names and
+/// an iterator over its records. The plaintext is never written to disk.
+pub fn open(
+    path: &Path,
+    identity_file: Option<&Path>,
+    has_headers: bool,
+    delimiter: u8,
+) -> anyhow::Result<(Vec<String>, RecordIter)> {
+    let ciphertext = std::fs::read(path).with_context(|| format!("reading {:?}", path))?;
+    let identities = identities(identity_file)?;
+    let decryptor = age::Decryptor::new_buffered(ciphertext.as_slice())?;
+    let mut plaintext = Vec::new();
+    std::io::copy(
+        &mut decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?,
+        &mut plaintext,
+    )
+    .with_context(|| format!("decrypting {:?}", path))?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_headers)
+        .delimiter(delimiter)
+        .from_reader(Cursor::new(plaintext));
+    // Without headers, this is the first record, which is still read as data
+    let first = reader.headers()?;
+    let headers = if has_headers {
+        first.iter().map(ToString::to_string).collect()
+    } else {
+        (1..=first.len()).map(|i| format!("col{}", i)).collect()
+    };
+    let records = reader.into_records().map(|res| res.map_err(Into::into));
+
+    Ok((headers, Box::new(records)))
+}
@@ -1,4 +1,5 @@
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 
 use anyhow::bail;
 use gluesql::core::result::Error as GlueError;
@@ -27,14 +28,6 @@ pub enum GlueErrorNoStorage {
     #[error(transparent)]
     Translate(#[from] TranslateError),
 
-    #[cfg(feature = "alter-table")]
-    #[error(transparent)]
-    AlterTable(#[from] AlterTableError),
-
-    #[cfg(feature = "index")]
-    #[error(transparent)]
-    Index(#[from] IndexError),
-
     #[error(transparent)]
     Execute(#[from] ExecuteError),
     #[error(transparent)]
@@ -138,3 +131,103 @@ impl<T> Sendify<T> for Result<T, GlueError> {
         }
     }
 }
+
+/// A table that doesn't exist in the data directory
+#[derive(ThisError, Debug)]
+#[error("no such table {0:?}")]
+pub struct TableNotFound(pub String);
+
+/// Broad categories of failure, each with its own process exit code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Invalid SQL, or data that doesn't fit the schema
+    Sql,
+    /// The command line couldn't be parsed
+    Usage,
+    /// Reading or writing files failed
+    Io,
+    /// The configuration couldn't be loaded or used
+    Config,
+    TableNotFound,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Sql | Self::Usage => 1,
+            Self::Io => 2,
+            Self::Config => 3,
+            Self::TableNotFound => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sql => "sql",
+            Self::Usage => "usage",
+            Self::Io => "io",
+            Self::Config => "config",
+            Self::TableNotFound => "table_not_found",
+        }
+    }
+
+    /// Categorize an error by its causes.
+    /// Errors can be explicitly categorized by adding
+    /// an `ErrorKind` as context, e.g. `.context(ErrorKind::Config)`.
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(&kind) = err.downcast_ref::<ErrorKind>() {
+            return kind;
+        }
+
+        for cause in err.chain() {
+            if cause.is::<clap::Error>() {
+                return Self::Usage;
+            }
+            if cause.is::<TableNotFound>() {
+                return Self::TableNotFound;
+            }
+            if let Some(glue_err) = cause.downcast_ref::<GlueErrorNoStorage>() {
+                if matches!(
+                    glue_err,
+                    GlueErrorNoStorage::Fetch(FetchError::TableNotFound(_))
+                        | GlueErrorNoStorage::Execute(ExecuteError::TableNotFound(_))
+                        | GlueErrorNoStorage::Alter(AlterError::TableNotFound(_))
+                ) {
+                    return Self::TableNotFound;
+                }
+            }
+            if cause.is::<std::io::Error>() {
+                return Self::Io;
+            }
+            if let Some(csv_err) = cause.downcast_ref::<csv::Error>() {
+                if csv_err.is_io_error() {
+                    return Self::Io;
+                }
+            }
+        }
+
+        Self::Sql
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sql => write!(f, "SQL error"),
+            Self::Usage => write!(f, "usage error"),
+            Self::Io => write!(f, "I/O error"),
+            Self::Config => write!(f, "configuration error"),
+            Self::TableNotFound => write!(f, "table not found"),
+        }
+    }
+}
+
+/// Describe an error as a JSON object with its exit code, kind and message
+pub fn error_json(err: &anyhow::Error) -> serde_json::Value {
+    let kind = ErrorKind::of(err);
+    serde_json::json!({
+        "code": kind.exit_code(),
+        "kind": kind.name(),
+        "message": format!("{:#}", err),
+    })
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use csv::StringRecord;
+
+use crate::config::FixedWidthSpec;
+
+/// File extensions recognized for fixed-width tables
+pub const EXTENSIONS: [&str; 2] = ["fw", "txt"];
+
+/// Find the fixed-width file for a table path (without extension)
+pub fn find_file(path: &Path) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|ext| {
+            let mut candidate = path.to_owned().into_os_string();
+            candidate.push(".");
+            candidate.push(ext);
+            PathBuf::from(candidate)
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// Read each line of a fixed-width file into a record
+/// of trimmed fields, sliced according to `spec`.
+pub fn read_records(
+    path: &Path,
+    spec: &FixedWidthSpec,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<StringRecord>>> {
+    let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let spec = spec.clone();
+
+    let iter = BufReader::new(file)
+        .lines()
+        .skip(spec.skip_lines)
+        .map(move |line_res| Ok(slice_line(&line_res?, &spec)));
+
+    Ok(iter)
+}
+
+/// Split a line into trimmed fields at the byte ranges in `spec`.
+/// Ranges past the end of a short line yield empty fields.
+fn slice_line(line: &str, spec: &FixedWidthSpec) -> StringRecord {
+    spec.columns
+        .iter()
+        .map(|col| {
+            let end = col.end.min(line.len());
+            let start = col.start.min(end);
+            line.get(start..end).unwrap_or_default().trim()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FixedWidthColumn;
+
+    #[test]
+    fn test_slice_line() {
+        let spec = FixedWidthSpec {
+            columns: vec![
+                FixedWidthColumn {
+                    name: "id".to_string(),
+                    start: 0,
+                    end: 4,
+                },
+                FixedWidthColumn {
+                    name: "name".to_string(),
+                    start: 4,
+                    end: 12,
+                },
+            ],
+            skip_lines: 0,
+        };
+
+        let record = slice_line("  42bob     ", &spec);
+        assert_eq!(record, StringRecord::from(vec!["42", "bob"]));
+
+        let record = slice_line("  7", &spec);
+        assert_eq!(record, StringRecord::from(vec!["7", ""]));
+    }
+}
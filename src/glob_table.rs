@@ -0,0 +1,49 @@
+use anyhow::bail;
+use gluesql::core::sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, Ident, ObjectName, Statement, TableFactor, Value,
+};
+
+use crate::table_factors;
+
+/// Prefix of the names given to `glob(...)` tables, followed by the pattern
+const PREFIX: &str = "glob:";
+
+/// The pattern of a table standing for the union of the tables
+/// matching a glob, if it is one
+pub fn pattern(table_name: &str) -> Option<&str> {
+    table_name.strip_prefix(PREFIX)
+}
+
+/// Replace each `glob('pattern')` in a statement's FROM clauses with a
+/// table named for the pattern, which the store reads as the union of
+/// the tables whose names match it. GlueSQL has no table-valued
+/// functions, and drops their arguments, so this is done before it
+/// translates the statement.
+pub fn rewrite(statement: &mut Statement) -> anyhow::Result<()> {
+    table_factors::visit_statement(statement, &mut rewrite_factor)
+}
+
+fn rewrite_factor(factor: &mut TableFactor) -> anyhow::Result<()> {
+    if let TableFactor::Table { name, args, .. } = factor {
+        if is_glob(name) && args.is_some() {
+            let pattern = match args.as_deref() {
+                Some(
+                    [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                        Value::SingleQuotedString(pattern),
+                    )))],
+                ) => pattern,
+                _ => bail!("glob() takes a single quoted pattern, e.g. glob('events/*')"),
+            };
+            *name = ObjectName(vec![Ident::with_quote(
+                '"',
+                format!("{}{}", PREFIX, pattern),
+            )]);
+            *args = None;
+        }
+    }
+    Ok(())
+}
+
+fn is_glob(name: &ObjectName) -> bool {
+    matches!(name.0.as_slice(), [ident] if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("glob"))
+}
@@ -1,33 +1,132 @@
+// GlueResult's error type is large, but it's dictated by the Store traits.
+#![allow(clippy::result_large_err)]
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{DirEntry, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Stderr, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
 
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
-use csv::{StringRecord, StringRecordsIter};
-use globset::Glob;
-use gluesql::core::ast::ColumnDef;
-use gluesql::core::data::{Key, Row, Schema};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use csv::StringRecord;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use gluesql::core::ast::{ColumnDef, ColumnOption, ColumnOptionDef, Statement};
+use gluesql::core::data::{Key, Literal, Row, Schema};
+use gluesql::core::parse_sql::parse_data_type;
 use gluesql::core::result::{Error as GlueError, MutResult, Result as GlueResult};
 use gluesql::core::store::{GStore, GStoreMut, RowIter, Store, StoreMut};
+use gluesql::core::translate::translate_data_type;
 use gluesql::prelude::{DataType, Value};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::aggregate_cache::{self, AggregateCache, CacheKey};
+use crate::computed::{append_computed, Computed};
+use crate::concurrency;
+use crate::config::{
+    Config, DeleteMode, FilenamePolicy, FixedWidthSpec, FloatToInt, TypeDetectorSpec,
+};
+#[cfg(feature = "encryption")]
+use crate::encrypted;
+use crate::error::TableNotFound;
+use crate::fixed_width;
 use crate::format_value;
-use crate::line_injector::{Injection, LineInjector};
-use crate::names::{TableIdentifier, TableName, TablePath};
+use crate::glob_table;
+use crate::html;
+use crate::lenient;
+use crate::lint::{self, LintReport};
+use crate::manifest::{self, Manifest};
+use crate::modeline::Modeline;
+use crate::names::{column_identifiers, nfc, TableIdentifier, TableName, TablePath};
+use crate::output::{delimiter_byte, value_json};
+use crate::overlay::{Overlay, OverlayTable};
+#[cfg(feature = "parquet")]
+use crate::parquet_file;
+use crate::partition::{self, Bounds};
+use crate::progress::{self, Progress};
+use crate::quarantine::Quarantine;
+use crate::regex_delimited;
+use crate::schema_file::{self, SchemaFile};
+use crate::views::Views;
+use crate::wal::{Transaction, Wal, WAL_DIR};
 
 // use crate::config::Config;
 
 pub struct CsvStore {
     pub data_dir: PathBuf,
-    ignores: Vec<String>,
+    /// File patterns to leave out of listings, compiled once
+    ignores: GlobSet,
+    writable_paths: GlobSet,
+    normalize_unicode: bool,
+    output_formats: HashMap<String, HashMap<String, String>>,
+    fixed_width: HashMap<String, FixedWidthSpec>,
+    regex_delimiter: Option<Regex>,
+    write_batch_size: Option<usize>,
+    cache_dir: Option<PathBuf>,
+    partitions: HashMap<String, String>,
+    lenient_parsing: bool,
+    lenient_warned: lenient::Warned,
+    display_order: HashMap<String, Vec<String>>,
+    column_aliases: HashMap<String, HashMap<String, String>>,
+    has_headers: bool,
+    modelines: bool,
+    sanitize_column_names: bool,
+    drop_trailing_empty_column: bool,
+    /// Progress bar for reading large files, if enabled
+    /// (and stderr is a terminal)
+    progress: Option<Progress<Stderr>>,
+    filename_policy: FilenamePolicy,
+    max_concurrency: usize,
+    inference_sample_rows: usize,
+    durable_writes: bool,
+    /// Where rows which can't be read are diverted while scanning, if set
+    quarantine: Option<Arc<Quarantine>>,
+    /// To decrypt `.csv.age` tables with (see `encrypted::KEY_ENV`)
+    #[cfg(feature = "encryption")]
+    age_identity_file: Option<PathBuf>,
+    float_to_int: FloatToInt,
+    /// Lines starting with this byte are skipped when reading CSV files
+    comment: Option<u8>,
+    /// Between the fields of CSV files, unless a modeline says otherwise
+    delimiter: u8,
+    /// Of the files which are tables, without the dot, most preferred first
+    extensions: Vec<String>,
+    /// Whether columns with a value in every sampled row are NOT NULL
+    infer_not_null: bool,
+    delete_mode: DeleteMode,
+    computed: HashMap<String, Vec<Computed>>,
+    views: Views,
+    /// Non-ISO date and timestamp formats, tried in order
+    date_formats: Vec<String>,
+    /// Consulted in order before the built-in type inference
+    type_detectors: Vec<TypeDetector>,
+    /// Column types of CSV tables, so they're inferred once per
+    /// version of each file rather than on every read
+    schema_cache: Mutex<HashMap<PathBuf, CachedColumns>>,
+    /// Bounds on column values implied by the current query
+    partition_bounds: HashMap<String, Bounds>,
+    /// Maximum number of rows the current query needs from a scan
+    scan_limit: Option<usize>,
+    /// Lowercased identifiers the current read-only query uses, if any
+    projection_hint: Option<HashSet<String>>,
+    /// Whether the current read-only query can select `ROWID_COLUMN`
+    rowid_column: bool,
+    /// Pending edits, if running in overlay mode
+    overlay: Option<Overlay>,
+    wal: Option<Wal>,
+    aggregate_cache: Option<AggregateCache>,
 }
 
 #[derive(Debug)]
 pub enum TableData {
+    #[allow(dead_code)]
     Table(Schema),
     Dir,
 }
@@ -38,548 +137,6117 @@ pub struct TableNode {
     pub data: TableData,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Table name component standing for the most recently modified table
+/// in its directory, e.g. `SELECT * FROM "logs/@latest"`
+pub const LATEST: &str = "@latest";
+
+/// Metadata about a table, as listed by `CsvStore::inventory`
+#[derive(Debug, Serialize)]
+pub struct TableInfo {
+    pub identifier: String,
+    /// `csv`, `fixed_width` or `manifest`
+    pub format: &'static str,
+    pub rows: usize,
+    pub columns: Vec<ColumnInfo>,
+    /// Size of the table's files in bytes
+    pub bytes: u64,
+    /// Latest modification time of the table's files, in RFC 3339 format
+    pub mtime: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+/// File stem and path of one partition of a partitioned table
+type Partition = (String, TablePath);
+
+/// Column names paired with their types
+type ColumnPairs = Vec<(String, ColumnType)>;
+
+pub type RecordIter = Box<dyn Iterator<Item = anyhow::Result<StringRecord>>>;
+
+/// Recognizes values of a domain-specific type, e.g. IP addresses,
+/// returning the SQL type to read them as.
+pub type TypeDetector = Box<dyn Fn(&str) -> Option<DataType> + Send + Sync>;
+
+/// A detector of the values which wholly match a configured pattern
+pub fn type_detector(spec: &TypeDetectorSpec) -> anyhow::Result<TypeDetector> {
+    let context = || format!("type detector {:?}", spec.pattern);
+    let regex = Regex::new(&format!("^(?:{})$", spec.pattern)).with_context(context)?;
+    let data_type = parse_data_type(&spec.data_type)
+        .and_then(|sql_type| translate_data_type(&sql_type))
+        .map_err(|err| anyhow!("{}", err))
+        .with_context(context)?;
+
+    Ok(Box::new(move |value| {
+        regex.is_match(value).then(|| data_type.clone())
+    }))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 enum ColumnType {
     Int,
+    /// Integers too large for `Int`
+    BigInt,
     Float,
+    /// `true` or `false`, in any case
+    Bool,
+    /// Calendar date, formatted as `DATE_FORMAT`
+    Date,
+    /// Time of day, formatted as `TIME_FORMAT`
+    Time,
+    /// Calendar date in one of `Config::date_formats`
+    FormattedDate(String),
+    /// Date and time in one of `Config::date_formats`
+    FormattedTimestamp(String),
     String,
+    /// A type recognized by a `TypeDetector`, read by casting text
+    Custom(DataType),
+}
+
+impl ColumnType {
+    fn from_data_type(data_type: DataType) -> Self {
+        match data_type {
+            DataType::Int32 => ColumnType::Int,
+            DataType::Int => ColumnType::BigInt,
+            DataType::Float => ColumnType::Float,
+            DataType::Boolean => ColumnType::Bool,
+            DataType::Date => ColumnType::Date,
+            DataType::Time => ColumnType::Time,
+            DataType::Text => ColumnType::String,
+            other => ColumnType::Custom(other),
+        }
+    }
+
+    /// The strictest type which can represent values of both types
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (first, second) if first == second => first,
+            (ColumnType::Int | ColumnType::BigInt, ColumnType::Int | ColumnType::BigInt) => {
+                ColumnType::BigInt
+            }
+            (
+                ColumnType::Int | ColumnType::BigInt | ColumnType::Float,
+                ColumnType::Int | ColumnType::BigInt | ColumnType::Float,
+            ) => ColumnType::Float,
+            _ => ColumnType::String,
+        }
+    }
 }
 
+/// Name of the column exposing each row's key, while enabled by
+/// `CsvStore::set_rowid_column`
+pub const ROWID_COLUMN: &str = "__rowid";
+
+/// How dates are read and written, e.g. `2023-01-15`
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// How timestamps are displayed, e.g. `2023-01-15 09:30:00`
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// How time-of-day values are read and written, e.g. `09:30:00` or `09:30:00.25`
+pub const TIME_FORMAT: &str = "%H:%M:%S%.f";
+
 impl From<ColumnType> for DataType {
     fn from(col_type: ColumnType) -> Self {
         match col_type {
             ColumnType::Int => DataType::Int32,
+            ColumnType::BigInt => DataType::Int,
             ColumnType::Float => DataType::Float,
+            ColumnType::Bool => DataType::Boolean,
+            ColumnType::Date => DataType::Date,
+            ColumnType::Time => DataType::Time,
+            ColumnType::FormattedDate(_) => DataType::Date,
+            ColumnType::FormattedTimestamp(_) => DataType::Timestamp,
             ColumnType::String => DataType::Text,
+            ColumnType::Custom(data_type) => data_type,
         }
     }
 }
 
-fn get_column_types_for_table(path: TablePath) -> anyhow::Result<Vec<(String, ColumnType)>> {
-    let mut reader = csv::Reader::from_path(path.as_csv())?;
-
-    let headers: Vec<_> = reader.headers()?.iter().map(ToString::to_string).collect();
-    let col_types =
-        determine_column_types(reader.records(), headers.len()).context("get col_types")?;
+/// Column names and types of a fixed-width table
+fn get_column_types_for_fixed_width(
+    path: &Path,
+    spec: &FixedWidthSpec,
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+    sample_rows: usize,
+) -> anyhow::Result<Vec<(String, ColumnType)>> {
+    let records = fixed_width::read_records(path, spec)?;
+    let col_types = determine_column_types(
+        records,
+        spec.columns.len(),
+        detectors,
+        date_formats,
+        sample_rows,
+    )
+    .context("get col_types")?;
 
-    let pairs = headers.into_iter().zip(col_types).collect();
+    let names = spec.columns.iter().map(|col| col.name.clone());
+    let pairs = names.zip(col_types).collect();
     Ok(pairs)
 }
 
-/// Read the whole file to try to determine a suitable schema
-fn read_schema(path: TablePath) -> anyhow::Result<Schema> {
-    let col_pairs =
-        get_column_types_for_table(path.clone()).context("getting column types for schema")?;
-
-    let table_id: TableIdentifier = path.try_into().context("table id -> path")?;
-
-    let mut schema = Schema {
-        table_name: table_id.to_string(),
-        column_defs: Vec::new(),
-        indexes: Vec::new(),
-    };
-
-    for (col_name, col_type) in col_pairs {
-        let col_def = ColumnDef {
-            name: col_name,
-            data_type: col_type.into(),
-            options: Vec::new(),
-        };
-
-        schema.column_defs.push(col_def);
-    }
-
-    Ok(schema)
-}
-
 /// Given two equal-length lists of column types,
 /// return a same-length list of the more general type in each position.
-fn merge_column_types(first: &[ColumnType], second: &[ColumnType]) -> Vec<ColumnType> {
-    first.iter().zip(second).map(|(&f, &s)| f.max(s)).collect()
+/// `None` means nothing is known about a column yet.
+fn merge_column_types(
+    first: &[Option<ColumnType>],
+    second: &[Option<ColumnType>],
+) -> Vec<Option<ColumnType>> {
+    first
+        .iter()
+        .zip(second)
+        .map(|(f, s)| match (f, s) {
+            (Some(f), Some(s)) => Some(f.clone().merge(s.clone())),
+            (f, s) => f.clone().or_else(|| s.clone()),
+        })
+        .collect()
 }
 
 /// Determine the minimum column type needed for each column
-/// by brute-force reading every value
-fn determine_column_types(
-    records: StringRecordsIter<std::fs::File>,
+/// by brute-force reading every value of the first `sample_rows`
+/// records, or of every record if it's 0.
+/// Columns without any (non-empty) values are `Int`.
+fn determine_column_types<I>(
+    records: I,
     ncols: usize,
-) -> anyhow::Result<Vec<ColumnType>> {
-    let init: Vec<ColumnType> = std::iter::repeat(ColumnType::Int).take(ncols).collect();
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+    sample_rows: usize,
+) -> anyhow::Result<Vec<ColumnType>>
+where
+    I: Iterator<Item = anyhow::Result<StringRecord>>,
+{
+    let init: Vec<Option<ColumnType>> = vec![None; ncols];
+    let sample_rows = match sample_rows {
+        0 => usize::MAX,
+        n => n,
+    };
 
-    records
+    let merged = records
+        .take(sample_rows)
+        .map(|res| res.map(|record| column_types_from_record(record, detectors, date_formats)))
+        .try_fold(init, reduce_column_types)?;
+
+    Ok(merged
         .into_iter()
-        .map(|res| {
-            res.map(column_types_from_record)
-                .map_err(Into::<anyhow::Error>::into)
-        })
-        .try_fold(init, reduce_column_types)
+        .map(|typ| typ.unwrap_or(ColumnType::Int))
+        .collect())
+}
+
+/// Column types, as by `determine_column_types`, and whether each column
+/// can be NULL: it can if it's empty in any sampled record, or if there
+/// are no records to sample. Also the number of records, if the sample
+/// turned out to be all of them.
+fn determine_columns<I>(
+    records: I,
+    ncols: usize,
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+    sample_rows: usize,
+) -> anyhow::Result<(Vec<ColumnType>, Vec<bool>, Option<usize>)>
+where
+    I: Iterator<Item = anyhow::Result<StringRecord>>,
+{
+    let mut nrecords = 0;
+    let mut nullable = vec![false; ncols];
+    let records = records.inspect(|res| {
+        if let Ok(record) = res {
+            nrecords += 1;
+            for (i, null) in nullable.iter_mut().enumerate() {
+                *null |= record.get(i).is_none_or(str::is_empty);
+            }
+        }
+    });
+    let col_types = determine_column_types(records, ncols, detectors, date_formats, sample_rows)?;
+    if nrecords == 0 {
+        nullable.fill(true);
+    }
+    let rows = (sample_rows == 0 || nrecords < sample_rows).then_some(nrecords);
+
+    Ok((col_types, nullable, rows))
 }
 
 fn reduce_column_types(
-    new_types: Vec<ColumnType>,
-    agg: anyhow::Result<Vec<ColumnType>>,
-) -> anyhow::Result<Vec<ColumnType>> {
-    agg.map(|ctypes| merge_column_types(&ctypes, &new_types))
+    agg: Vec<Option<ColumnType>>,
+    new_types: anyhow::Result<Vec<Option<ColumnType>>>,
+) -> anyhow::Result<Vec<Option<ColumnType>>> {
+    new_types.map(|new_types| merge_column_types(&agg, &new_types))
 }
 
-fn column_types_from_record(record: StringRecord) -> Vec<ColumnType> {
-    record.into_iter().map(min_column_type).collect()
+/// The type of each value in a record. Empty cells are NULLs,
+/// which say nothing about their column's type.
+fn column_types_from_record(
+    record: StringRecord,
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+) -> Vec<Option<ColumnType>> {
+    record
+        .into_iter()
+        .map(|value| (!value.is_empty()).then(|| min_column_type(value, detectors, date_formats)))
+        .collect()
 }
 
-/// Determine the strictest column type that can represent a value
-fn min_column_type(value: &str) -> ColumnType {
-    if value.parse::<i64>().is_ok() {
+/// Determine the strictest column type that can represent a value,
+/// according to the first detector to recognize it, if any.
+/// Dates in other formats than ISO are read with the first of
+/// `date_formats` to fit.
+fn min_column_type(value: &str, detectors: &[TypeDetector], date_formats: &[String]) -> ColumnType {
+    if let Some(data_type) = detectors.iter().find_map(|detect| detect(value)) {
+        ColumnType::from_data_type(data_type)
+    } else if value.parse::<i32>().is_ok() {
         ColumnType::Int
+    } else if value.parse::<i64>().is_ok() {
+        ColumnType::BigInt
     } else if value.parse::<f64>().is_ok() {
         ColumnType::Float
+    } else if parse_bool(value).is_some() {
+        ColumnType::Bool
+    } else if NaiveDate::parse_from_str(value, DATE_FORMAT).is_ok() {
+        ColumnType::Date
+    } else if let Some(typ) = date_formats
+        .iter()
+        .find_map(|fmt| formatted_date_type(value, fmt))
+    {
+        typ
+    } else if NaiveTime::parse_from_str(value, TIME_FORMAT).is_ok() {
+        ColumnType::Time
     } else {
         ColumnType::String
     }
 }
 
-impl TableNode {
-    fn try_from_dir_entry(entry: DirEntry, data_dir: &Path) -> anyhow::Result<Self> {
-        let ftype = entry.metadata()?.file_type();
-
-        let path = TablePath::try_new(entry.path(), data_dir.to_owned())?;
-        let name: TableName = path.clone().try_into()?;
-        // let name = TableName::try_from_path(&entry.path(), data_dir)?;
-
-        if ftype.is_dir() {
-            let data = TableData::Dir;
-            return Ok(TableNode { name, data });
-        } else if ftype.is_file() && entry.path().extension() == Some(OsStr::new("csv")) {
-            let schema = read_schema(path)?;
-            let data = TableData::Table(schema);
-            return Ok(TableNode { name, data });
-        } else {
-            bail!("{:?} is not a file or directory?", entry.path());
-        }
+/// The type of a date or timestamp in this chrono format, if it is one
+fn formatted_date_type(value: &str, fmt: &str) -> Option<ColumnType> {
+    // Dates parse even if the format has a time, so try timestamps first
+    if NaiveDateTime::parse_from_str(value, fmt).is_ok() {
+        Some(ColumnType::FormattedTimestamp(fmt.to_string()))
+    } else if NaiveDate::parse_from_str(value, fmt).is_ok() {
+        Some(ColumnType::FormattedDate(fmt.to_string()))
+    } else {
+        None
     }
 }
 
-/// Expand and canonicalize path
-fn parse_data_dir(orig: &str) -> anyhow::Result<PathBuf> {
-    let s = shellexpand::tilde(orig);
-    let pb = PathBuf::from_str(&s)?;
-    let can = pb.canonicalize()?;
-    Ok(can)
-}
-
-impl CsvStore {
-    pub fn try_new(config: Config) -> anyhow::Result<Self> {
-        let data_dir = parse_data_dir(&config.data_dir)?;
-        let new = Self {
-            data_dir,
-            ignores: config.ignores,
-        };
-
-        Ok(new)
-    }
-
-    pub fn should_ignore(&self, filename: &str) -> anyhow::Result<bool> {
-        self.ignores
-            .iter()
-            .map(|ig| Ok(Glob::new(ig.as_str())?.compile_matcher().is_match(filename)))
-            .try_fold(false, |acc, next| next.map(|x| acc || x))
+/// `true` or `false`, ignoring case
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
     }
+}
 
-    pub fn list_tables(&self, dir: TableName) -> anyhow::Result<Vec<TableNode>> {
-        let dir_path: TablePath = dir.try_into()?;
-        let mut tables = Vec::new();
+/// The outcome of type inference over a list of values
+#[derive(Debug)]
+pub struct InferredType {
+    pub column_type: String,
+    pub data_type: DataType,
+    /// Each value which widened the type, with the type it widened to
+    pub widened_by: Vec<(String, String)>,
+}
 
-        for entry_res in std::fs::read_dir(dir_path.as_dir())? {
-            let entry = entry_res?;
+/// Run type inference over values as if they were a column, with the
+/// given detectors and date formats, recording which values forced
+/// the type to widen
+fn infer_type(
+    values: &[String],
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+) -> InferredType {
+    let mut typ: Option<ColumnType> = None;
+    let mut widened_by = Vec::new();
 
-            if !self.should_ignore(entry.file_name().to_str().expect("funny filename!"))? {
-                let node = TableNode::try_from_dir_entry(entry, &self.data_dir)?;
-                tables.push(node);
-            }
+    // Empty values are NULLs, which fit any type
+    for value in values.iter().filter(|value| !value.is_empty()) {
+        let value_type = min_column_type(value, detectors, date_formats);
+        let merged = match &typ {
+            Some(typ) => typ.clone().merge(value_type),
+            None => value_type,
+        };
+        // An empty column is an Int column
+        if merged != typ.unwrap_or(ColumnType::Int) {
+            widened_by.push((value.clone(), format!("{:?}", merged)));
         }
+        typ = Some(merged);
+    }
+    let typ = typ.unwrap_or(ColumnType::Int);
 
-        Ok(tables)
+    InferredType {
+        column_type: format!("{:?}", typ),
+        data_type: typ.into(),
+        widened_by,
     }
 }
 
-fn value_from_str(val: &str, typ: ColumnType) -> anyhow::Result<Value> {
-    let res = match typ {
-        ColumnType::Int => Value::I32(val.parse()?),
-        ColumnType::Float => Value::F64(val.parse()?),
-        ColumnType::String => Value::Str(val.to_owned()),
-    };
-
-    Ok(res)
+/// Whether a source file for import has a header row
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportHeaders {
+    Yes,
+    No,
+    /// Guess from the types of the first rows
+    Auto,
 }
 
-fn get_i32_key(key: &Key) -> anyhow::Result<i32> {
-    match *key {
-        Key::I32(x) => Ok(x),
-        _ => bail!("non-i32 key {:?}", key),
-    }
+/// File format for exported tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    /// An array of objects keyed by column name
+    Json,
+    /// A `<table>`, with NULLs as empty cells of class `null`
+    Html,
+    /// Columns are typed by their values, with NULLs as missing values
+    #[cfg(feature = "parquet")]
+    Parquet,
 }
 
-fn get_row_num(key: &Key) -> anyhow::Result<usize> {
-    match get_i32_key(key) {
-        Ok(i) => match usize::try_from(i) {
-            // Add one to account for headers
-            Ok(row_num) => Ok(row_num + 1),
-            Err(_err) => bail!("Invalid row number {}", i),
-        },
-        Err(err) => Err(err),
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => ".csv",
+            ExportFormat::Json => ".json",
+            ExportFormat::Html => ".html",
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => ".parquet",
+        }
     }
-}
 
-#[async_trait(?Send)]
-impl Store for CsvStore {
-    async fn fetch_schema(&self, table_name: &str) -> GlueResult<Option<Schema>> {
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id
-            .try_into()
-            .context("convert table id to path")
-            .to_glue_err()?;
-        if path.clone().as_csv().exists() {
-            let schema = read_schema(path).context("reading schema").to_glue_err()?;
-
-            Ok(Some(schema))
-        } else {
-            Ok(None)
+    /// The format a file's extension implies, if any
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            "html" | "htm" => Some(ExportFormat::Html),
+            #[cfg(feature = "parquet")]
+            "parquet" => Some(ExportFormat::Parquet),
+            _ => None,
         }
     }
+}
 
-    async fn fetch_data(&self, table_name: &str, key: &Key) -> GlueResult<Option<Row>> {
-        println!("fetch_data");
-        dbg!(table_name);
-        dbg!(key);
-
-        // Number of rows to skip
-        let nskip = get_row_num(key).to_glue_err()?;
+/// Number of rows read to guess whether a source has a header
+const HEADER_SAMPLE_ROWS: usize = 100;
 
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id
-            .try_into()
-            .context("table id -> path")
-            .to_glue_err()?;
-
-        let col_pairs = get_column_types_for_table(path.clone())
-            .context("getting column types")
-            .to_glue_err()?;
-        let col_types: Vec<_> = col_pairs.into_iter().map(|(_name, typ)| typ).collect();
+/// The outcome of importing a table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Imported {
+    pub rows: usize,
+    /// Whether the source's first row was read as a header
+    pub header: bool,
+}
 
-        let reader = csv::Reader::from_path(path.as_csv())
-            .context("opening csv reader")
-            .to_glue_err()?;
+/// Guess whether the first of some records is a header: it is if it's
+/// all text while some column of the others isn't, and it isn't if any
+/// of its values aren't text. `None` means there's no telling.
+fn looks_like_header(
+    sample: &[StringRecord],
+    detectors: &[TypeDetector],
+    date_formats: &[String],
+) -> Option<bool> {
+    let (first, rest) = sample.split_first()?;
+    if first
+        .iter()
+        .any(|value| min_column_type(value, detectors, date_formats) != ColumnType::String)
+    {
+        return Some(false);
+    }
+    if rest.is_empty() {
+        return None;
+    }
 
-        // Skip first n records
-        let mut records = reader.into_records().skip(nskip);
+    // Columns which are empty after the first row don't count either way
+    let rest_types = rest.iter().fold(vec![None; first.len()], |agg, record| {
+        merge_column_types(
+            &agg,
+            &column_types_from_record(record.clone(), detectors, date_formats),
+        )
+    });
+    rest_types
+        .iter()
+        .any(|typ| typ.as_ref().is_some_and(|typ| *typ != ColumnType::String))
+        .then_some(true)
+}
 
-        records
-            .next()
-            .map(|res| {
-                let record = res.context("reading csv record").to_glue_err()?;
-                let row = read_csv_record(record, col_types.clone())?;
-                Ok(row)
-            })
-            .transpose()
+impl TableNode {
+    pub fn is_table(&self) -> bool {
+        matches!(self.data, TableData::Table(_))
     }
 
-    async fn scan_data(&self, table_name: &str) -> GlueResult<RowIter> {
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id
-            .try_into()
-            .context("table id -> path")
-            .to_glue_err()?;
-
-        let col_pairs = get_column_types_for_table(path.clone())
-            .context("getting column types")
-            .to_glue_err()?;
-        let col_types: Vec<_> = col_pairs.into_iter().map(|(_name, typ)| typ).collect();
+    fn try_from_dir_entry(entry: DirEntry, store: &CsvStore) -> anyhow::Result<Self> {
+        let entry_path = entry.path();
+        // Follow symlinks
+        let ftype = std::fs::metadata(&entry_path)?.file_type();
 
-        let reader = csv::Reader::from_path(path.as_csv())
-            .context("opening csv reader")
-            .to_glue_err()?;
+        #[cfg(feature = "encryption")]
+        if let Some(stem) = encrypted::table_stem(&entry_path).filter(|_| ftype.is_file()) {
+            let path = TablePath::try_new(stem, store.data_dir.clone())?;
+            let name = TableName::from_path_lossy(path)?;
+            let table_id: TableIdentifier = name.clone().try_into()?;
+            let schema = store
+                .read_stored_schema(&table_id)?
+                .with_context(|| format!("can't read {:?}", entry_path))?;
+            let data = TableData::Table(schema);
+            return Ok(TableNode { name, data });
+        }
+        let is_fixed_width = entry_path
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| fixed_width::EXTENSIONS.contains(&ext));
 
-        // Loop over rows
-        let records = reader.into_records();
-        let unboxed_iter = records.into_iter().enumerate().map(move |(i, res)| {
-            let key = Key::I32(i.try_into().expect("failed to convert key to i32"));
-            let record = res.context("reading csv record").to_glue_err()?;
-            let row = read_csv_record(record, col_types.clone())?;
-            Ok((key, row))
-        });
+        let is_table_file = entry_path
+            .extension()
+            .is_some_and(|ext| store.is_table_extension(ext));
 
-        let iter: RowIter = Box::new(unboxed_iter);
+        let path = if ftype.is_file() && is_table_file {
+            let stem = TablePath::try_new(entry_path.with_extension(""), store.data_dir.clone())?;
+            store.with_table_extension(stem)
+        } else if ftype.is_file() && is_fixed_width {
+            TablePath::try_new(entry_path.with_extension(""), store.data_dir.clone())?
+        } else {
+            TablePath::try_new(entry_path.clone(), store.data_dir.clone())?
+        };
+        // Names have been checked according to the filename policy already
+        let name = TableName::from_path_lossy(path.clone())?;
 
-        Ok(iter)
+        if ftype.is_dir() && entry_path.join(manifest::FILE_NAME).is_file() {
+            let table_id: TableIdentifier = name.clone().try_into()?;
+            let schema = store
+                .read_stored_schema(&table_id)?
+                .with_context(|| format!("no manifest in {:?}", entry_path))?;
+            let data = TableData::Table(schema);
+            Ok(TableNode { name, data })
+        } else if ftype.is_dir() {
+            let data = TableData::Dir;
+            Ok(TableNode { name, data })
+        } else if ftype.is_file() && is_table_file {
+            let table_id: TableIdentifier = name.clone().try_into()?;
+            let col_pairs = store.column_types_for_table(&path)?;
+            let schema = store.build_schema(table_id.to_string(), col_pairs);
+            let data = TableData::Table(schema);
+            Ok(TableNode { name, data })
+        } else if ftype.is_file() && is_fixed_width {
+            let table_id: TableIdentifier = name.clone().try_into()?;
+            match store.fixed_width_table(&table_id)? {
+                Some((fw_path, spec)) => {
+                    let schema = store.read_fixed_width_schema(&table_id, &fw_path, spec)?;
+                    let data = TableData::Table(schema);
+                    Ok(TableNode { name, data })
+                }
+                None => bail!("{:?} has no fixed-width spec", entry_path),
+            }
+        } else {
+            bail!("{:?} is not a file or directory?", entry_path);
+        }
     }
 }
 
-fn read_csv_record(record: StringRecord, col_types: Vec<ColumnType>) -> GlueResult<Row> {
-    // Loop over records in the row
-    let rec_it = record.into_iter();
-
-    let row_vec: Vec<_> = rec_it
-        .zip(col_types)
-        .map(|(s, typ)| value_from_str(s, typ))
-        .collect::<anyhow::Result<Vec<_>>>()
-        .context("reading csv value")
-        .to_glue_err()?;
-
-    Ok(Row(row_vec))
+/// Expand a leading `~` to the home directory, unless paths are literal
+pub fn expand_path(orig: &str, literal: bool) -> anyhow::Result<PathBuf> {
+    let s = if literal {
+        orig.into()
+    } else {
+        shellexpand::tilde(orig)
+    };
+    Ok(PathBuf::from_str(&s)?)
 }
 
-trait IntoMutResult<T, U> {
-    fn into_mut_result(self, t: T) -> MutResult<T, U>;
+/// Expand and canonicalize path
+fn parse_data_dir(orig: &str, literal: bool) -> anyhow::Result<PathBuf> {
+    let pb = expand_path(orig, literal)?;
+    let can = pb.canonicalize()?;
+    Ok(can)
 }
 
-impl<T, U> IntoMutResult<T, U> for Result<U, GlueError> {
-    fn into_mut_result(self, t: T) -> MutResult<T, U> {
-        match self {
-            Ok(val) => Ok((t, val)),
-            Err(err) => Err((t, err)),
+impl CsvStore {
+    pub fn try_new(config: Config) -> anyhow::Result<Self> {
+        let data_dir = parse_data_dir(&config.data_dir, config.literal_paths)?;
+        let regex_delimiter = config
+            .regex_delimiter
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("parsing regex_delimiter")?;
+        let cache_dir = config
+            .cache_dir
+            .as_deref()
+            .map(|dir| expand_path(dir, config.literal_paths))
+            .transpose()?;
+        let quarantine = config
+            .quarantine
+            .as_deref()
+            .map(|path| expand_path(path, config.literal_paths))
+            .transpose()?;
+        #[cfg(feature = "encryption")]
+        let age_identity_file = config
+            .age_identity_file
+            .as_deref()
+            .map(|path| expand_path(path, config.literal_paths))
+            .transpose()?;
+        let comment = config
+            .comment
+            .map(|c| {
+                u8::try_from(c)
+                    .ok()
+                    .filter(u8::is_ascii)
+                    .with_context(|| format!("comment {:?} isn't an ASCII character", c))
+            })
+            .transpose()?;
+        let delimiter = delimiter_byte(config.delimiter).context("delimiter")?;
+        let extensions: Vec<String> = config
+            .extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_string())
+            .collect();
+        if extensions.is_empty() {
+            bail!("extensions: at least one is needed");
+        }
+        if config.delete_mode == DeleteMode::Tombstone && comment.is_none() {
+            bail!("delete_mode = \"tombstone\" requires a comment character");
+        }
+        let ignores = config
+            .ignores
+            .iter()
+            .try_fold(GlobSetBuilder::new(), |mut builder, pattern| {
+                builder.add(Glob::new(pattern).with_context(|| format!("ignore {:?}", pattern))?);
+                anyhow::Ok(builder)
+            })?
+            .build()
+            .context("compiling ignores")?;
+        let writable_paths = config
+            .writable_paths
+            .iter()
+            .try_fold(GlobSetBuilder::new(), |mut builder, pattern| {
+                let glob =
+                    Glob::new(pattern).with_context(|| format!("writable path {:?}", pattern))?;
+                builder.add(glob);
+                anyhow::Ok(builder)
+            })?
+            .build()
+            .context("compiling writable_paths")?;
+        let computed = config
+            .computed_columns
+            .iter()
+            .map(|(table, specs)| {
+                let columns = specs
+                    .iter()
+                    .map(Computed::parse)
+                    .collect::<anyhow::Result<_>>()?;
+                Ok((table.clone(), columns))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let type_detectors = config
+            .type_detectors
+            .iter()
+            .map(type_detector)
+            .collect::<anyhow::Result<_>>()?;
+        let views = Views::parse(&config.views)?;
+        let wal = config.wal.then(|| Wal::new(&data_dir));
+        let aggregate_cache = config
+            .aggregate_cache
+            .then(|| AggregateCache::new(&data_dir));
+        if let Some(wal) = &wal {
+            let pending = wal.pending()?;
+            if pending > 0 {
+                eprintln!(
+                    "warning: {} incomplete mutations in the write-ahead log; run `feet recover`",
+                    pending
+                );
+            }
         }
+        let new = Self {
+            data_dir,
+            ignores,
+            writable_paths,
+            normalize_unicode: config.normalize_unicode,
+            output_formats: config.output_formats,
+            fixed_width: config.fixed_width,
+            regex_delimiter,
+            write_batch_size: config.write_batch_size,
+            cache_dir,
+            partitions: config.partitions,
+            lenient_parsing: config.lenient_parsing,
+            lenient_warned: lenient::Warned::default(),
+            display_order: config.display_order,
+            column_aliases: config.column_aliases,
+            has_headers: config.has_headers,
+            modelines: config.modelines,
+            drop_trailing_empty_column: config.drop_trailing_empty_column,
+            sanitize_column_names: config.sanitize_column_names,
+            progress: (config.progress && std::io::stderr().is_terminal())
+                .then(|| Progress::new(std::io::stderr())),
+            filename_policy: config.filename_policy,
+            max_concurrency: config
+                .max_concurrency
+                .unwrap_or_else(concurrency::default_max_concurrency),
+            inference_sample_rows: config.inference_sample_rows,
+            date_formats: config.date_formats,
+            durable_writes: config.durable_writes,
+            quarantine: quarantine.map(|path| Arc::new(Quarantine::new(path))),
+            #[cfg(feature = "encryption")]
+            age_identity_file,
+            float_to_int: config.float_to_int,
+            comment,
+            delimiter,
+            extensions,
+            infer_not_null: config.infer_not_null,
+            delete_mode: config.delete_mode,
+            computed,
+            views,
+            type_detectors,
+            schema_cache: Mutex::new(HashMap::new()),
+            partition_bounds: HashMap::new(),
+            scan_limit: None,
+            projection_hint: None,
+            rowid_column: false,
+            overlay: config.overlay.then(Overlay::default),
+            wal,
+            aggregate_cache,
+        };
+
+        Ok(new)
     }
-}
 
-trait ToGlueError {
-    type Target;
+    /// A builder of CSV readers using the configured delimiter
+    fn csv_reader(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder.delimiter(self.delimiter);
+        builder
+    }
 
-    fn to_glue_err(self) -> GlueResult<Self::Target>;
-}
+    /// A builder of CSV writers using the configured delimiter
+    fn csv_writer(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder.delimiter(self.delimiter);
+        builder
+    }
 
-impl<T> ToGlueError for anyhow::Result<T> {
-    type Target = T;
+    /// A builder of CSV readers of the file at `path`
+    fn file_reader(&self, path: &Path) -> csv::ReaderBuilder {
+        let mut builder = self.csv_reader();
+        builder.delimiter(self.delimiter_for(path));
+        builder
+    }
 
-    fn to_glue_err(self) -> GlueResult<Self::Target> {
-        self.map_err(|err| GlueError::StorageMsg(err.to_string()))
+    /// A builder of CSV writers of the file at `path`
+    fn file_writer(&self, path: &Path) -> csv::WriterBuilder {
+        let mut builder = self.csv_writer();
+        builder.delimiter(self.delimiter_for(path));
+        builder
     }
-}
 
-impl CsvStore {
-    async fn insert_schema(&mut self, schema: &Schema) -> anyhow::Result<()> {
-        let table_id = TableIdentifier::new(schema.table_name.clone(), self.data_dir.clone());
-        let path: TablePath = table_id.try_into()?;
-        let headers = schema.column_defs.iter().map(|col| col.name.clone());
-        let csv_path = path.as_csv();
-        if let Some(parent) = csv_path.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// The delimiter of the file at `path`: a tab for `.tsv` files,
+    /// otherwise the configured one
+    fn delimiter_for(&self, path: &Path) -> u8 {
+        match path.extension() {
+            Some(ext) if ext == "tsv" => b'\t',
+            _ => self.delimiter,
         }
-        let mut writer = csv::Writer::from_path(csv_path)?;
-
-        writer.write_record(headers)?;
-
-        Ok(())
     }
 
-    async fn delete_schema(&mut self, table_name: &str) -> anyhow::Result<()> {
-        println!("delete_data");
-        dbg!(table_name);
-
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id.try_into()?;
-        std::fs::remove_file(path.as_csv())?;
-
-        Ok(())
+    /// Whether files with this extension are tables
+    fn is_table_extension(&self, ext: &OsStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|table_ext| ext == table_ext.as_str())
     }
 
-    async fn append_data(&mut self, table_name: &str, rows: Vec<Row>) -> anyhow::Result<()> {
-        println!("append_data");
-        dbg!(table_name);
-        dbg!(&rows);
+    /// The path of a table with the most preferred extension it has
+    /// a file with, or the first one for a table which doesn't exist yet
+    fn with_table_extension(&self, path: TablePath) -> TablePath {
+        self.extensions
+            .iter()
+            .map(|ext| path.clone().with_file_extension(ext))
+            .find(|path| path.as_csv().exists())
+            .unwrap_or_else(|| path.with_file_extension(&self.extensions[0]))
+    }
 
+    /// Resolve a table name as used in queries to its path on disk
+    fn table_path(&self, table_name: &str) -> anyhow::Result<TablePath> {
         let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id.try_into()?;
-        let file = OpenOptions::new().append(true).open(path.as_csv())?;
-        let mut writer = csv::WriterBuilder::new().from_writer(file);
-
-        for row in rows {
-            let values = row.0.into_iter().map(format_value);
-            writer.write_record(values)?;
+        let mut name: TableName = table_id.try_into()?;
+        if self.normalize_unicode {
+            name = name.resolve_unicode(&self.extensions);
         }
-
-        Ok(())
+        if name.last().as_deref() == Some(LATEST) {
+            name = self
+                .latest_table(name.parent())?
+                .with_context(|| format!("no tables to resolve {:?}", table_name))?;
+        }
+        Ok(self.with_table_extension(name.try_into()?))
     }
 
-    async fn insert_data(&mut self, table_name: &str, rows: Vec<(Key, Row)>) -> anyhow::Result<()> {
-        println!("insert_data");
-        dbg!(table_name);
-        dbg!(&rows);
+    /// The most recently modified CSV table directly within `dir`, if any
+    pub fn latest_table(&self, dir: TableName) -> anyhow::Result<Option<TableName>> {
+        let dir_path: TablePath = dir.clone().try_into()?;
+        let mut latest = None;
 
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id.try_into()?;
+        for entry_res in std::fs::read_dir(dir_path.as_dir())? {
+            let entry = entry_res?;
+            let path = entry.path();
+            let fname = match self.file_name(&entry)? {
+                Some(fname) => fname,
+                None => continue,
+            };
 
-        let mut numbered_rows: Vec<_> = rows
-            .into_iter()
-            .map(|(key, row)| get_row_num(&key).map(|row_num| (row_num, row)))
-            .collect::<anyhow::Result<_>>()?;
+            if self.should_ignore(&fname)
+                || !path
+                    .extension()
+                    .is_some_and(|ext| self.is_table_extension(ext))
+            {
+                continue;
+            }
 
-        // Sort rows
-        numbered_rows.sort_by_key(|(row_num, _row)| *row_num);
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified()?;
 
-        // Extract sorted row_nums & data
-        let mut row_nums = Vec::new();
-        let mut row_data = Vec::new();
-        for (row_num, row) in numbered_rows {
-            row_nums.push(row_num);
-            row_data.push(row);
+            if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or(&fname);
+                latest = Some((modified, stem.to_owned()));
+            }
         }
 
-        // Write new CSV rows to temporary buffer
-        let mut buf = Vec::new();
+        Ok(latest.map(|(_time, stem)| dir.join(stem)))
+    }
 
-        {
-            let mut writer = csv::WriterBuilder::new().from_writer(&mut buf);
+    /// Resolve the path of a CSV table, failing if it doesn't exist
+    fn existing_table_path(&self, table_name: &str) -> anyhow::Result<TablePath> {
+        let path = self.table_path(table_name)?;
+        if !path.as_csv().exists() {
+            return Err(TableNotFound(table_name.to_string()).into());
+        }
 
-            // Write rows to temporary buffer
-            for row in row_data {
-                let values = row.0.into_iter().map(format_value);
-                writer.write_record(values)?;
+        Ok(path)
+    }
+
+    /// Read the whole file to try to determine a suitable schema
+    fn read_schema(&self, path: TablePath) -> anyhow::Result<Schema> {
+        let InferredColumns {
+            col_pairs,
+            nullable,
+            ..
+        } = self
+            .columns_for_table(&path)
+            .context("getting column types for schema")?;
+
+        let table_id: TableIdentifier = path.try_into().context("table id -> path")?;
+
+        let mut schema = self.build_schema(table_id.to_string(), col_pairs);
+        if self.infer_not_null {
+            for (col_def, nullable) in schema.column_defs.iter_mut().zip(nullable) {
+                if !nullable {
+                    col_def.options = vec![ColumnOptionDef {
+                        name: None,
+                        option: ColumnOption::NotNull,
+                    }];
+                }
             }
         }
+        Ok(schema)
+    }
 
-        let new_lines = buf.lines();
-        let numbered_lines: Vec<_> = new_lines
-            .zip(row_nums)
-            .map(|(line, row_num)| line.map(|l| (row_num, l)))
-            .collect::<std::io::Result<_>>()?;
+    /// Read a whole fixed-width file to determine its column types
+    fn read_fixed_width_schema(
+        &self,
+        table_name: &str,
+        path: &Path,
+        spec: &FixedWidthSpec,
+    ) -> anyhow::Result<Schema> {
+        let col_pairs = get_column_types_for_fixed_width(
+            path,
+            spec,
+            &self.type_detectors,
+            &self.date_formats,
+            self.inference_sample_rows,
+        )
+        .context("getting column types for schema")?;
 
-        let previous_file = File::open(path.clone().as_csv())?;
-        let previous_reader = BufReader::new(previous_file);
+        Ok(self.build_schema(table_name.to_string(), col_pairs))
+    }
 
-        let previous_lines = previous_reader.lines();
+    fn build_schema(&self, table_name: String, col_pairs: Vec<(String, ColumnType)>) -> Schema {
+        let table_name = match self.normalize_unicode {
+            true => nfc(&table_name),
+            false => table_name,
+        };
+        let mut schema = Schema {
+            table_name,
+            column_defs: Vec::new(),
+            indexes: Vec::new(),
+        };
 
-        let injection = Injection::new(numbered_lines);
-        let injector = LineInjector::new(previous_lines, injection);
+        let (col_names, col_types): (Vec<_>, Vec<_>) = col_pairs.into_iter().unzip();
+        let col_names = self.schema_column_names(&col_names);
+        for (col_name, col_type) in col_names.into_iter().zip(col_types) {
+            // Any cell can be left empty, i.e. NULL, unless
+            // `read_schema` finds a CSV column has no empty cells
+            let col_def = ColumnDef {
+                name: col_name,
+                data_type: col_type.into(),
+                options: vec![ColumnOptionDef {
+                    name: None,
+                    option: ColumnOption::Null,
+                }],
+            };
 
-        // Write combined stream to buffer
-        let mut buf = Vec::new();
-        for line_res in injector {
-            let combined_line = line_res?;
-            writeln!(buf, "{}", combined_line)?;
+            schema.column_defs.push(col_def);
         }
 
-        // Overwrite original file with combined buffer
-        let mut combined_file = File::create(path.as_csv())?;
-        combined_file.write_all(&buf)?;
+        schema
+    }
 
-        Ok(())
+    /// The names queries use for columns with these headers: sanitized
+    /// (see `sanitize_column_names`), then in NFC form if names are normalized
+    fn schema_column_names(&self, headers: &[String]) -> Vec<String> {
+        let names = match self.sanitize_column_names {
+            true => column_identifiers(headers),
+            false => headers.to_vec(),
+        };
+        match self.normalize_unicode {
+            true => names.iter().map(|name| nfc(name)).collect(),
+            false => names,
+        }
     }
 
-    async fn delete_data(&mut self, table_name: &str, keys: Vec<Key>) -> anyhow::Result<()> {
-        println!("delete_data");
-        dbg!(table_name);
-        dbg!(&keys);
+    /// If the table has a fixed-width spec and a matching file,
+    /// return the file's path and the spec.
+    fn fixed_width_table(
+        &self,
+        table_name: &str,
+    ) -> anyhow::Result<Option<(PathBuf, &FixedWidthSpec)>> {
+        let spec = match self.fixed_width.get(table_name) {
+            Some(spec) => spec,
+            None => return Ok(None),
+        };
+        let path = self.table_path(table_name)?;
+
+        Ok(fixed_width::find_file(&path.as_dir()).map(|fw_path| (fw_path, spec)))
+    }
+
+    /// Fail if the table can't be modified
+    fn check_writable(&self, table_name: &str) -> anyhow::Result<()> {
+        if self.fixed_width.contains_key(table_name) {
+            bail!("fixed-width table {:?} is read-only", table_name);
+        }
+        if self.regex_delimiter.is_some() {
+            bail!("tables are read-only when regex_delimiter is set");
+        }
+        if self.partitions.contains_key(table_name) {
+            bail!("partitioned table {:?} is read-only", table_name);
+        }
+        if glob_table::pattern(table_name).is_some() {
+            bail!("{:?} is read-only", table_name);
+        }
+        if self.manifest_table(table_name)?.is_some() {
+            bail!("table {:?} with a manifest is read-only", table_name);
+        }
+        if self.encrypted_table(table_name)?.is_some() {
+            bail!("encrypted table {:?} is read-only", table_name);
+        }
+        let csv_path = self.table_path(table_name)?.as_csv();
+        if csv_path.exists() && self.modeline(&csv_path)?.is_some() {
+            bail!("table {:?} with a modeline is read-only", table_name);
+        }
+        if !self.is_within_writable_paths(table_name)? {
+            bail!(
+                "table {:?} is read-only: it's not within writable_paths",
+                table_name
+            );
+        }
+
+        Ok(())
+    }
+
+    /// A directory entry's file name, according to the filename policy.
+    /// `None` means the entry should be skipped.
+    fn file_name(&self, entry: &DirEntry) -> anyhow::Result<Option<String>> {
+        let fname = entry.file_name();
+        if let Some(fname) = fname.to_str() {
+            return Ok(Some(fname.to_owned()));
+        }
+
+        match self.filename_policy {
+            FilenamePolicy::Strict => bail!("file name {:?} is not valid UTF-8", fname),
+            FilenamePolicy::Skip => {
+                eprintln!(
+                    "warning: skipping {:?}, whose name is not valid UTF-8",
+                    entry.path()
+                );
+                Ok(None)
+            }
+            FilenamePolicy::Lossy => Ok(Some(fname.to_string_lossy().into_owned())),
+        }
+    }
+
+    /// Whether a table's path (relative to the data dir, without extension)
+    /// matches one of the writable_paths globs. Everything is writable
+    /// if none are configured.
+    fn is_within_writable_paths(&self, table_name: &str) -> anyhow::Result<bool> {
+        if self.writable_paths.is_empty() {
+            return Ok(true);
+        }
+
+        let path = self.table_path(table_name)?.as_dir();
+        let rel_path = path.strip_prefix(&self.data_dir)?;
+        Ok(self.writable_paths.is_match(rel_path))
+    }
+
+    /// Fail if writing a file at `path` would create or modify a table
+    /// that can't be modified. Files outside the data dir are fine.
+    pub fn check_writable_file(&self, path: &Path) -> anyhow::Result<()> {
+        // Resolve links in the part of the path that exists so far
+        let path = std::path::absolute(path)?;
+        let existing = path
+            .ancestors()
+            .find(|ancestor| ancestor.exists())
+            .context("no part of the path exists")?;
+        let resolved = existing.canonicalize()?.join(path.strip_prefix(existing)?);
+
+        let Ok(rel_path) = resolved.strip_prefix(&self.data_dir) else {
+            return Ok(());
+        };
+        let table_name = rel_path
+            .with_extension("")
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.check_writable(&table_name)
+    }
+
+    pub fn should_ignore(&self, filename: &str) -> bool {
+        self.ignores.is_match(filename)
+    }
+
+    /// List the tables and subdirectories directly within `dir`.
+    /// Entries are sorted by name, with a directory listed before
+    /// a table of the same name. If several files provide the same
+    /// table (e.g. `t.csv` and `t.fw`), only the first is kept.
+    pub fn list_tables(&self, dir: TableName) -> anyhow::Result<Vec<TableNode>> {
+        let dir_path: TablePath = dir.try_into()?;
+        let mut tables = Vec::new();
+
+        for entry_res in std::fs::read_dir(dir_path.as_dir())? {
+            let entry = entry_res?;
+
+            let fname = match self.file_name(&entry)? {
+                Some(fname) => fname,
+                None => continue,
+            };
+
+            if fname == WAL_DIR
+                || fname == aggregate_cache::AGGREGATE_DIR
+                || fname == manifest::FILE_NAME
+                || fname.ends_with(schema_file::SUFFIX)
+            {
+                continue;
+            }
+            if !self.should_ignore(&fname) {
+                let node = TableNode::try_from_dir_entry(entry, self)?;
+                tables.push(node);
+            }
+        }
+
+        tables.sort_by_key(|node| (node.name.last(), node.is_table()));
+        tables.dedup_by(|a, b| a.is_table() && b.is_table() && a.name.last() == b.name.last());
+
+        Ok(tables)
+    }
+
+    /// For recursive walks: list the tables and subdirectories within `dir`,
+    /// along with its canonical path, unless that's one of `ancestors` (the
+    /// directories being walked within), in which case warn and return
+    /// `None`. This stops symlink cycles (e.g. a link to a parent directory)
+    /// from recursing forever, while other links are still followed.
+    pub fn list_within(
+        &self,
+        dir: TableName,
+        ancestors: &[PathBuf],
+    ) -> anyhow::Result<Option<(PathBuf, Vec<TableNode>)>> {
+        let dir_path: TablePath = dir.clone().try_into()?;
+        let canonical = dir_path.as_dir().canonicalize()?;
+        if ancestors.contains(&canonical) {
+            let table_id: TableIdentifier = dir.try_into()?;
+            eprintln!(
+                "warning: skipping {:?}, which leads back to {:?}",
+                &*table_id, canonical
+            );
+            return Ok(None);
+        }
+
+        Ok(Some((canonical, self.list_tables(dir)?)))
+    }
+
+    /// Open a table's file, returning its column names
+    /// and an iterator over its records
+    fn open_table(&self, path: &TablePath) -> anyhow::Result<(Vec<String>, RecordIter)> {
+        let csv_path = self.cached(&path.as_csv())?;
+        // These read the first line as a header row regardless
+        let line_by_line: Option<(Vec<String>, RecordIter)> = match &self.regex_delimiter {
+            Some(delimiter) => {
+                let (first, records) = regex_delimited::open(&csv_path, delimiter)?;
+                Some((first, Box::new(records)))
+            }
+            None if self.lenient_parsing => {
+                let delimiter = self.delimiter_for(&csv_path);
+                let warned = Arc::clone(&self.lenient_warned);
+                let (first, records) = lenient::open(&csv_path, delimiter, warned)?;
+                Some((first, Box::new(records)))
+            }
+            None => None,
+        };
+        if let Some((first, records)) = line_by_line {
+            return Ok(match self.has_headers {
+                true => (first, records),
+                false => first_line_as_record(first, records),
+            });
+        }
+
+        let modeline = self.modeline(&csv_path)?;
+        let has_headers = modeline
+            .and_then(|modeline| modeline.has_headers)
+            .unwrap_or(self.has_headers);
+
+        let mut file = BufReader::new(self.open_file(&csv_path)?);
+        if modeline.is_some() {
+            file.read_line(&mut String::new())?;
+        }
+        let mut builder = self.file_reader(&csv_path);
+        builder.has_headers(has_headers).comment(self.comment);
+        if let Some(delimiter) = modeline.and_then(|modeline| modeline.delimiter) {
+            builder.delimiter(delimiter);
+        }
+        let mut reader = builder.from_reader(file);
+        // Without headers, this is the first record, which is still read as data
+        let first = reader.headers()?;
+        let mut headers: Vec<String> = if has_headers {
+            first.iter().map(ToString::to_string).collect()
+        } else {
+            (1..=first.len()).map(|i| format!("col{}", i)).collect()
+        };
+        let records = reader.into_records().map(|res| res.map_err(Into::into));
+
+        if !(has_headers && self.has_trailing_empty_column(&headers)) {
+            return Ok((headers, Box::new(records)));
+        }
+        headers.pop();
+        let ncols = headers.len();
+        let records = records.map(move |res| {
+            let mut record: StringRecord = res?;
+            if record.len() > ncols {
+                if record.get(ncols) != Some("") {
+                    bail!("unnamed trailing column has a value: {:?}", record);
+                }
+                record.truncate(ncols);
+            }
+            Ok(record)
+        });
+
+        Ok((headers, Box::new(records)))
+    }
+
+    /// Whether a table's unnamed last column (e.g. from a trailing comma
+    /// on every line) should be dropped, per `drop_trailing_empty_column`
+    fn has_trailing_empty_column(&self, headers: &[String]) -> bool {
+        self.drop_trailing_empty_column && headers.last().is_some_and(String::is_empty)
+    }
+
+    /// Whether rows written to a table need a trailing empty field,
+    /// to match the unnamed column dropped when it's read
+    fn writes_trailing_empty_column(&self, path: &TablePath) -> anyhow::Result<bool> {
+        if !(self.drop_trailing_empty_column && self.has_headers) {
+            return Ok(false);
+        }
+
+        let mut reader = self
+            .file_reader(&path.as_csv())
+            .has_headers(false)
+            .comment(self.comment)
+            .from_path(path.as_csv())?;
+        let headers: Vec<String> = match reader.records().next() {
+            Some(record) => record?.iter().map(ToString::to_string).collect(),
+            None => return Ok(false),
+        };
+
+        Ok(self.has_trailing_empty_column(&headers))
+    }
+
+    /// Open a table file for reading, drawing a progress bar
+    /// for large files if enabled
+    fn open_file(&self, path: &Path) -> anyhow::Result<Box<dyn Read>> {
+        let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+        let size = file.metadata()?.len();
+        match &self.progress {
+            Some(progress) if size >= progress::MIN_BYTES => {
+                match progress.reader(file, path, size) {
+                    Ok(reader) => Ok(Box::new(reader)),
+                    Err(file) => Ok(Box::new(file)),
+                }
+            }
+            _ => Ok(Box::new(file)),
+        }
+    }
+
+    /// Let each large file's progress, and any lenient parsing warning,
+    /// be shown again the next time it's read. Within a statement, they're
+    /// only shown the first time.
+    pub fn end_statement(&self) {
+        if let Some(progress) = &self.progress {
+            progress.reset();
+        }
+        self.lenient_warned
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+    }
+
+    /// A CSV file's modeline, if modelines are enabled and it has one
+    fn modeline(&self, csv_path: &Path) -> anyhow::Result<Option<Modeline>> {
+        match self.modelines {
+            true => Modeline::read(csv_path),
+            false => Ok(None),
+        }
+    }
+
+    /// Path to read a table file from: its copy in the cache
+    /// directory (refreshed if stale), or the file itself if there's no cache
+    fn cached(&self, source: &Path) -> anyhow::Result<PathBuf> {
+        let cache_dir = match &self.cache_dir {
+            Some(dir) => dir,
+            None => return Ok(source.to_owned()),
+        };
+        let rel = source
+            .strip_prefix(&self.data_dir)
+            .with_context(|| format!("{:?} is not in the data directory", source))?;
+        let copy = cache_dir.join(rel);
+
+        let source_meta = std::fs::metadata(source)?;
+        let fresh = std::fs::metadata(&copy).is_ok_and(|copy_meta| {
+            copy_meta.len() == source_meta.len()
+                && copy_meta.modified().ok() == source_meta.modified().ok()
+        });
+
+        if !fresh {
+            if let Some(parent) = copy.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(source, &copy)
+                .with_context(|| format!("caching {:?} at {:?}", source, copy))?;
+            // The copy's mtime records which version of the source it holds
+            File::options()
+                .write(true)
+                .open(&copy)?
+                .set_modified(source_meta.modified()?)?;
+        }
+
+        Ok(copy)
+    }
+
+    /// Number of header lines at the start of each CSV file
+    fn header_lines(&self) -> usize {
+        usize::from(self.has_headers)
+    }
+
+    /// Column names of a table, read from its header row
+    fn read_headers(&self, path: &TablePath) -> anyhow::Result<Vec<String>> {
+        let (headers, _records) = self.open_table(path)?;
+        Ok(headers)
+    }
+
+    /// The type of each column of a table, from the schema cache
+    /// if neither the table's file nor its schema file has changed
+    fn column_types_for_table(
+        &self,
+        path: &TablePath,
+    ) -> anyhow::Result<Vec<(String, ColumnType)>> {
+        Ok(self.columns_for_table(path)?.col_pairs)
+    }
+
+    /// The type of each column of a table, and whether it can be NULL,
+    /// from the schema cache if the table's files haven't changed
+    fn columns_for_table(&self, path: &TablePath) -> anyhow::Result<InferredColumns> {
+        let csv_path = path.as_csv();
+        let stamp = FileStamp::of(&csv_path, &path.as_schema_file())?;
+        let cached = self
+            .cached_columns()
+            .get(&csv_path)
+            .and_then(|cached| (cached.stamp == stamp).then(|| cached.columns.clone()));
+        if let Some(columns) = cached {
+            return Ok(columns);
+        }
+
+        // Without holding the lock, so other tables can be read meanwhile
+        let columns = self.infer_column_types(path)?;
+        let cached = CachedColumns {
+            stamp,
+            columns: columns.clone(),
+        };
+        self.cached_columns().insert(csv_path, cached);
+
+        Ok(columns)
+    }
+
+    /// A table's row count from the schema cache, if its files haven't
+    /// changed and inferring its types read every record
+    fn cached_row_count(&self, path: &TablePath) -> anyhow::Result<Option<usize>> {
+        let csv_path = path.as_csv();
+        let stamp = FileStamp::of(&csv_path, &path.as_schema_file())?;
+        Ok(self
+            .cached_columns()
+            .get(&csv_path)
+            .filter(|cached| cached.stamp == stamp)
+            .and_then(|cached| cached.columns.rows))
+    }
+
+    fn cached_columns(&self) -> MutexGuard<'_, HashMap<PathBuf, CachedColumns>> {
+        self.schema_cache
+            .lock()
+            .expect("schema cache lock poisoned")
+    }
+
+    /// Read a whole table to determine the type of each column, and
+    /// whether it can be NULL, unless they're given by its schema file
+    fn infer_column_types(&self, path: &TablePath) -> anyhow::Result<InferredColumns> {
+        let (headers, records) = self.open_table(path)?;
+        let schema_path = path.as_schema_file();
+        if schema_path.exists() {
+            let schema = SchemaFile::read(&schema_path)?;
+            self.check_schema_file_columns(&schema, &headers)
+                .with_context(|| format!("columns of {:?}", path.as_csv()))?;
+            let pairs: Vec<_> = schema
+                .data_types()?
+                .into_iter()
+                .map(|(name, data_type)| (name, ColumnType::from_data_type(data_type)))
+                .collect();
+            let nullable = vec![true; pairs.len()];
+            return Ok(InferredColumns {
+                col_pairs: pairs,
+                nullable,
+                rows: None,
+            });
+        }
+
+        let (col_types, nullable, rows) = determine_columns(
+            records,
+            headers.len(),
+            &self.type_detectors,
+            &self.date_formats,
+            self.inference_sample_rows,
+        )
+        .context("get col_types")?;
+
+        Ok(InferredColumns {
+            col_pairs: headers.into_iter().zip(col_types).collect(),
+            nullable,
+            rows,
+        })
+    }
+
+    /// Check that a schema file describes a file's columns: the same
+    /// number of them, with the same names if the file has a header row
+    fn check_schema_file_columns(
+        &self,
+        schema: &SchemaFile,
+        headers: &[String],
+    ) -> anyhow::Result<()> {
+        let names = schema.names();
+        if names.len() != headers.len() || (self.has_headers && names != headers) {
+            bail!(
+                "expected {:?} from the schema file, found {:?}",
+                names,
+                headers
+            );
+        }
+        Ok(())
+    }
+
+    /// A table's header row, as CSV text without a trailing newline.
+    /// Only the first line of the file is read.
+    pub fn header_line(&self, table_name: &str) -> anyhow::Result<String> {
+        let path = self.existing_table_path(table_name)?;
+        let headers = self.read_headers(&path)?;
+
+        let mut writer = self.file_writer(&path.as_csv()).from_writer(Vec::new());
+        writer.write_record(&headers)?;
+        let line = String::from_utf8(writer.into_inner()?)?;
+
+        Ok(line.trim_end().to_string())
+    }
+
+    /// The number of rows in a table. CSV records are counted without
+    /// inferring column types or reading values, unlike in a query, or
+    /// not read at all if inferring the table's types already counted them.
+    pub fn count_rows(&self, table_name: &str) -> anyhow::Result<usize> {
+        if let Some(pending) = self.pending_table(table_name) {
+            let table = pending.ok_or_else(|| TableNotFound(table_name.to_string()))?;
+            return Ok(table.rows.len());
+        }
+        if let Some((fw_path, spec)) = self.fixed_width_table(table_name)? {
+            let mut records = fixed_width::read_records(&fw_path, spec)?;
+            return records.try_fold(0, |n, res| res.map(|_record| n + 1));
+        }
+
+        let path = self.table_path(table_name)?;
+        let mut records = match glob_table::pattern(table_name) {
+            None if path.as_csv().exists() => {
+                if let Some(rows) = self.cached_row_count(&path)? {
+                    return Ok(rows);
+                }
+                self.open_table(&path)?.1
+            }
+            // Other kinds of table, e.g. with manifests
+            _ => self.open_records(table_name)?.1,
+        };
+
+        records.try_fold(0, |n, res| res.map(|_record| n + 1))
+    }
+
+    /// The rows of a table with row ids from `start` up to (not including)
+    /// `end`, and the table's column names. Reading stops at `end`.
+    pub fn slice_rows(
+        &self,
+        table_name: &str,
+        start: usize,
+        end: usize,
+    ) -> anyhow::Result<(Vec<String>, Vec<Vec<Value>>)> {
+        if start > end {
+            bail!("slice start {} is after its end {}", start, end);
+        }
+        let (col_pairs, records) = self.open_records(table_name)?;
+        let (headers, col_types): (Vec<_>, Vec<_>) = col_pairs.into_iter().unzip();
+        // Computed columns refer to columns by the names queries use
+        let col_names = self.schema_column_names(&headers);
+        let computed = self.computed_columns(table_name);
+
+        let rows = records
+            .skip(start)
+            .take(end - start)
+            .map(|res| {
+                let row = read_csv_record(&res?, &col_types, self.float_to_int)?;
+                let Row(values) =
+                    append_computed(&col_names, row, computed).map_err(|err| anyhow!("{}", err))?;
+                Ok(values)
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let labels = col_names
+            .into_iter()
+            .chain(computed.iter().map(|column| column.column_def().name))
+            .collect();
+
+        Ok((labels, rows))
+    }
+
+    /// The format to write each of a table's columns in, if not the
+    /// default: its configured output format, or else the format
+    /// (from `date_formats`) its existing dates were read in
+    fn write_formats(
+        &self,
+        table_name: &str,
+        headers: &[String],
+    ) -> anyhow::Result<Vec<Option<String>>> {
+        let configured = self.output_formats.get(table_name);
+        let path = self.table_path(table_name)?;
+        let read_formats = if self.date_formats.is_empty() || !path.as_csv().exists() {
+            Vec::new()
+        } else {
+            self.column_types_for_table(&path)?
+                .into_iter()
+                .map(|(_, typ)| match typ {
+                    ColumnType::FormattedDate(fmt) | ColumnType::FormattedTimestamp(fmt) => {
+                        Some(fmt)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        Ok(headers
+            .iter()
+            .enumerate()
+            .map(|(i, col)| {
+                configured
+                    .and_then(|formats| formats.get(col))
+                    .or_else(|| read_formats.get(i)?.as_ref())
+                    .cloned()
+            })
+            .collect())
+    }
+
+    /// Stream the raw records of a table, along with its header
+    pub fn table_records(&self, table_name: &str) -> anyhow::Result<(Vec<String>, RecordIter)> {
+        if let Some((dir, manifest)) = self.manifest_table(table_name)? {
+            return manifest.open(&dir, self.delimiter);
+        }
+        let path = self.existing_table_path(table_name)?;
+        self.open_table(&path)
+            .with_context(|| format!("opening table {:?}", table_name))
+    }
+
+    /// Stream the raw values of one column of a table
+    pub fn column_values(
+        &self,
+        table_name: &str,
+        column: &str,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<String>>> {
+        let (headers, records) = self.table_records(table_name)?;
+
+        let col_index = headers
+            .iter()
+            .position(|header| header == column)
+            .with_context(|| format!("no column {:?} in table {:?}", column, table_name))?;
+
+        let iter = records.map(move |res| {
+            let record = res?;
+            let value = record.get(col_index).unwrap_or_default().to_owned();
+            Ok(value)
+        });
+
+        Ok(iter)
+    }
+
+    /// Stream the rows of a table whose `column` matches `pattern`
+    /// to `out` as CSV, after the header, returning how many matched
+    pub fn grep_table<W: Write>(
+        &self,
+        table_name: &str,
+        column: &str,
+        pattern: &Regex,
+        out: W,
+    ) -> anyhow::Result<usize> {
+        let (headers, records) = self.table_records(table_name)?;
+        let col_index = headers
+            .iter()
+            .position(|header| header == column)
+            .with_context(|| format!("no column {:?} in table {:?}", column, table_name))?;
+
+        let mut writer = self.csv_writer().from_writer(out);
+        writer.write_record(&headers)?;
+
+        let mut nmatched = 0;
+        for record_res in records {
+            let record = record_res?;
+            if pattern.is_match(record.get(col_index).unwrap_or_default()) {
+                writer.write_record(&record)?;
+                nmatched += 1;
+            }
+        }
+        writer.flush()?;
+
+        Ok(nmatched)
+    }
+
+    /// Copy a CSV file into the data directory as a new table.
+    /// Whether the source has a header row is given by `headers`, which
+    /// defaults to yes unless `columns_from` is given. If `auto` can't
+    /// tell, the `has_headers` setting decides.
+    /// With `columns_from`, each record is validated against that table's
+    /// column count and types, and its column names become the new table's
+    /// header. Otherwise a headerless source gets columns `col1..N`.
+    /// With `schema_file`, records are validated against the types it
+    /// lists, and it's copied beside the table to fix them from then on.
+    pub fn import_table(
+        &self,
+        source: &Path,
+        table_name: &str,
+        columns_from: Option<&str>,
+        headers: Option<ImportHeaders>,
+        schema_file: Option<&Path>,
+    ) -> anyhow::Result<Imported> {
+        self.check_writable(table_name)?;
+        let path = self.table_path(table_name)?;
+        let csv_path = path.as_csv();
+        if csv_path.exists() {
+            bail!("table {:?} already exists", table_name);
+        }
+        let schema = schema_file.map(SchemaFile::read).transpose()?;
+
+        let reader = self
+            .file_reader(source)
+            .has_headers(false)
+            .from_path(source)
+            .with_context(|| format!("opening {:?}", source))?;
+        let mut records = reader.into_records();
+        let sample = records
+            .by_ref()
+            .take(HEADER_SAMPLE_ROWS)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let has_header = match headers {
+            Some(ImportHeaders::Yes) => true,
+            Some(ImportHeaders::No) => false,
+            Some(ImportHeaders::Auto) => {
+                looks_like_header(&sample, &self.type_detectors, &self.date_formats)
+                    .unwrap_or(self.has_headers)
+            }
+            None => columns_from.is_none(),
+        };
+        let mut records = sample.into_iter().map(Ok).chain(records).peekable();
+        let source_headers = match has_header {
+            true => records.next().transpose()?,
+            false => None,
+        };
+
+        let (headers, col_types) = match (columns_from, &schema) {
+            (Some(_), Some(_)) => bail!("columns_from and schema_file can't both be given"),
+            (Some(reference), None) => {
+                let ref_path = self.existing_table_path(reference)?;
+                let schema = self.read_schema(ref_path.clone())?;
+                let col_pairs = self.column_types_for_table(&ref_path)?;
+                let headers: Vec<_> = schema.column_defs.into_iter().map(|col| col.name).collect();
+                let col_types: Vec<_> = col_pairs.into_iter().map(|(_name, typ)| typ).collect();
+                (headers, Some(col_types))
+            }
+            (None, Some(schema)) => {
+                let headers = schema.names();
+                if let Some(record) = &source_headers {
+                    if !record.iter().eq(headers.iter()) {
+                        bail!(
+                            "{:?} has columns {:?}, but the schema file has {:?}",
+                            source,
+                            record,
+                            headers
+                        );
+                    }
+                }
+                let col_types = schema
+                    .data_types()?
+                    .into_iter()
+                    .map(|(_name, data_type)| ColumnType::from_data_type(data_type))
+                    .collect();
+                (headers, Some(col_types))
+            }
+            (None, None) => {
+                let headers = match &source_headers {
+                    Some(record) => record.iter().map(ToString::to_string).collect(),
+                    None => {
+                        let ncols = match records.peek() {
+                            Some(Ok(record)) => record.len(),
+                            _ => 0,
+                        };
+                        (1..=ncols).map(|i| format!("col{}", i)).collect()
+                    }
+                };
+                (headers, None)
+            }
+        };
+
+        let mut buf = Vec::new();
+        let mut nrows = 0;
+
+        {
+            let mut writer = self.file_writer(&csv_path).from_writer(&mut buf);
+            if self.has_headers {
+                writer.write_record(&headers)?;
+            }
+
+            let first_line = if has_header { 2 } else { 1 };
+            for (i, res) in records.enumerate() {
+                let record = res?;
+                let record = match &col_types {
+                    Some(col_types) => validate_record(&record, col_types, self.float_to_int)
+                        .with_context(|| format!("{:?} line {}", source, first_line + i))?,
+                    None => record,
+                };
+                writer.write_record(&record)?;
+                nrows += 1;
+            }
+        }
+
+        if let Some(parent) = csv_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(csv_path, buf)?;
+        if let Some(schema) = &schema {
+            schema.write(&path.as_schema_file())?;
+        }
+
+        Ok(Imported {
+            rows: nrows,
+            header: has_header,
+        })
+    }
+
+    /// Replace the contents of an existing table with a CSV file, which
+    /// must have a header row. Unless `allow_schema_change` is set, its
+    /// columns must have the table's names, and types which fit the
+    /// table's (e.g. integers in a float column). The new file is
+    /// written alongside the table and renamed over it, so readers see
+    /// either the old or the new contents. With `allow_schema_change`,
+    /// the table's schema file is removed, so its types are inferred again.
+    /// Returns the number of rows.
+    pub fn replace_table(
+        &self,
+        source: &Path,
+        table_name: &str,
+        allow_schema_change: bool,
+    ) -> anyhow::Result<usize> {
+        self.check_writable(table_name)?;
+        let path = self.existing_table_path(table_name)?;
+
+        let mut reader = self
+            .file_reader(source)
+            .from_path(source)
+            .with_context(|| format!("opening {:?}", source))?;
+        let mut headers: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+        let mut records = reader.into_records().collect::<Result<Vec<_>, _>>()?;
+        // A source in the table's own layout has the trailing column too
+        if self.has_trailing_empty_column(&headers) {
+            headers.pop();
+            for record in &mut records {
+                record.truncate(headers.len());
+            }
+        }
+
+        if !allow_schema_change {
+            // Every row being replaced must fit the table
+            let col_types = determine_column_types(
+                records.iter().cloned().map(Ok),
+                headers.len(),
+                &self.type_detectors,
+                &self.date_formats,
+                0,
+            )?;
+            let new_pairs: Vec<_> = headers.iter().cloned().zip(col_types).collect();
+            let old_pairs = self.column_types_for_table(&path)?;
+            let compatible = old_pairs.len() == new_pairs.len()
+                && old_pairs.iter().zip(&new_pairs).all(|(old, new)| {
+                    old.0 == new.0 && old.1.clone().merge(new.1.clone()) == old.1
+                });
+            if !compatible {
+                bail!(
+                    "{:?} has columns {:?}, but {:?} has {:?} \
+                     (use --allow-schema-change to replace it anyway)",
+                    source,
+                    new_pairs,
+                    table_name,
+                    old_pairs
+                );
+            }
+        }
+
+        let trailing_empty = self.writes_trailing_empty_column(&path)?;
+        let csv_path = path.as_csv();
+        let tmp_path = temp_path(&csv_path);
+        {
+            let file =
+                File::create(&tmp_path).with_context(|| format!("creating {:?}", tmp_path))?;
+            let mut writer = self
+                .file_writer(&csv_path)
+                .from_writer(BufWriter::new(file));
+            if self.has_headers {
+                if trailing_empty {
+                    headers.push(String::new());
+                }
+                writer.write_record(&headers)?;
+            }
+            for record in &mut records {
+                if trailing_empty {
+                    record.push_field("");
+                }
+                writer.write_record(&*record)?;
+            }
+            writer.flush()?;
+        }
+
+        // A schema file no longer describes the new columns
+        let schema_path = path.as_schema_file();
+        let stale_schema = allow_schema_change && schema_path.exists();
+        let mut paths = vec![csv_path.clone()];
+        if stale_schema {
+            paths.push(schema_path.clone());
+        }
+        let txn = self.begin_mutation(&paths)?;
+        self.replace_file(&tmp_path, &csv_path)?;
+        if stale_schema {
+            std::fs::remove_file(&schema_path)?;
+        }
+        txn.commit()?;
+
+        Ok(records.len())
+    }
+
+    /// Rewrite a table so its rows are in ascending order of `key_column`,
+    /// keeping rows with equal keys (or NULLs, which go last) in their
+    /// current order. A row's key is otherwise its position in the file,
+    /// so without a column there's nothing to reorder. The file is replaced
+    /// atomically, as in `replace_table`. Returns whether rows were reordered.
+    pub fn reindex_table(
+        &self,
+        table_name: &str,
+        key_column: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        self.check_writable(table_name)?;
+        let path = self.existing_table_path(table_name)?;
+        let key_column = match key_column {
+            Some(column) => column,
+            None => return Ok(false),
+        };
+
+        let (col_pairs, records) = self.open_records(table_name)?;
+        let (index, col_type) = col_pairs
+            .iter()
+            .enumerate()
+            .find_map(|(i, (name, col_type))| (name == key_column).then_some((i, col_type)))
+            .with_context(|| format!("{:?} has no column {:?}", table_name, key_column))?;
+
+        let mut keyed = records
+            .enumerate()
+            .map(|(position, res)| {
+                let record = res?;
+                let key = match record.get(index).unwrap_or_default() {
+                    "" => None,
+                    value => Some(value_from_str(value, col_type, self.float_to_int)?),
+                };
+                Ok((key, position, record))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        keyed.sort_by(|(a, a_pos, _), (b, b_pos, _)| {
+            let by_key = match (a, b) {
+                (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            by_key.then(a_pos.cmp(b_pos))
+        });
+        if keyed
+            .iter()
+            .enumerate()
+            .all(|(i, (_key, position, _record))| i == *position)
+        {
+            return Ok(false);
+        }
+
+        let mut headers = self.read_headers(&path)?;
+        let trailing_empty = self.writes_trailing_empty_column(&path)?;
+        let csv_path = path.as_csv();
+        let tmp_path = temp_path(&csv_path);
+        {
+            let file =
+                File::create(&tmp_path).with_context(|| format!("creating {:?}", tmp_path))?;
+            let mut writer = self
+                .file_writer(&csv_path)
+                .from_writer(BufWriter::new(file));
+            if self.has_headers {
+                if trailing_empty {
+                    headers.push(String::new());
+                }
+                writer.write_record(&headers)?;
+            }
+            for (_key, _position, mut record) in keyed {
+                if trailing_empty {
+                    record.push_field("");
+                }
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+
+        let txn = self.begin_mutation(std::slice::from_ref(&csv_path))?;
+        self.replace_file(&tmp_path, &csv_path)?;
+        txn.commit()?;
+
+        Ok(true)
+    }
+
+    /// Append rows given as a JSON array of objects keyed by column name.
+    /// Fields are converted to the column types inferred for the table,
+    /// and missing fields are NULL. Returns the number of rows appended.
+    pub async fn insert_json(&mut self, table_name: &str, json: &str) -> anyhow::Result<usize> {
+        let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(json).context("expected a JSON array of objects")?;
+        let (col_pairs, _records) = self.open_records(table_name)?;
+
+        let rows = objects
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut object)| {
+                let values = col_pairs
+                    .iter()
+                    .map(|(name, typ)| match object.remove(name) {
+                        Some(field) => value_from_json(&field, typ, self.float_to_int)
+                            .with_context(|| format!("column {:?}", name)),
+                        None => Ok(Value::Null),
+                    })
+                    .collect::<anyhow::Result<_>>()
+                    .with_context(|| format!("object {}", i))?;
+                if let Some(unknown) = object.keys().next() {
+                    bail!("object {}: no column {:?} in {:?}", i, unknown, table_name);
+                }
+                Ok(Row(values))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let nrows = rows.len();
+        self.append_data(table_name, rows).await?;
+
+        Ok(nrows)
+    }
+
+    /// Append CSV records read from `source` to an existing table, one at
+    /// a time, so input of any size is loaded in bounded memory. Each
+    /// record must fit the table's columns; if one doesn't, the table is
+    /// left as it was. With `ImportHeaders::Auto`, the first record is
+    /// skipped if it's the same as the table's header.
+    /// Returns the number of rows appended.
+    pub fn load_csv<R: Read>(
+        &self,
+        table_name: &str,
+        source: R,
+        headers: ImportHeaders,
+    ) -> anyhow::Result<usize> {
+        self.check_writable(table_name)?;
+        if self.overlay.is_some() {
+            bail!("loading writes straight to disk, so it can't be done in overlay mode");
+        }
+        let path = self.existing_table_path(table_name)?;
+        let col_types: Vec<_> = self
+            .column_types_for_table(&path)?
+            .into_iter()
+            .map(|(_, typ)| typ)
+            .collect();
+        let table_headers = self.read_headers(&path)?;
+        let trailing_empty = self.writes_trailing_empty_column(&path)?;
+
+        let mut records = self
+            .file_reader(&path.as_csv())
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(source)
+            .into_records()
+            .peekable();
+        let skip_first = match headers {
+            ImportHeaders::Yes => true,
+            ImportHeaders::No => false,
+            ImportHeaders::Auto => matches!(
+                records.peek(),
+                Some(Ok(first)) if first.iter().eq(table_headers.iter().map(String::as_str))
+            ),
+        };
+        if skip_first {
+            records.next().transpose()?;
+        }
+
+        let csv_path = path.as_csv();
+        let txn = self.begin_mutation(std::slice::from_ref(&csv_path))?;
+        let mut file = OpenOptions::new().append(true).open(&csv_path)?;
+        let original_len = file.metadata()?.len();
+        let mut writer = self.file_writer(&csv_path).from_writer(&mut file);
+        let loaded = records.enumerate().try_fold(0, |nrows, (i, res)| {
+            let mut record = validate_record(&res?, &col_types, self.float_to_int)
+                .with_context(|| format!("row {}", i + 1))?;
+            if trailing_empty {
+                record.push_field("");
+            }
+            writer.write_record(&record)?;
+            anyhow::Ok(nrows + 1)
+        });
+        let loaded = loaded.and_then(|nrows| {
+            writer.flush()?;
+            Ok(nrows)
+        });
+        drop(writer);
+
+        let nrows = match loaded {
+            Ok(nrows) => nrows,
+            Err(err) => {
+                file.set_len(original_len)?;
+                txn.commit()?;
+                return Err(err.context(format!("loading into {:?}", table_name)));
+            }
+        };
+        if self.durable_writes {
+            file.sync_all()?;
+        }
+        txn.commit()?;
+
+        Ok(nrows)
+    }
+
+    /// Scan a table's CSV file for structural problems (see `lint::lint`)
+    pub fn lint_table(&self, table_name: &str) -> anyhow::Result<LintReport> {
+        let path = self.existing_table_path(table_name)?.as_csv();
+        let file = File::open(&path).with_context(|| format!("opening {:?}", path))?;
+        Ok(lint::lint(
+            file,
+            self.delimiter_for(&path),
+            self.comment,
+            self.has_headers,
+        )?)
+    }
+
+    /// Metadata about every table within `dir`, recursively
+    pub fn inventory(&self, dir: TableName) -> anyhow::Result<Vec<TableInfo>> {
+        let mut tables = Vec::new();
+        self.tables_within(dir, &mut Vec::new(), &mut tables)?;
+
+        concurrency::map_bounded(&tables, self.max_concurrency, |(table_id, schema)| {
+            self.table_info(table_id, schema.clone())
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Every table within `dir`, recursively, with its schema
+    fn tables_within(
+        &self,
+        dir: TableName,
+        ancestors: &mut Vec<PathBuf>,
+        tables: &mut Vec<(TableIdentifier, Schema)>,
+    ) -> anyhow::Result<()> {
+        let Some((canonical, nodes)) = self.list_within(dir, ancestors)? else {
+            return Ok(());
+        };
+        ancestors.push(canonical);
+        for node in nodes {
+            match node.data {
+                TableData::Table(schema) => tables.push((node.name.try_into()?, schema)),
+                TableData::Dir => self.tables_within(node.name, ancestors, tables)?,
+            }
+        }
+        ancestors.pop();
+
+        Ok(())
+    }
+
+    fn table_info(&self, table_id: &TableIdentifier, schema: Schema) -> anyhow::Result<TableInfo> {
+        let (format, file_paths) =
+            if let Some((fw_path, _spec)) = self.fixed_width_table(table_id)? {
+                ("fixed_width", vec![fw_path])
+            } else if let Some((dir, manifest)) = self.manifest_table(table_id)? {
+                let mut paths: Vec<_> = manifest.parts.iter().map(|part| dir.join(part)).collect();
+                paths.push(dir.join(manifest::FILE_NAME));
+                ("manifest", paths)
+            } else if let Some(path) = self.encrypted_table(table_id)? {
+                ("encrypted", vec![path])
+            } else {
+                ("csv", vec![self.table_path(table_id)?.as_csv()])
+            };
+        let mut bytes = 0;
+        let mut modified = std::time::SystemTime::UNIX_EPOCH;
+        for path in &file_paths {
+            let metadata = std::fs::metadata(path)?;
+            bytes += metadata.len();
+            modified = modified.max(metadata.modified()?);
+        }
+        let mtime = chrono::DateTime::<chrono::Utc>::from(modified);
+
+        let rows = self.count_rows(table_id)?;
+
+        let columns = schema
+            .column_defs
+            .into_iter()
+            .map(|col| ColumnInfo {
+                name: col.name,
+                data_type: col.data_type.to_string(),
+            })
+            .collect();
+
+        Ok(TableInfo {
+            identifier: table_id.to_string(),
+            format,
+            rows,
+            columns,
+            bytes,
+            mtime: mtime.to_rfc3339(),
+        })
+    }
+
+    /// Write every table within `dir`, recursively, to a file in `out_dir`
+    /// at the same relative path, creating directories as needed.
+    /// Returns the paths written.
+    pub fn export_all(
+        &self,
+        dir: TableName,
+        out_dir: &Path,
+        format: ExportFormat,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let dir_path: TablePath = dir.clone().try_into()?;
+        let mut tables = Vec::new();
+        self.tables_within(dir, &mut Vec::new(), &mut tables)?;
+
+        let root = dir_path.as_dir();
+        concurrency::map_bounded(&tables, self.max_concurrency, |(table_id, _schema)| {
+            let path = self.table_path(table_id)?;
+            let mut out_path = out_dir
+                .join(path.as_dir().strip_prefix(&root)?)
+                .into_os_string();
+            out_path.push(format.extension());
+            let out_path = PathBuf::from(out_path);
+            self.check_writable_file(&out_path)?;
+
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file =
+                File::create(&out_path).with_context(|| format!("creating {:?}", out_path))?;
+            self.export_table(table_id, BufWriter::new(file), format)
+                .with_context(|| format!("exporting {:?}", table_id.to_string()))?;
+            Ok(out_path)
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Write a table's rows as CSV, a JSON array of objects, an HTML table
+    /// or (with the `parquet` feature) a Parquet file
+    fn export_table<W: Write + Send>(
+        &self,
+        table_name: &str,
+        mut out: W,
+        format: ExportFormat,
+    ) -> anyhow::Result<()> {
+        let (col_pairs, records) = self.open_records(table_name)?;
+        let aliases = self.column_aliases(table_name);
+        let labels: Vec<_> = col_pairs
+            .iter()
+            .map(|(name, _typ)| {
+                aliases
+                    .and_then(|aliases| aliases.get(name))
+                    .unwrap_or(name)
+                    .clone()
+            })
+            .collect();
+
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = self.csv_writer().from_writer(out);
+                writer.write_record(&labels)?;
+                for record in records {
+                    writer.write_record(&record?)?;
+                }
+                writer.flush()?;
+            }
+            ExportFormat::Json => {
+                write!(out, "[")?;
+                for (i, record) in records.enumerate() {
+                    let object: serde_json::Map<_, _> = record?
+                        .iter()
+                        .zip(col_pairs.iter().zip(&labels))
+                        .map(|(val, ((_name, typ), label))| {
+                            Ok((
+                                label.clone(),
+                                value_json(value_from_str(val, typ, self.float_to_int)?),
+                            ))
+                        })
+                        .collect::<anyhow::Result<_>>()?;
+                    let sep = if i == 0 { "\n" } else { ",\n" };
+                    write!(out, "{}{}", sep, serde_json::Value::Object(object))?;
+                }
+                writeln!(out, "\n]")?;
+                out.flush()?;
+            }
+            ExportFormat::Html => {
+                // NULLs are written as empty cells
+                let rows = records.map(|record| {
+                    Ok(record?
+                        .iter()
+                        .map(|val| (!val.is_empty()).then(|| val.to_owned()))
+                        .collect())
+                });
+                html::write_table(&mut out, &labels, rows, false)?;
+                out.flush()?;
+            }
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => {
+                let col_types: Vec<_> = col_pairs.into_iter().map(|(_name, typ)| typ).collect();
+                let rows = records
+                    .map(|record| Ok(read_csv_record(&record?, &col_types, self.float_to_int)?.0))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                parquet_file::write_rows(out, &labels, &rows)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Identifiers of all CSV tables within `dir`, recursively
+    fn csv_tables_within(
+        &self,
+        dir: TableName,
+        ancestors: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<Vec<String>> {
+        let Some((canonical, nodes)) = self.list_within(dir, ancestors)? else {
+            return Ok(Vec::new());
+        };
+        ancestors.push(canonical);
+        let mut ids = Vec::new();
+        for node in nodes {
+            if node.is_table() {
+                let path = self.with_table_extension(node.name.clone().try_into()?);
+                if path.as_csv().exists() {
+                    let table_id: TableIdentifier = node.name.try_into()?;
+                    ids.push(table_id.to_string());
+                }
+            } else {
+                ids.extend(self.csv_tables_within(node.name, ancestors)?);
+            }
+        }
+        ancestors.pop();
+
+        Ok(ids)
+    }
+
+    /// Concatenate the rows of all tables whose identifiers match `pattern`
+    /// into a new table, which is written atomically. The tables must have
+    /// the same column names and types. Returns the merged table names
+    /// and the number of rows written.
+    pub fn merge_tables(
+        &self,
+        pattern: &str,
+        table_name: &str,
+    ) -> anyhow::Result<(Vec<String>, usize)> {
+        self.check_writable(table_name)?;
+        let csv_path = self.table_path(table_name)?.as_csv();
+        if csv_path.exists() {
+            bail!("table {:?} already exists", table_name);
+        }
+
+        let (sources, expected) = self.glob_sources(pattern)?;
+
+        if let Some(parent) = csv_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = temp_path(&csv_path);
+
+        let mut nrows = 0;
+        {
+            let file = File::create(&tmp_path)?;
+            let mut writer = self.file_writer(&csv_path).from_writer(file);
+            if self.has_headers {
+                let headers: Vec<_> = expected.iter().map(|(name, _typ)| name).collect();
+                writer.write_record(headers)?;
+            }
+
+            for source in &sources {
+                let (_headers, records) = self.open_table(&self.table_path(source)?)?;
+                for res in records {
+                    writer.write_record(&res.with_context(|| format!("reading {:?}", source))?)?;
+                    nrows += 1;
+                }
+            }
+            writer.flush()?;
+        }
+        self.replace_file(&tmp_path, &csv_path)?;
+
+        Ok((sources, nrows))
+    }
+
+    /// The CSV tables whose names match a glob, which must all have
+    /// the same columns, along with those columns
+    fn glob_sources(&self, pattern: &str) -> anyhow::Result<(Vec<String>, ColumnPairs)> {
+        let matcher = Glob::new(pattern)?.compile_matcher();
+        let root = TableName::new(Vec::new(), self.data_dir.clone());
+        let sources: Vec<_> = self
+            .csv_tables_within(root, &mut Vec::new())?
+            .into_iter()
+            .filter(|id| matcher.is_match(id))
+            .collect();
+
+        let (first, rest) = match sources.split_first() {
+            Some(split) => split,
+            None => bail!("no tables match {:?}", pattern),
+        };
+        let expected = self.column_types_for_table(&self.table_path(first)?)?;
+        for source in rest {
+            let col_pairs = self.column_types_for_table(&self.table_path(source)?)?;
+            if col_pairs != expected {
+                bail!(
+                    "columns of {:?} ({:?}) don't match those of {:?} ({:?})",
+                    source,
+                    col_pairs,
+                    first,
+                    expected
+                );
+            }
+        }
+
+        Ok((sources, expected))
+    }
+
+    /// The rows of every table matching a glob, one table after another
+    fn open_glob(&self, pattern: &str) -> anyhow::Result<(ColumnPairs, RecordIter)> {
+        let (sources, col_pairs) = self.glob_sources(pattern)?;
+        let mut records: RecordIter = Box::new(std::iter::empty());
+        for source in sources {
+            let (_headers, source_records) = self.open_table(&self.table_path(&source)?)?;
+            records = Box::new(records.chain(source_records));
+        }
+
+        Ok((col_pairs, records))
+    }
+}
+
+/// Check that a record has one parseable value per column, returning it
+/// with any fractions in integer columns rounded as `float_to_int` says
+fn validate_record(
+    record: &StringRecord,
+    col_types: &[ColumnType],
+    float_to_int: FloatToInt,
+) -> anyhow::Result<StringRecord> {
+    if record.len() != col_types.len() {
+        bail!(
+            "expected {} columns, found {}",
+            col_types.len(),
+            record.len()
+        );
+    }
+
+    let mut validated = StringRecord::with_capacity(record.as_slice().len(), record.len());
+    for (val, typ) in record.iter().zip(col_types) {
+        let value = value_from_str(val, typ, float_to_int)
+            .with_context(|| format!("{:?} is not a valid {:?}", val, typ))?;
+        match typ {
+            ColumnType::Int | ColumnType::BigInt if val.parse::<i64>().is_err() => {
+                validated.push_field(&format_value(value))
+            }
+            _ => validated.push_field(val),
+        }
+    }
+
+    Ok(validated)
+}
+
+/// Format a row for writing to a table's CSV file,
+/// in the formats given by `CsvStore::write_formats`
+fn format_row_for_write(formats: &[Option<String>], row: Row) -> Vec<String> {
+    row.0
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let fmt = formats.get(i).and_then(Option::as_deref);
+            format_value_for_write(value, fmt)
+        })
+        .collect()
+}
+
+/// Format a value for writing to CSV, applying a chrono
+/// output format to dates, timestamps and ISO-8601 text if given.
+fn format_value_for_write(value: Value, output_format: Option<&str>) -> String {
+    // An empty cell, rather than the displayed "NULL", which is a valid string
+    if let Value::Null = value {
+        return String::new();
+    }
+
+    let fmt = match output_format {
+        Some(fmt) => fmt,
+        None => return format_value(value),
+    };
+
+    match value {
+        Value::Date(date) => date.format(fmt).to_string(),
+        Value::Timestamp(timestamp) => timestamp.format(fmt).to_string(),
+        Value::Time(time) => time.format(fmt).to_string(),
+        Value::Str(s) => {
+            if let Ok(date) = NaiveDate::parse_from_str(&s, DATE_FORMAT) {
+                date.format(fmt).to_string()
+            } else if let Ok(timestamp) = NaiveDateTime::parse_from_str(&s, TIMESTAMP_FORMAT) {
+                timestamp.format(fmt).to_string()
+            } else {
+                s
+            }
+        }
+        other => format_value(other),
+    }
+}
+
+/// Read a CSV field as a value of the given column type, with fractions
+/// in integer columns (e.g. `3.0`) rounded as `float_to_int` says
+fn value_from_str(val: &str, typ: &ColumnType, float_to_int: FloatToInt) -> anyhow::Result<Value> {
+    // NULLs are written as empty cells
+    if val.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    let res = match typ {
+        ColumnType::Int => Value::I32(int_from_str(val, float_to_int)?.try_into()?),
+        ColumnType::BigInt => Value::I64(int_from_str(val, float_to_int)?),
+        ColumnType::Float => Value::F64(val.parse()?),
+        ColumnType::Bool => match parse_bool(val) {
+            Some(x) => Value::Bool(x),
+            None => bail!("{:?} is not a valid {:?}", val, typ),
+        },
+        ColumnType::Date => Value::Date(NaiveDate::parse_from_str(val, DATE_FORMAT)?),
+        ColumnType::Time => Value::Time(NaiveTime::parse_from_str(val, TIME_FORMAT)?),
+        ColumnType::FormattedDate(fmt) => Value::Date(NaiveDate::parse_from_str(val, fmt)?),
+        ColumnType::FormattedTimestamp(fmt) => {
+            Value::Timestamp(NaiveDateTime::parse_from_str(val, fmt)?)
+        }
+        ColumnType::String => Value::Str(val.to_owned()),
+        ColumnType::Custom(data_type) => custom_value(val, data_type)?,
+    };
+
+    Ok(res)
+}
+
+/// An integer, or a number with a fraction rounded as `float_to_int` says
+fn int_from_str(val: &str, float_to_int: FloatToInt) -> anyhow::Result<i64> {
+    match val.parse() {
+        Ok(x) => Ok(x),
+        Err(err) => val
+            .parse()
+            .ok()
+            .and_then(|x| float_as_int(x, float_to_int))
+            .ok_or_else(|| err.into()),
+    }
+}
+
+/// Read text as a value of a custom column type. Some types (e.g. UUID)
+/// can't be cast from text, but can be read as a text literal.
+fn custom_value(val: &str, data_type: &DataType) -> anyhow::Result<Value> {
+    Value::Str(val.to_owned())
+        .cast(data_type)
+        .or_else(|_| {
+            Value::try_from_literal(
+                data_type,
+                &Literal::Text(std::borrow::Cow::Owned(val.to_owned())),
+            )
+        })
+        .map_err(|err| anyhow!("{}", err))
+}
+
+/// Convert a JSON field to a value of the given column type.
+/// Any scalar can be stored as text, but numbers must fit their column,
+/// with fractions going into integer columns as `float_to_int` says.
+fn value_from_json(
+    json: &serde_json::Value,
+    typ: &ColumnType,
+    float_to_int: FloatToInt,
+) -> anyhow::Result<Value> {
+    use serde_json::Value as Json;
+
+    let value = match (json, typ) {
+        (Json::Null, _) => Value::Null,
+        (Json::Number(n), ColumnType::Int | ColumnType::BigInt) => {
+            let int = match n.as_i64() {
+                Some(x) => Some(x),
+                None => n.as_f64().and_then(|x| float_as_int(x, float_to_int)),
+            };
+            match (int, typ) {
+                (Some(x), ColumnType::Int) => match i32::try_from(x) {
+                    Ok(x) => Value::I32(x),
+                    Err(_) => bail!("{} is not a valid {:?}", n, typ),
+                },
+                (Some(x), _) => Value::I64(x),
+                (None, _) => bail!("{} is not a valid {:?}", n, typ),
+            }
+        }
+        (Json::Number(n), ColumnType::Float) => match n.as_f64() {
+            Some(x) => Value::F64(x),
+            None => bail!("{} is not a valid {:?}", n, typ),
+        },
+        (Json::Bool(x), ColumnType::Bool) => Value::Bool(*x),
+        (Json::String(s), ColumnType::String) => Value::Str(s.clone()),
+        (Json::String(s), ColumnType::Date) => match NaiveDate::parse_from_str(s, DATE_FORMAT) {
+            Ok(date) => Value::Date(date),
+            Err(_) => bail!("{:?} is not a valid {:?}", s, typ),
+        },
+        (Json::String(s), ColumnType::Time) => match NaiveTime::parse_from_str(s, TIME_FORMAT) {
+            Ok(time) => Value::Time(time),
+            Err(_) => bail!("{:?} is not a valid {:?}", s, typ),
+        },
+        (Json::String(s), ColumnType::FormattedDate(fmt)) => {
+            match NaiveDate::parse_from_str(s, fmt)
+                .or_else(|_| NaiveDate::parse_from_str(s, DATE_FORMAT))
+            {
+                Ok(date) => Value::Date(date),
+                Err(_) => bail!("{:?} is not a valid {:?}", s, typ),
+            }
+        }
+        (Json::String(s), ColumnType::FormattedTimestamp(fmt)) => {
+            match NaiveDateTime::parse_from_str(s, fmt)
+                .or_else(|_| NaiveDateTime::parse_from_str(s, TIMESTAMP_FORMAT))
+            {
+                Ok(timestamp) => Value::Timestamp(timestamp),
+                Err(_) => bail!("{:?} is not a valid {:?}", s, typ),
+            }
+        }
+        (Json::Number(_) | Json::Bool(_), ColumnType::String) => Value::Str(json.to_string()),
+        (Json::String(s), ColumnType::Custom(data_type)) => custom_value(s, data_type)?,
+        (other, typ) => bail!("{} is not a valid {:?}", other, typ),
+    };
+
+    Ok(value)
+}
+
+/// A float as an `i64`, rounded according to `float_to_int`,
+/// if it's in range
+fn float_as_int(x: f64, float_to_int: FloatToInt) -> Option<i64> {
+    let whole = match float_to_int {
+        FloatToInt::Error if x.fract() != 0.0 => return None,
+        FloatToInt::Error => x,
+        FloatToInt::Truncate => x.trunc(),
+        FloatToInt::Round => x.round(),
+    };
+
+    // i64::MAX rounds up to 2^63 as a float, which is out of range
+    (i64::MIN as f64..i64::MAX as f64)
+        .contains(&whole)
+        .then_some(whole as i64)
+}
+
+fn get_i32_key(key: &Key) -> anyhow::Result<i32> {
+    match *key {
+        Key::I32(x) => Ok(x),
+        _ => bail!("non-i32 key {:?}", key),
+    }
+}
+
+/// Identifies a version of a table's file and schema file,
+/// by their sizes and modification times
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    csv: (u64, SystemTime),
+    schema_file: Option<(u64, SystemTime)>,
+}
+
+impl FileStamp {
+    fn of(csv_path: &Path, schema_path: &Path) -> anyhow::Result<Self> {
+        let stamp = |path: &Path| -> std::io::Result<(u64, SystemTime)> {
+            let metadata = std::fs::metadata(path)?;
+            Ok((metadata.len(), metadata.modified()?))
+        };
+        Ok(Self {
+            csv: stamp(csv_path).with_context(|| format!("reading metadata of {:?}", csv_path))?,
+            schema_file: stamp(schema_path).ok(),
+        })
+    }
+}
+
+/// For a headerless table read by a reader which took its first line as
+/// a header row, that line as the first record, and column names
+/// `col1..colN` as when reading CSV without headers
+fn first_line_as_record(first: Vec<String>, records: RecordIter) -> (Vec<String>, RecordIter) {
+    let names = (1..=first.len()).map(|i| format!("col{}", i)).collect();
+    let first = (!first.is_empty()).then(|| Ok(StringRecord::from(first)));
+    (names, Box::new(first.into_iter().chain(records)))
+}
+
+/// A table's column names and types
+#[derive(Clone)]
+struct InferredColumns {
+    col_pairs: Vec<(String, ColumnType)>,
+    /// Whether each column can be NULL
+    nullable: Vec<bool>,
+    /// How many records the table has, if inference read all of them
+    rows: Option<usize>,
+}
+
+/// A table's inferred columns, as of a version of its file
+struct CachedColumns {
+    stamp: FileStamp,
+    columns: InferredColumns,
+}
+
+/// A sibling of `path` to write its new contents to, which is then
+/// renamed over it, so that readers never see a partly written file
+fn temp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.to_owned().into_os_string();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// Where the record after byte `pos` of a CSV file starts,
+/// past any blank lines and comment lines, as the CSV reader skips them
+fn record_start(bytes: &[u8], mut pos: usize, comment: Option<u8>) -> usize {
+    loop {
+        match bytes.get(pos) {
+            Some(b'\r' | b'\n') => pos += 1,
+            Some(&c) if Some(c) == comment => {
+                pos = match bytes[pos..].iter().position(|&b| b == b'\n') {
+                    Some(i) => pos + i + 1,
+                    None => bytes.len(),
+                };
+            }
+            _ => return pos,
+        }
+    }
+}
+
+/// `bytes` with each range replaced by its new contents.
+/// Ranges may repeat, but mustn't otherwise overlap.
+fn splice(bytes: &[u8], mut replacements: Vec<(Range<usize>, Vec<u8>)>) -> Vec<u8> {
+    replacements.sort_by_key(|(range, _new)| range.start);
+    replacements.dedup_by_key(|(range, _new)| range.start);
+
+    let mut spliced = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    for (range, new) in replacements {
+        spliced.extend_from_slice(&bytes[pos..range.start]);
+        spliced.extend_from_slice(&new);
+        pos = range.end;
+    }
+    spliced.extend_from_slice(&bytes[pos..]);
+
+    spliced
+}
+
+/// Line number of the row with the given key,
+/// after `header_lines` lines of headers
+fn get_row_num(key: &Key, header_lines: usize) -> anyhow::Result<usize> {
+    match get_i32_key(key) {
+        Ok(i) => match usize::try_from(i) {
+            Ok(row_num) => Ok(row_num + header_lines),
+            Err(_err) => bail!("Invalid row number {}", i),
+        },
+        Err(err) => Err(err),
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for CsvStore {
+    async fn fetch_schema(&self, table_name: &str) -> GlueResult<Option<Schema>> {
+        if let Some(pending) = self.pending_table(table_name) {
+            return Ok(pending.map(|table| self.pending_schema(table_name, table)));
+        }
+
+        self.read_table_schema(table_name)
+            .context("reading schema")
+            .to_glue_err()
+    }
+
+    async fn fetch_data(&self, table_name: &str, key: &Key) -> GlueResult<Option<Row>> {
+        log::trace!("fetch_data {:?} {:?}", table_name, key);
+
+        if let Some(pending) = self.pending_table(table_name) {
+            let index = get_i32_key(key).to_glue_err()?;
+            let row = pending.and_then(|table| {
+                let row = usize::try_from(index)
+                    .ok()
+                    .and_then(|i| table.rows.get(i).cloned())?;
+                Some(self.pending_row(table_name, table, row))
+            });
+            return row.map(|row| self.with_rowid(key, row?)).transpose();
+        }
+
+        // Number of rows to skip
+        let nskip = get_row_num(key, self.header_lines()).to_glue_err()?;
+
+        let (col_pairs, records) = self.open_records(table_name).to_glue_err()?;
+        let (headers, col_types): (Vec<_>, Vec<_>) = col_pairs.into_iter().unzip();
+        let col_names = self.schema_column_names(&headers);
+        let computed = self.computed_columns(table_name);
+
+        // Skip first n records
+        let mut records = records.skip(nskip);
+
+        records
+            .next()
+            .map(|res| {
+                let record = res.context("reading csv record").to_glue_err()?;
+                let row = read_csv_record(&record, &col_types, self.float_to_int).to_glue_err()?;
+                let row = append_computed(&col_names, row, computed)?;
+                self.with_rowid(key, row)
+            })
+            .transpose()
+    }
+
+    async fn scan_data(&self, table_name: &str) -> GlueResult<RowIter> {
+        if let Some(pending) = self.pending_table(table_name) {
+            let rows = match pending {
+                Some(table) => table
+                    .rows
+                    .iter()
+                    .map(|row| self.pending_row(table_name, table, row.clone()))
+                    .collect::<GlueResult<_>>()?,
+                None => Vec::new(),
+            };
+            let iter = rows.into_iter().enumerate().map(|(i, row)| {
+                let key = Key::I32(i.try_into().expect("failed to convert key to i32"));
+                Ok((key, row))
+            });
+            return Ok(self.with_rowids(Box::new(iter)));
+        }
+
+        let (col_pairs, records) = self.open_records(table_name).to_glue_err()?;
+        let computed = self.computed_columns(table_name).to_vec();
+        // Computed columns may depend on columns the query doesn't mention
+        let needed = match computed.is_empty() {
+            true => self.needed_columns(&col_pairs),
+            false => None,
+        };
+        let (headers, col_types): (Vec<_>, Vec<_>) = col_pairs.into_iter().unzip();
+        let col_names = self.schema_column_names(&headers);
+
+        let quarantine = self.quarantine.clone();
+        let table_name = table_name.to_owned();
+        let header_lines = self.header_lines();
+        let float_to_int = self.float_to_int;
+
+        // Loop over rows
+        let unboxed_iter = records.enumerate().filter_map(move |(i, res)| {
+            let key = Key::I32(i.try_into().expect("failed to convert key to i32"));
+            let record = match res.context("reading csv record").to_glue_err() {
+                Ok(record) => record,
+                Err(err) => return Some(Err(err)),
+            };
+            let row = match &needed {
+                Some(needed) => {
+                    read_csv_record_projected(&record, &col_types, needed, float_to_int)
+                }
+                None => read_csv_record(&record, &col_types, float_to_int),
+            };
+            let row = match (row, &quarantine) {
+                (Ok(row), _) => row,
+                // Skip the row, keeping the keys of the rest
+                (Err(err), Some(quarantine)) => {
+                    let line = record
+                        .position()
+                        .map_or((i + header_lines + 1) as u64, csv::Position::line);
+                    return quarantine
+                        .divert(&table_name, line, &record, &err)
+                        .to_glue_err()
+                        .err()
+                        .map(Err);
+                }
+                (Err(err), None) => return Some(Err(err).to_glue_err()),
+            };
+            Some(append_computed(&col_names, row, &computed).map(|row| (key, row)))
+        });
+
+        let iter: RowIter = match self.scan_limit {
+            Some(limit) => Box::new(unboxed_iter.take(limit)),
+            None => Box::new(unboxed_iter),
+        };
+
+        Ok(self.with_rowids(iter))
+    }
+}
+
+impl CsvStore {
+    /// Read a table's schema from disk, if it exists,
+    /// including any computed columns
+    fn read_table_schema(&self, table_name: &str) -> anyhow::Result<Option<Schema>> {
+        let mut schema = self.read_stored_schema(table_name)?;
+        if let Some(schema) = schema.as_mut() {
+            let computed = self.computed_columns(table_name);
+            schema
+                .column_defs
+                .extend(computed.iter().map(Computed::column_def));
+            if self.rowid_column {
+                schema.column_defs.push(ColumnDef {
+                    name: ROWID_COLUMN.to_string(),
+                    data_type: DataType::Int32,
+                    options: Vec::new(),
+                });
+            }
+        }
+
+        Ok(schema)
+    }
+
+    /// A table's column names and types, as `SHOW COLUMNS` gives them
+    pub fn describe_table(&self, table_name: &str) -> anyhow::Result<Vec<(String, DataType)>> {
+        let schema = match self.pending_table(table_name) {
+            Some(pending) => pending.map(|table| self.pending_schema(table_name, table)),
+            None => self.read_table_schema(table_name)?,
+        };
+        let schema = schema.ok_or_else(|| TableNotFound(table_name.to_string()))?;
+
+        Ok(schema
+            .column_defs
+            .into_iter()
+            .map(|col| (col.name, col.data_type))
+            .collect())
+    }
+
+    /// Append a row's key as `ROWID_COLUMN`, if it's enabled
+    fn with_rowid(&self, key: &Key, row: Row) -> GlueResult<Row> {
+        if !self.rowid_column {
+            return Ok(row);
+        }
+
+        let Row(mut values) = row;
+        values.push(Value::I32(get_i32_key(key).to_glue_err()?));
+        Ok(Row(values))
+    }
+
+    fn with_rowids(&self, iter: RowIter) -> RowIter {
+        if !self.rowid_column {
+            return iter;
+        }
+
+        Box::new(iter.map(|res| {
+            let (key, Row(mut values)) = res?;
+            values.push(Value::I32(get_i32_key(&key).to_glue_err()?));
+            Ok((key, Row(values)))
+        }))
+    }
+
+    /// The columns configured to be computed from each row of a table
+    fn computed_columns(&self, table_name: &str) -> &[Computed] {
+        self.computed
+            .get(table_name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// A pending table's schema, including any computed columns
+    fn pending_schema(&self, table_name: &str, table: &OverlayTable) -> Schema {
+        let mut schema = table.schema.clone();
+        let computed = self.computed_columns(table_name);
+        schema
+            .column_defs
+            .extend(computed.iter().map(Computed::column_def));
+        schema
+    }
+
+    /// A pending table's row, with the values of any computed columns
+    fn pending_row(&self, table_name: &str, table: &OverlayTable, row: Row) -> GlueResult<Row> {
+        let computed = self.computed_columns(table_name);
+        if computed.is_empty() {
+            return Ok(row);
+        }
+        let names: Vec<_> = table
+            .schema
+            .column_defs
+            .iter()
+            .map(|col| col.name.clone())
+            .collect();
+        append_computed(&names, row, computed)
+    }
+
+    /// Rows to write without the values of the table's computed columns,
+    /// which aren't stored. Rows being updated are given with their keys.
+    /// Fails if a computed column is set to anything but NULL, as when an
+    /// INSERT leaves it out, what it's computed as, or its previous value.
+    async fn stored_rows(
+        &self,
+        table_name: &str,
+        rows: Vec<(Option<Key>, Row)>,
+    ) -> anyhow::Result<Vec<Row>> {
+        let computed = self.computed_columns(table_name);
+        if computed.is_empty() {
+            return Ok(rows.into_iter().map(|(_key, row)| row).collect());
+        }
+        let schema = match self.pending_table(table_name) {
+            Some(pending) => pending.map(|table| table.schema.clone()),
+            None => self.read_stored_schema(table_name)?,
+        };
+        let names: Vec<_> = schema
+            .ok_or_else(|| TableNotFound(table_name.to_string()))?
+            .column_defs
+            .into_iter()
+            .map(|col| col.name)
+            .collect();
+
+        // The computed values of the rows being updated, before the update
+        let mut previous: HashMap<_, _> = rows
+            .iter()
+            .filter_map(|(key, _row)| key.clone().map(|key| (key, None)))
+            .collect();
+        if !previous.is_empty() {
+            let scanned = self
+                .scan_data(table_name)
+                .await
+                .map_err(|err| anyhow!("{}", err))?;
+            for res in scanned {
+                let (key, Row(mut values)) = res.map_err(|err| anyhow!("{}", err))?;
+                if let Some(before) = previous.get_mut(&key) {
+                    *before = Some(values.split_off(names.len()));
+                }
+            }
+        }
+
+        rows.into_iter()
+            .map(|(key, Row(mut values))| {
+                let given = values.split_off(names.len().min(values.len()));
+                let stored = Row(values);
+                let Row(mut recomputed) = append_computed(&names, stored.clone(), computed)
+                    .map_err(|err| anyhow!("{}", err))?;
+                let recomputed = recomputed.split_off(names.len());
+                let before = key.and_then(|key| previous.get(&key)?.as_ref());
+                for (i, (column, value)) in computed.iter().zip(&given).enumerate() {
+                    let unchanged = matches!(value, Value::Null)
+                        || Some(value) == recomputed.get(i)
+                        || before.and_then(|before| before.get(i)) == Some(value);
+                    if !unchanged {
+                        bail!("column {:?} is computed, so it can't be set", column.name());
+                    }
+                }
+                Ok(stored)
+            })
+            .collect()
+    }
+
+    /// Read the schema of a table's stored columns, if it exists
+    fn read_stored_schema(&self, table_name: &str) -> anyhow::Result<Option<Schema>> {
+        if let Some((fw_path, spec)) = self.fixed_width_table(table_name)? {
+            let schema = self.read_fixed_width_schema(table_name, &fw_path, spec)?;
+            return Ok(Some(schema));
+        }
+        if let Some(column) = self.partitions.get(table_name) {
+            let (col_pairs, _records) = self.open_partitioned(table_name, column)?;
+            return Ok(Some(self.build_schema(table_name.to_owned(), col_pairs)));
+        }
+        // Checking the matching tables have the same columns while planning
+        if let Some(pattern) = glob_table::pattern(table_name) {
+            let (_sources, col_pairs) = self.glob_sources(pattern)?;
+            return Ok(Some(self.build_schema(table_name.to_owned(), col_pairs)));
+        }
+        if let Some((dir, manifest)) = self.manifest_table(table_name)? {
+            let (col_pairs, _records) = self.open_manifest(&dir, &manifest)?;
+            return Ok(Some(self.build_schema(table_name.to_owned(), col_pairs)));
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(path) = self.encrypted_table(table_name)? {
+            let (col_pairs, _records) = self.open_encrypted(&path)?;
+            return Ok(Some(self.build_schema(table_name.to_owned(), col_pairs)));
+        }
+
+        let path = self
+            .table_path(table_name)
+            .context("convert table id to path")?;
+        if path.as_csv().exists() {
+            Ok(Some(self.read_schema(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// In overlay mode, the pending state of a table if it's been modified.
+    /// `Some(None)` means the table was dropped.
+    fn pending_table(&self, table_name: &str) -> Option<Option<&OverlayTable>> {
+        self.overlay
+            .as_ref()
+            .and_then(|overlay| overlay.get(table_name))
+    }
+
+    /// In overlay mode, the pending contents of a table,
+    /// loaded from disk when it's first modified.
+    fn pending_table_mut(&mut self, table_name: &str) -> anyhow::Result<Option<&mut OverlayTable>> {
+        let loaded = match &self.overlay {
+            None => return Ok(None),
+            Some(overlay) if overlay.get(table_name).is_some() => None,
+            Some(_) => Some(self.load_table(table_name)?),
+        };
+
+        let overlay = self.overlay.as_mut().expect("overlay mode");
+        if let Some(table) = loaded {
+            overlay.set(table_name.to_string(), Some(table));
+        }
+
+        match overlay.get_mut(table_name) {
+            Some(Some(table)) => Ok(Some(table)),
+            _ => bail!("table {:?} has been dropped", table_name),
+        }
+    }
+
+    /// Read a table's schema and all of its rows into memory
+    fn load_table(&self, table_name: &str) -> anyhow::Result<OverlayTable> {
+        // Computed columns are appended when the rows are read
+        let schema = self
+            .read_stored_schema(table_name)?
+            .ok_or_else(|| TableNotFound(table_name.to_string()))?;
+        let (col_pairs, records) = self.open_records(table_name)?;
+        let col_types: Vec<_> = col_pairs.into_iter().map(|(_name, typ)| typ).collect();
+
+        let rows = records
+            .map(|res| {
+                let record = res.context("reading csv record")?;
+                read_csv_record(&record, &col_types, self.float_to_int)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(OverlayTable { schema, rows })
+    }
+
+    /// Number of tables with edits waiting to be flushed
+    pub fn pending_edits(&self) -> usize {
+        self.overlay.as_ref().map_or(0, Overlay::len)
+    }
+
+    /// Write all pending overlay edits to disk,
+    /// returning the number of tables written.
+    /// Each table is written to a temporary file first, and the edits
+    /// are only discarded once every table has been replaced.
+    pub fn flush(&mut self) -> anyhow::Result<usize> {
+        let overlay = match &self.overlay {
+            Some(overlay) => overlay,
+            None => bail!("not in overlay mode"),
+        };
+
+        let mut written = Vec::new();
+        let mut dropped = Vec::new();
+        let staged = overlay.iter().try_for_each(|(table_name, table)| {
+            let csv_path = self.table_path(table_name)?.as_csv();
+            match table {
+                None => dropped.push(csv_path),
+                Some(table) => {
+                    let tmp_path = self
+                        .write_pending_table(table_name, table, &csv_path)
+                        .with_context(|| format!("writing {:?}", table_name))?;
+                    written.push((tmp_path, csv_path));
+                }
+            }
+            anyhow::Ok(())
+        });
+        if let Err(err) = staged {
+            for (tmp_path, _csv_path) in &written {
+                let _ = std::fs::remove_file(tmp_path);
+            }
+            return Err(err);
+        }
+
+        let csv_paths: Vec<_> = written
+            .iter()
+            .map(|(_tmp_path, csv_path)| csv_path.clone())
+            .chain(dropped.iter().cloned())
+            .collect();
+        let txn = self.begin_mutation(&csv_paths)?;
+        for (tmp_path, csv_path) in &written {
+            self.replace_file(tmp_path, csv_path)?;
+        }
+        for csv_path in dropped {
+            if csv_path.exists() {
+                std::fs::remove_file(csv_path)?;
+            }
+        }
+        txn.commit()?;
+
+        let overlay = self.overlay.as_mut().expect("overlay mode");
+        Ok(overlay.take().len())
+    }
+
+    /// Write a table's pending contents to a temporary file
+    /// beside `csv_path`, returning the temporary file's path
+    fn write_pending_table(
+        &self,
+        table_name: &str,
+        table: &OverlayTable,
+        csv_path: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let mut headers: Vec<_> = table
+            .schema
+            .column_defs
+            .iter()
+            .map(|col| col.name.clone())
+            .collect();
+        let mut trailing_empty = false;
+        if csv_path.exists() {
+            let path = self.table_path(table_name)?;
+            // Write back the original headers the names were made from
+            if self.sanitize_column_names {
+                let raw = self.read_headers(&path)?;
+                if column_identifiers(&raw) == headers {
+                    headers = raw;
+                }
+            }
+            trailing_empty = self.writes_trailing_empty_column(&path)?;
+        }
+        if let Some(parent) = csv_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let formats = self.write_formats(table_name, &headers)?;
+        let tmp_path = temp_path(csv_path);
+        let mut writer = self.file_writer(csv_path).from_path(&tmp_path)?;
+        if self.has_headers {
+            if trailing_empty {
+                headers.push(String::new());
+            }
+            writer.write_record(&headers)?;
+        }
+        for row in &table.rows {
+            let mut values = format_row_for_write(&formats, row.clone());
+            if trailing_empty {
+                values.push(String::new());
+            }
+            writer.write_record(values)?;
+        }
+        writer.flush()?;
+
+        Ok(tmp_path)
+    }
+
+    /// Byte ranges of the records of a table's file, its contents,
+    /// in order, each including its line ending. Multi-line quoted fields
+    /// are within their record's range; comment and blank lines aren't.
+    fn record_spans(&self, csv_path: &Path, bytes: &[u8]) -> anyhow::Result<Vec<Range<usize>>> {
+        if self.lenient_parsing {
+            let mut spans = lenient::line_spans(bytes);
+            if self.has_headers && !spans.is_empty() {
+                spans.remove(0);
+            }
+            return Ok(spans);
+        }
+
+        let mut reader = self
+            .file_reader(csv_path)
+            .has_headers(self.has_headers)
+            .comment(self.comment)
+            .from_reader(bytes);
+        let mut end = match self.has_headers {
+            true => {
+                reader.byte_headers()?;
+                reader.position().byte() as usize
+            }
+            false => 0,
+        };
+        let mut spans = Vec::new();
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            // A record's own position includes the lines skipped before it
+            let start = record_start(bytes, end, self.comment);
+            end = reader.position().byte() as usize;
+            // The reader stops after the `\r` of a `\r\n`
+            if bytes[..end].ends_with(b"\r") && bytes.get(end) == Some(&b'\n') {
+                end += 1;
+            }
+            spans.push(start..end);
+        }
+
+        Ok(spans)
+    }
+
+    /// Byte ranges of the rows with these keys in a table's file,
+    /// its contents, in the same order as the keys (see `record_spans`)
+    fn row_spans(
+        &self,
+        csv_path: &Path,
+        bytes: &[u8],
+        keys: &[Key],
+    ) -> anyhow::Result<Vec<Range<usize>>> {
+        let spans = self.record_spans(csv_path, bytes)?;
+        keys.iter()
+            .map(|key| {
+                let row_num = get_row_num(key, 0)?;
+                spans
+                    .get(row_num)
+                    .cloned()
+                    .with_context(|| format!("no row with key {}", row_num))
+            })
+            .collect()
+    }
+
+    /// Rename a fully written temporary file (see `temp_path`) over `path`.
+    /// With `durable_writes`, the file is synced to disk first,
+    /// and then its directory, so the rename itself survives a crash.
+    fn replace_file(&self, tmp_path: &Path, path: &Path) -> anyhow::Result<()> {
+        if self.durable_writes {
+            File::open(tmp_path)?.sync_all()?;
+        }
+        std::fs::rename(tmp_path, path)
+            .with_context(|| format!("renaming {:?} to {:?}", tmp_path, path))?;
+        // Directories can't be opened (and so synced) like this on Windows
+        #[cfg(unix)]
+        if self.durable_writes {
+            if let Some(dir) = path.parent() {
+                File::open(dir)?.sync_all()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record that `paths` are about to be modified, if there's a write-ahead log
+    fn begin_mutation(&self, paths: &[PathBuf]) -> anyhow::Result<Transaction> {
+        let mut cache = self.cached_columns();
+        for path in paths {
+            cache.remove(path);
+        }
+        drop(cache);
+
+        match &self.wal {
+            Some(wal) => wal.begin(paths),
+            None => Ok(Transaction::none()),
+        }
+    }
+
+    /// Roll back mutations left incomplete in the write-ahead log
+    /// (e.g. by a crash), returning how many there were
+    pub fn recover(&self) -> anyhow::Result<usize> {
+        Wal::new(&self.data_dir).recover()
+    }
+
+    /// Drop all pending overlay edits, returning the number of tables affected.
+    pub fn discard(&mut self) -> anyhow::Result<usize> {
+        match self.overlay.as_mut() {
+            Some(overlay) => Ok(overlay.take().len()),
+            None => bail!("not in overlay mode"),
+        }
+    }
+
+    /// Determine a table's column names and types,
+    /// and open an iterator over its records
+    fn open_records(
+        &self,
+        table_name: &str,
+    ) -> anyhow::Result<(Vec<(String, ColumnType)>, RecordIter)> {
+        if let Some((fw_path, spec)) = self.fixed_width_table(table_name)? {
+            let col_pairs = get_column_types_for_fixed_width(
+                &fw_path,
+                spec,
+                &self.type_detectors,
+                &self.date_formats,
+                self.inference_sample_rows,
+            )
+            .context("getting column types")?;
+            let records = fixed_width::read_records(&fw_path, spec)?;
+
+            return Ok((col_pairs, Box::new(records)));
+        }
+        if let Some(column) = self.partitions.get(table_name) {
+            return self.open_partitioned(table_name, column);
+        }
+        if let Some(pattern) = glob_table::pattern(table_name) {
+            return self.open_glob(pattern);
+        }
+        if let Some((dir, manifest)) = self.manifest_table(table_name)? {
+            return self.open_manifest(&dir, &manifest);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(path) = self.encrypted_table(table_name)? {
+            return self.open_encrypted(&path);
+        }
+
+        let path = self.table_path(table_name).context("table id -> path")?;
+
+        let col_pairs = self
+            .column_types_for_table(&path)
+            .context("getting column types")?;
+
+        let (_headers, records) = self.open_table(&path).context("opening csv reader")?;
+
+        Ok((col_pairs, records))
+    }
+
+    /// The raw headers of a CSV table's columns, keyed by the names its
+    /// schema gives them, where they differ (see `sanitize_column_names`)
+    pub fn original_column_names(
+        &self,
+        table_name: &str,
+    ) -> anyhow::Result<Option<HashMap<String, String>>> {
+        if !self.sanitize_column_names {
+            return Ok(None);
+        }
+        let path = self.table_path(table_name)?;
+        if !path.as_csv().exists() {
+            return Ok(None);
+        }
+
+        let headers = self.read_headers(&path)?;
+        let names = column_identifiers(&headers)
+            .into_iter()
+            .zip(headers)
+            .filter(|(name, header)| name != header)
+            .collect();
+
+        Ok(Some(names))
+    }
+
+    /// Columns to display first in results selected from a table
+    pub fn display_order(&self, table_name: &str) -> Option<&[String]> {
+        self.display_order.get(table_name).map(Vec::as_slice)
+    }
+
+    /// Names to show a table's columns by, keyed by column name
+    pub fn column_aliases(&self, table_name: &str) -> Option<&HashMap<String, String>> {
+        self.column_aliases.get(table_name)
+    }
+
+    /// Named queries which can be selected from like tables
+    pub fn views(&self) -> &Views {
+        &self.views
+    }
+
+    /// Results of simple aggregate queries, if they're cached
+    pub fn aggregate_cache(&self) -> Option<&AggregateCache> {
+        self.aggregate_cache.as_ref()
+    }
+
+    /// The key to cache a statement's result under, if aggregates are
+    /// cached and it's a simple aggregate of a plain CSV table
+    pub fn aggregate_cache_key(&self, statement: &Statement) -> anyhow::Result<Option<CacheKey>> {
+        let table_name = match aggregate_cache::aggregated_table(statement) {
+            Some(table_name) if self.aggregate_cache.is_some() => table_name,
+            _ => return Ok(None),
+        };
+        if self.pending_table(table_name).is_some()
+            || glob_table::pattern(table_name).is_some()
+            || self.fixed_width.contains_key(table_name)
+            || self.partitions.contains_key(table_name)
+            || self.computed.contains_key(table_name)
+            || self.manifest_table(table_name)?.is_some()
+        {
+            return Ok(None);
+        }
+        let path = self.table_path(table_name)?;
+        let csv_path = path.as_csv();
+        if !csv_path.exists() {
+            return Ok(None);
+        }
+
+        // Taken before the query runs, so a result computed from a file
+        // which changes meanwhile is cached under its old version
+        let stamp = FileStamp::of(&csv_path, &path.as_schema_file())?;
+        // Settings which change how the file is read change the result
+        let shape = format!(
+            "{:?} {:?} {:?} comment={:?} has_headers={} lenient={} float_to_int={:?} quarantine={}",
+            csv_path,
+            statement,
+            self.column_types_for_table(&path)?,
+            self.comment,
+            self.has_headers,
+            self.lenient_parsing,
+            self.float_to_int,
+            self.quarantine.is_some(),
+        );
+        Ok(Some(CacheKey { shape, stamp }))
+    }
+
+    /// Stop scans after this many rows, for a query which needs no more.
+    /// Cleared with `None`.
+    pub fn set_scan_limit(&mut self, limit: Option<usize>) {
+        self.scan_limit = limit;
+    }
+
+    /// Set bounds on partition column values implied by the statement about
+    /// to run (see `partition::where_bounds`), used to skip partitions which
+    /// can't contain matching rows. `None` clears them.
+    pub fn set_partition_bounds(&mut self, statement: Option<&Statement>) {
+        self.partition_bounds = match statement {
+            Some(statement) => partition::where_bounds(statement, &self.partitions),
+            None => HashMap::new(),
+        };
+    }
+
+    /// Partitions of a partitioned table, as (file stem, path) pairs
+    /// sorted by stem, including only those within the current bounds
+    /// on `column`. Also returns the first partition overall, if any.
+    fn partitions_in_bounds(
+        &self,
+        table_name: &str,
+        column: &str,
+    ) -> anyhow::Result<(Vec<Partition>, Option<TablePath>)> {
+        let dir = self.table_path(table_name)?.as_dir();
+        let mut parts = Vec::new();
+
+        for entry_res in std::fs::read_dir(&dir).with_context(|| format!("reading {:?}", dir))? {
+            let entry = entry_res?;
+            let path = entry.path();
+            let fname = match self.file_name(&entry)? {
+                Some(fname) => fname,
+                None => continue,
+            };
+
+            let ext = match path.extension() {
+                Some(ext) if self.is_table_extension(ext) => ext.to_string_lossy().into_owned(),
+                _ => continue,
+            };
+            if self.should_ignore(&fname) {
+                continue;
+            }
+            let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or(&fname);
+            let stem = stem.to_owned();
+            let part_path = TablePath::try_new(path.with_extension(""), self.data_dir.clone())?;
+            parts.push((stem, part_path.with_file_extension(&ext)));
+        }
+        parts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let first = parts.first().map(|(_stem, path)| path.clone());
+        if let Some(bounds) = self.partition_bounds.get(&column.to_lowercase()) {
+            parts.retain(|(stem, _path)| bounds.contains(stem));
+        }
+
+        Ok((parts, first))
+    }
+
+    /// Headers of the given partitions, which must all agree,
+    /// and their records, each ending with its partition's stem
+    fn partition_records(
+        &self,
+        parts: &[Partition],
+        first: &TablePath,
+    ) -> anyhow::Result<(Vec<String>, RecordIter)> {
+        let headers = self.read_headers(first)?;
+        let mut iters = Vec::new();
+
+        for (stem, path) in parts {
+            let (part_headers, records) = self.open_table(path)?;
+            if part_headers != headers {
+                bail!(
+                    "columns of partition {:?} ({:?}) don't match {:?}",
+                    stem,
+                    part_headers,
+                    headers
+                );
+            }
+            let stem = stem.clone();
+            iters.push(records.map(move |res| {
+                res.map(|mut record| {
+                    record.push_field(&stem);
+                    record
+                })
+            }));
+        }
+
+        Ok((headers, Box::new(iters.into_iter().flatten())))
+    }
+
+    /// Open the partitions of a table within the current bounds,
+    /// as if they were a single table with an extra `column`
+    fn open_partitioned(
+        &self,
+        table_name: &str,
+        column: &str,
+    ) -> anyhow::Result<(Vec<(String, ColumnType)>, RecordIter)> {
+        let (parts, first) = self.partitions_in_bounds(table_name, column)?;
+        let first = first.with_context(|| format!("no partitions of {:?}", table_name))?;
+
+        let (mut headers, records) = self.partition_records(&parts, &first)?;
+        if headers.iter().any(|header| header == column) {
+            bail!("partition column {:?} is already a column", column);
+        }
+        headers.push(column.to_owned());
+
+        let mut col_types = determine_column_types(
+            records,
+            headers.len(),
+            &self.type_detectors,
+            &self.date_formats,
+            self.inference_sample_rows,
+        )?;
+        if let Some(last) = col_types.last_mut() {
+            *last = ColumnType::String;
+        }
+        let col_pairs = headers.into_iter().zip(col_types).collect();
+
+        let (_headers, records) = self.partition_records(&parts, &first)?;
+
+        Ok((col_pairs, records))
+    }
+
+    /// The directory of a table split into parts, and the manifest
+    /// listing them, if the table is one (see `manifest::FILE_NAME`)
+    fn manifest_table(&self, table_name: &str) -> anyhow::Result<Option<(PathBuf, Manifest)>> {
+        if glob_table::pattern(table_name).is_some() {
+            return Ok(None);
+        }
+        let dir = self.table_path(table_name)?.as_dir();
+        Ok(Manifest::find(&dir)?.map(|manifest| (dir, manifest)))
+    }
+
+    /// Open the parts of a table listed in its manifest,
+    /// as if they were a single table
+    fn open_manifest(
+        &self,
+        dir: &Path,
+        manifest: &Manifest,
+    ) -> anyhow::Result<(Vec<(String, ColumnType)>, RecordIter)> {
+        let (headers, records) = manifest.open(dir, self.delimiter)?;
+        let col_types = determine_column_types(
+            records,
+            headers.len(),
+            &self.type_detectors,
+            &self.date_formats,
+            self.inference_sample_rows,
+        )?;
+        let col_pairs = headers.into_iter().zip(col_types).collect();
+
+        let (_headers, records) = manifest.open(dir, self.delimiter)?;
+
+        Ok((col_pairs, records))
+    }
+
+    /// The age-encrypted file holding a table, if there is one
+    #[cfg(feature = "encryption")]
+    fn encrypted_table(&self, table_name: &str) -> anyhow::Result<Option<PathBuf>> {
+        if glob_table::pattern(table_name).is_some() {
+            return Ok(None);
+        }
+        let mut path = self.table_path(table_name)?.as_dir().into_os_string();
+        path.push(encrypted::SUFFIX);
+        let path = PathBuf::from(path);
+
+        Ok(path.is_file().then_some(path))
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn encrypted_table(&self, _table_name: &str) -> anyhow::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Decrypt a table in memory, inferring its column types
+    #[cfg(feature = "encryption")]
+    fn open_encrypted(
+        &self,
+        path: &Path,
+    ) -> anyhow::Result<(Vec<(String, ColumnType)>, RecordIter)> {
+        let identity_file = self.age_identity_file.as_deref();
+        let (headers, records) =
+            encrypted::open(path, identity_file, self.has_headers, self.delimiter)?;
+        let col_types = determine_column_types(
+            records,
+            headers.len(),
+            &self.type_detectors,
+            &self.date_formats,
+            self.inference_sample_rows,
+        )?;
+        let col_pairs = headers.into_iter().zip(col_types).collect();
+
+        let (_headers, records) =
+            encrypted::open(path, identity_file, self.has_headers, self.delimiter)?;
+
+        Ok((col_pairs, records))
+    }
+
+    /// Run type inference over values as if they were a column of a table,
+    /// with this store's type detectors and date formats
+    pub fn infer_type(&self, values: &[String]) -> InferredType {
+        infer_type(values, &self.type_detectors, &self.date_formats)
+    }
+
+    /// The number of rows diverted to the quarantine file since this was
+    /// last called, and the file, if any were
+    pub fn take_quarantined(&self) -> Option<(usize, &Path)> {
+        let quarantine = self.quarantine.as_ref()?;
+        match quarantine.take_count() {
+            0 => None,
+            n => Some((n, quarantine.path())),
+        }
+    }
+
+    /// Expose each row's key as a trailing `ROWID_COLUMN`.
+    /// Must only be enabled for read-only statements.
+    pub fn set_rowid_column(&mut self, enabled: bool) {
+        self.rowid_column = enabled;
+    }
+
+    /// Hint that the statement about to run only uses columns whose
+    /// names appear in `query`, so scans can skip parsing other columns.
+    /// Must only be set for read-only statements. Cleared with `None`.
+    pub fn set_projection_hint(&mut self, columns: Option<HashSet<String>>) {
+        self.projection_hint = columns;
+    }
+
+    /// Which columns need to be parsed, according to the projection hint.
+    /// Columns are matched by the names queries use, not their headers.
+    /// If the hint names anything else, such as an alias or a column of
+    /// another table, it can't be relied on, so every column is parsed.
+    fn needed_columns(&self, col_pairs: &[(String, ColumnType)]) -> Option<Vec<bool>> {
+        let referenced = self.projection_hint.as_ref()?;
+        let headers: Vec<_> = col_pairs.iter().map(|(name, _typ)| name.clone()).collect();
+        let names: Vec<_> = self
+            .schema_column_names(&headers)
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        if !referenced.iter().all(|column| names.contains(column)) {
+            return None;
+        }
+
+        Some(names.iter().map(|name| referenced.contains(name)).collect())
+    }
+}
+
+fn read_csv_record(
+    record: &StringRecord,
+    col_types: &[ColumnType],
+    float_to_int: FloatToInt,
+) -> anyhow::Result<Row> {
+    // Loop over records in the row
+    let rec_it = record.into_iter();
+
+    let row_vec: Vec<_> = rec_it
+        .zip(col_types)
+        .map(|(s, typ)| value_from_str(s, typ, float_to_int))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .context("reading csv value (types are inferred from inference_sample_rows rows)")?;
+
+    Ok(Row(row_vec))
+}
+
+/// Write records, flushing after every `batch_size` of them (and at the end).
+/// On failure, report how many records had been successfully flushed.
+fn write_batched<W: Write>(
+    writer: &mut csv::Writer<W>,
+    records: impl Iterator<Item = Vec<String>>,
+    batch_size: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut flushed = 0;
+    let mut written = 0;
+
+    for values in records {
+        writer
+            .write_record(values)
+            .with_context(|| format!("write failed after {} rows", flushed))?;
+        written += 1;
+
+        if batch_size.is_some_and(|size| written - flushed >= size) {
+            writer
+                .flush()
+                .with_context(|| format!("write failed after {} rows", flushed))?;
+            flushed = written;
+        }
+    }
+
+    writer
+        .flush()
+        .with_context(|| format!("write failed after {} rows", flushed))?;
+
+    Ok(())
+}
+
+/// Like `read_csv_record`, but only parse the columns where `needed`
+/// is true, leaving `Value::Null` placeholders in the rest.
+fn read_csv_record_projected(
+    record: &StringRecord,
+    col_types: &[ColumnType],
+    needed: &[bool],
+    float_to_int: FloatToInt,
+) -> anyhow::Result<Row> {
+    let row_vec: Vec<_> = record
+        .iter()
+        .zip(col_types.iter().zip(needed))
+        .map(|(s, (typ, &needed))| {
+            if needed {
+                value_from_str(s, typ, float_to_int)
+            } else {
+                Ok(Value::Null)
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .context("reading csv value (types are inferred from inference_sample_rows rows)")?;
+
+    Ok(Row(row_vec))
+}
+
+trait IntoMutResult<T, U> {
+    fn into_mut_result(self, t: T) -> MutResult<T, U>;
+}
+
+impl<T, U> IntoMutResult<T, U> for Result<U, GlueError> {
+    fn into_mut_result(self, t: T) -> MutResult<T, U> {
+        match self {
+            Ok(val) => Ok((t, val)),
+            Err(err) => Err((t, err)),
+        }
+    }
+}
+
+trait ToGlueError {
+    type Target;
+
+    fn to_glue_err(self) -> GlueResult<Self::Target>;
+}
+
+impl<T> ToGlueError for anyhow::Result<T> {
+    type Target = T;
+
+    fn to_glue_err(self) -> GlueResult<Self::Target> {
+        self.map_err(|err| GlueError::StorageMsg(err.to_string()))
+    }
+}
+
+impl CsvStore {
+    async fn insert_schema(&mut self, schema: &Schema) -> anyhow::Result<()> {
+        self.check_writable(&schema.table_name)?;
+
+        if let Some(overlay) = self.overlay.as_mut() {
+            let table = OverlayTable {
+                schema: schema.clone(),
+                rows: Vec::new(),
+            };
+            overlay.set(schema.table_name.clone(), Some(table));
+            return Ok(());
+        }
+
+        let path = self.table_path(&schema.table_name)?;
+        let headers = schema.column_defs.iter().map(|col| col.name.clone());
+        let csv_path = path.as_csv();
+        if let Some(parent) = csv_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let txn = self.begin_mutation(std::slice::from_ref(&csv_path))?;
+        let mut writer = self.file_writer(&csv_path).from_path(csv_path)?;
+
+        if self.has_headers {
+            writer.write_record(headers)?;
+        }
+        writer.flush()?;
+
+        txn.commit()
+    }
+
+    async fn delete_schema(&mut self, table_name: &str) -> anyhow::Result<()> {
+        log::trace!("delete_schema {:?}", table_name);
+
+        self.check_writable(table_name)?;
+
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.set(table_name.to_string(), None);
+            return Ok(());
+        }
+
+        let path = self.table_path(table_name)?;
+        let csv_path = path.as_csv();
+        let schema_path = path.as_schema_file();
+        let mut paths = vec![csv_path.clone()];
+        if schema_path.exists() {
+            paths.push(schema_path);
+        }
+        let txn = self.begin_mutation(&paths)?;
+        for path in paths {
+            std::fs::remove_file(path)?;
+        }
+
+        txn.commit()
+    }
+
+    async fn append_data(&mut self, table_name: &str, rows: Vec<Row>) -> anyhow::Result<()> {
+        log::trace!("append_data {:?}: {} rows", table_name, rows.len());
+
+        self.check_writable(table_name)?;
+        let rows = rows.into_iter().map(|row| (None, row)).collect();
+        let rows = self.stored_rows(table_name, rows).await?;
+
+        if let Some(table) = self.pending_table_mut(table_name)? {
+            table.rows.extend(rows);
+            return Ok(());
+        }
+
+        let path = self.table_path(table_name)?;
+        let headers = self.read_headers(&path)?;
+        let formats = self.write_formats(table_name, &headers)?;
+        let trailing_empty = self.writes_trailing_empty_column(&path)?;
+        let txn = self.begin_mutation(&[path.as_csv()])?;
+        let file = OpenOptions::new().append(true).open(path.as_csv())?;
+        let mut writer = self.file_writer(&path.as_csv()).from_writer(file);
+
+        let records = rows.into_iter().map(|row| {
+            let mut values = format_row_for_write(&formats, row);
+            if trailing_empty {
+                values.push(String::new());
+            }
+            values
+        });
+        write_batched(&mut writer, records, self.write_batch_size)
+            .with_context(|| format!("appending to {}", table_name))?;
+        if self.durable_writes {
+            let file = writer.into_inner()?;
+            file.sync_all()?;
+        }
+
+        txn.commit()
+    }
+
+    async fn insert_data(&mut self, table_name: &str, rows: Vec<(Key, Row)>) -> anyhow::Result<()> {
+        log::trace!("insert_data {:?}: {} rows", table_name, rows.len());
+
+        self.check_writable(table_name)?;
+        let keys: Vec<_> = rows.iter().map(|(key, _row)| key.clone()).collect();
+        let with_keys = rows
+            .into_iter()
+            .map(|(key, row)| (Some(key), row))
+            .collect();
+        let rows: Vec<_> = keys
+            .into_iter()
+            .zip(self.stored_rows(table_name, with_keys).await?)
+            .collect();
+
+        if let Some(table) = self.pending_table_mut(table_name)? {
+            for (key, row) in rows {
+                let index = usize::try_from(get_i32_key(&key)?)?;
+                match table.rows.get_mut(index) {
+                    Some(existing) => *existing = row,
+                    None => bail!("no row with key {}", index),
+                }
+            }
+            return Ok(());
+        }
+
+        let path = self.table_path(table_name)?;
+        let csv_path = path.as_csv();
+        let bytes = std::fs::read(&csv_path).with_context(|| format!("reading {:?}", csv_path))?;
+
+        let keys: Vec<_> = rows.iter().map(|(key, _row)| key.clone()).collect();
+        let spans = self.row_spans(&csv_path, &bytes, &keys)?;
+
+        let headers = self.read_headers(&path)?;
+        let formats = self.write_formats(table_name, &headers)?;
+        let trailing_empty = self.writes_trailing_empty_column(&path)?;
+        let mut replacements = Vec::new();
+        for (span, (_key, row)) in spans.into_iter().zip(rows) {
+            let mut values = format_row_for_write(&formats, row);
+            if trailing_empty {
+                values.push(String::new());
+            }
+            let mut writer = self.file_writer(&csv_path).from_writer(Vec::new());
+            writer.write_record(values)?;
+            let mut new = writer.into_inner()?;
+            // The last row may have no line ending
+            if !bytes[span.clone()].ends_with(b"\n") {
+                new.pop();
+            }
+            replacements.push((span, new));
+        }
+
+        // Replace the original file, so it's never left half-written
+        let tmp_path = temp_path(&csv_path);
+        std::fs::write(&tmp_path, splice(&bytes, replacements))
+            .with_context(|| format!("writing {:?}", tmp_path))?;
+        let txn = self.begin_mutation(std::slice::from_ref(&csv_path))?;
+        self.replace_file(&tmp_path, &csv_path)?;
+
+        txn.commit()
+    }
+
+    async fn delete_data(&mut self, table_name: &str, keys: Vec<Key>) -> anyhow::Result<()> {
+        log::trace!("delete_data {:?}: {} rows", table_name, keys.len());
+
+        self.check_writable(table_name)?;
+
+        if let Some(table) = self.pending_table_mut(table_name)? {
+            let mut indices: Vec<_> = keys
+                .iter()
+                .map(|key| Ok(usize::try_from(get_i32_key(key)?)?))
+                .collect::<anyhow::Result<_>>()?;
+            // Remove from the end so earlier indices stay valid
+            indices.sort_unstable();
+            indices.dedup();
+            for index in indices.into_iter().rev() {
+                if index < table.rows.len() {
+                    table.rows.remove(index);
+                }
+            }
+            return Ok(());
+        }
+
+        let path = self.table_path(table_name)?;
+        let csv_path = path.as_csv();
+        let bytes = std::fs::read(&csv_path).with_context(|| format!("reading {:?}", csv_path))?;
+
+        let tombstone = match self.delete_mode {
+            DeleteMode::Remove => None,
+            DeleteMode::Tombstone => self.comment,
+        };
+        let replacements = self
+            .row_spans(&csv_path, &bytes, &keys)?
+            .into_iter()
+            .map(|span| {
+                let mut new = Vec::new();
+                // Every line of the row, as a quoted field may span several
+                if let Some(comment) = tombstone {
+                    for line in bytes[span.clone()].split_inclusive(|&b| b == b'\n') {
+                        new.push(comment);
+                        new.extend_from_slice(line);
+                    }
+                }
+                (span, new)
+            })
+            .collect();
+
+        let tmp_path = temp_path(&csv_path);
+        std::fs::write(&tmp_path, splice(&bytes, replacements))
+            .with_context(|| format!("writing {:?}", tmp_path))?;
+
+        let txn = self.begin_mutation(std::slice::from_ref(&csv_path))?;
+        self.replace_file(&tmp_path, &csv_path)?;
+
+        txn.commit()
+    }
+}
+
+#[async_trait(?Send)]
+impl StoreMut for CsvStore {
+    async fn insert_schema(self, schema: &Schema) -> MutResult<Self, ()> {
+        let mut storage = self;
+        CsvStore::insert_schema(&mut storage, schema)
+            .await
+            .to_glue_err()
+            .into_mut_result(storage)
+    }
+
+    async fn delete_schema(self, table_name: &str) -> MutResult<Self, ()> {
+        let mut storage = self;
+        CsvStore::delete_schema(&mut storage, table_name)
+            .await
+            .to_glue_err()
+            .into_mut_result(storage)
+    }
+
+    async fn append_data(self, table_name: &str, rows: Vec<Row>) -> MutResult<Self, ()> {
+        let mut storage = self;
+        CsvStore::append_data(&mut storage, table_name, rows)
+            .await
+            .to_glue_err()
+            .into_mut_result(storage)
+    }
+
+    async fn insert_data(self, table_name: &str, rows: Vec<(Key, Row)>) -> MutResult<Self, ()> {
+        let mut storage = self;
+        CsvStore::insert_data(&mut storage, table_name, rows)
+            .await
+            .to_glue_err()
+            .into_mut_result(storage)
+    }
+
+    async fn delete_data(self, table_name: &str, keys: Vec<Key>) -> MutResult<Self, ()> {
+        let mut storage = self;
+        CsvStore::delete_data(&mut storage, table_name, keys)
+            .await
+            .to_glue_err()
+            .into_mut_result(storage)
+    }
+}
+
+impl GStore for CsvStore {}
+impl GStoreMut for CsvStore {}
+
+#[cfg(test)]
+mod tests {
+    use gluesql::prelude::{Glue, Payload};
+
+    use super::*;
+    use crate::config::{ComputedColumn, FixedWidthColumn};
+
+    /// Create a store over a temporary data directory containing `files`,
+    /// given as (relative path, contents) pairs.
+    fn store_with_files(files: &[(&str, &str)], config: Config) -> (tempdir::TempDir, CsvStore) {
+        let tmpdir = tempdir::TempDir::new("feet-test").expect("tmpdir");
+        for (rel_path, contents) in files {
+            let path = tmpdir.path().join(rel_path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).expect("create parent dir");
+            }
+            std::fs::write(path, contents).expect("write test file");
+        }
+
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
+            ..config
+        };
+        let store = CsvStore::try_new(config).expect("CsvStore::try_new");
+
+        (tmpdir, store)
+    }
+
+    async fn scan_rows(store: &CsvStore, table_name: &str) -> Vec<Row> {
+        store
+            .scan_data(table_name)
+            .await
+            .expect("scan_data")
+            .map(|res| res.expect("row").1)
+            .collect()
+    }
+
+    /// The rows selected by a query, which must be a single SELECT
+    async fn select_rows(glue: &mut Glue<CsvStore>, sql: &str) -> Vec<Vec<Value>> {
+        let payloads = glue.execute_async(sql).await.expect("query");
+        match <[_; 1]>::try_from(payloads) {
+            Ok([Payload::Select { rows, .. }]) => rows,
+            other => panic!("expected a single SELECT, found {:?}", other),
+        }
+    }
+
+    /// The rows selected by a query of a store holding `files`
+    async fn query_rows(files: &[(&str, &str)], config: Config, sql: &str) -> Vec<Vec<Value>> {
+        let (_tmpdir, store) = store_with_files(files, config);
+        select_rows(&mut Glue::new(store), sql).await
+    }
+
+    fn source_file(contents: &str) -> (tempdir::TempDir, PathBuf) {
+        let tmpdir = tempdir::TempDir::new("feet-source").expect("tmpdir");
+        let path = tmpdir.path().join("source.csv");
+        std::fs::write(&path, contents).expect("write source file");
+        (tmpdir, path)
+    }
+
+    #[tokio::test]
+    async fn test_import_columns_from() {
+        let (_tmpdir, store) =
+            store_with_files(&[("people.csv", "name,age\nann,30\n")], Config::default());
+        let (_srcdir, source) = source_file("bob,41\ncat,25\n");
+
+        let imported = store
+            .import_table(&source, "more_people", Some("people"), None, None)
+            .expect("import");
+        assert_eq!(imported.rows, 2);
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name FROM more_people WHERE age > 30").await,
+            vec![vec![Value::Str("bob".to_string())]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_with_schema_file() {
+        let (tmpdir, store) = store_with_files(&[], Config::default());
+        let (_schemadir, schema_path) = source_file(
+            "columns = [{ name = \"code\", type = \"TEXT\" }, { name = \"score\", type = \"FLOAT\" }]\n",
+        );
+
+        for (table, contents) in [("a", "code,score\n007,2.5\n"), ("b", "code,score\n42,3\n")] {
+            let (_srcdir, source) = source_file(contents);
+            store
+                .import_table(&source, table, None, None, Some(&schema_path))
+                .expect("import");
+        }
+        assert!(tmpdir.path().join("b.schema.toml").exists());
+
+        let (_srcdir, source) = source_file("code,score\nx,high\n");
+        store
+            .import_table(&source, "c", None, None, Some(&schema_path))
+            .expect_err("score isn't a float");
+
+        let mut glue = Glue::new(store);
+        for (table, row) in [
+            ("a", vec![Value::Str("007".to_string()), Value::F64(2.5)]),
+            ("b", vec![Value::Str("42".to_string()), Value::F64(3.0)]),
+        ] {
+            let payloads = glue
+                .execute_async(format!("SELECT code, score FROM {}", table).as_str())
+                .await
+                .expect("query");
+            assert_eq!(
+                payloads,
+                vec![Payload::Select {
+                    labels: vec!["code".to_string(), "score".to_string()],
+                    rows: vec![row],
+                }]
+            );
+        }
+
+        glue.execute_async("DROP TABLE b").await.expect("drop");
+        assert!(!tmpdir.path().join("b.schema.toml").exists());
+    }
+
+    #[test]
+    fn test_import_detects_header() {
+        let (tmpdir, store) = store_with_files(&[], Config::default());
+
+        let (_srcdir, source) = source_file("name,age\nbob,41\ncat,25\n");
+        let imported = store
+            .import_table(&source, "headered", None, Some(ImportHeaders::Auto), None)
+            .expect("import");
+        assert_eq!(
+            imported,
+            Imported {
+                rows: 2,
+                header: true
+            }
+        );
+        let contents = std::fs::read_to_string(tmpdir.path().join("headered.csv")).expect("read");
+        assert_eq!(contents, "name,age\nbob,41\ncat,25\n");
+
+        let (_srcdir, source) = source_file("bob,41\ncat,25\n");
+        let imported = store
+            .import_table(&source, "headerless", None, Some(ImportHeaders::Auto), None)
+            .expect("import");
+        assert_eq!(
+            imported,
+            Imported {
+                rows: 2,
+                header: false
+            }
+        );
+        let contents = std::fs::read_to_string(tmpdir.path().join("headerless.csv")).expect("read");
+        assert_eq!(contents, "col1,col2\nbob,41\ncat,25\n");
+    }
+
+    #[test]
+    fn test_replace_table() {
+        let (tmpdir, store) =
+            store_with_files(&[("t.csv", "id,score\n1,2.5\n2,3.5\n")], Config::default());
+        let read = || std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+
+        // Integers fit a float column
+        let (_srcdir, source) = source_file("id,score\n3,4\n");
+        let nrows = store.replace_table(&source, "t", false).expect("replace");
+        assert_eq!(nrows, 1);
+        assert_eq!(read(), "id,score\n3,4\n");
+
+        let (_srcdir, source) = source_file("id,note\n4,four\n");
+        assert!(store.replace_table(&source, "t", false).is_err());
+        assert_eq!(read(), "id,score\n3,4\n");
+
+        store
+            .replace_table(&source, "t", true)
+            .expect("replace with schema change");
+        assert_eq!(read(), "id,note\n4,four\n");
+        assert!(store.replace_table(&source, "missing", true).is_err());
+    }
+
+    #[test]
+    fn test_import_columns_from_mismatch() {
+        let (_tmpdir, store) =
+            store_with_files(&[("people.csv", "name,age\nann,30\n")], Config::default());
+
+        let (_srcdir, source) = source_file("bob,41,extra\n");
+        let err = store
+            .import_table(&source, "more_people", Some("people"), None, None)
+            .expect_err("column count mismatch");
+        assert!(format!("{:#}", err).contains("expected 2 columns, found 3"));
+
+        let (_srcdir, source) = source_file("bob,old\n");
+        assert!(store
+            .import_table(&source, "more_people", Some("people"), None, None)
+            .is_err());
+        assert!(!store.data_dir.join("more_people.csv").exists());
+    }
+
+    #[tokio::test]
+    async fn test_output_format_on_insert() {
+        let mut formats = HashMap::new();
+        formats.insert("day".to_string(), "%m/%d/%Y".to_string());
+        let mut config = Config::default();
+        config.output_formats.insert("events".to_string(), formats);
+
+        let (_tmpdir, store) =
+            store_with_files(&[("events.csv", "name,day\nlaunch,01/02/2023\n")], config);
+        let csv_path = store.data_dir.join("events.csv");
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO events VALUES ('party', '2023-03-04')")
+            .await
+            .expect("insert");
+
+        let contents = std::fs::read_to_string(csv_path).expect("read table");
+        assert_eq!(contents, "name,day\nlaunch,01/02/2023\nparty,03/04/2023\n");
+    }
+
+    #[test]
+    fn test_load_csv() {
+        let (_tmpdir, store) =
+            store_with_files(&[("t.csv", "id,name\n1,ann\n")], Config::default());
+        let csv_path = store.data_dir.join("t.csv");
+
+        let input = "id,name\n2,bob\n3,\"carol, jr\"\n";
+        let nrows = store
+            .load_csv("t", input.as_bytes(), ImportHeaders::Auto)
+            .expect("load");
+        assert_eq!(nrows, 2);
+        assert_eq!(store.count_rows("t").expect("count"), 3);
+
+        let nrows = store
+            .load_csv("t", "4,dan\n".as_bytes(), ImportHeaders::Auto)
+            .expect("load headerless");
+        assert_eq!(nrows, 1);
+
+        let before = std::fs::read_to_string(&csv_path).expect("read");
+        let err = store
+            .load_csv("t", "5,eve\nsix,fay\n".as_bytes(), ImportHeaders::No)
+            .expect_err("bad id");
+        assert!(format!("{:#}", err).contains("row 2"), "{:#}", err);
+        assert_eq!(std::fs::read_to_string(&csv_path).expect("read"), before);
+        assert!(store
+            .load_csv("t", "7\n".as_bytes(), ImportHeaders::No)
+            .is_err());
+        assert_eq!(store.count_rows("t").expect("count"), 4);
+    }
+
+    #[tokio::test]
+    async fn test_date_formats() {
+        let config = Config {
+            date_formats: vec!["%d/%m/%Y".to_string(), "%m/%d/%Y %H:%M".to_string()],
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(
+            &[(
+                "events.csv",
+                "name,day,at\nlaunch,15/01/2023,01/15/2023 09:30\nparty,01/02/2023,\nsale,28/02/2023,\n",
+            )],
+            config,
+        );
+        let csv_path = store.data_dir.join("events.csv");
+        let types = store.describe_table("events").expect("describe");
+        assert_eq!(types[1], ("day".to_string(), DataType::Date));
+        assert_eq!(types[2], ("at".to_string(), DataType::Timestamp));
+
+        let mut glue = Glue::new(store);
+        // 01/02/2023 is read with the first format, as 1 February
+        let payloads = glue
+            .execute_async(
+                "SELECT name FROM events WHERE day BETWEEN '2023-01-20' AND '2023-02-28' ORDER BY day",
+            )
+            .await
+            .expect("select");
+        match &payloads[..] {
+            [Payload::Select { rows, .. }] => assert_eq!(
+                rows,
+                &vec![
+                    vec![Value::Str("party".to_string())],
+                    vec![Value::Str("sale".to_string())],
+                ]
+            ),
+            other => panic!("unexpected payloads {:?}", other),
+        }
+
+        glue.execute_async("INSERT INTO events VALUES ('fair', '2023-03-04', NULL)")
+            .await
+            .expect("insert");
+        let contents = std::fs::read_to_string(csv_path).expect("read table");
+        assert!(contents.ends_with("fair,04/03/2023,\n"), "{}", contents);
+    }
+
+    #[tokio::test]
+    async fn test_fixed_width_table() {
+        let spec = FixedWidthSpec {
+            columns: vec![
+                FixedWidthColumn {
+                    name: "id".to_string(),
+                    start: 0,
+                    end: 3,
+                },
+                FixedWidthColumn {
+                    name: "name".to_string(),
+                    start: 3,
+                    end: 10,
+                },
+                FixedWidthColumn {
+                    name: "score".to_string(),
+                    start: 10,
+                    end: 15,
+                },
+            ],
+            skip_lines: 1,
+        };
+        let mut config = Config::default();
+        config.fixed_width.insert("legacy".to_string(), spec);
+
+        let contents = "ID NAME   SCORE\n  1alice   9.5\n  2bob    12.0\n";
+        let (_tmpdir, store) = store_with_files(&[("legacy.fw", contents)], config);
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name, score FROM legacy WHERE id = 2").await,
+            vec![vec![Value::Str("bob".to_string()), Value::F64(12.0)]]
+        );
+
+        let err = glue
+            .execute_async("DELETE FROM legacy")
+            .await
+            .expect_err("fixed-width tables are read-only");
+        assert!(format!("{:?}", err).contains("read-only"));
+    }
+
+    #[test]
+    fn test_list_tables_sorted() {
+        let names = ["m", "b", "z", "a", "q", "c", "y", "k"];
+        let mut files: Vec<_> = names
+            .iter()
+            .map(|name| (format!("{}.csv", name), "x\n1\n"))
+            .collect();
+        files.push(("k/inner.csv".to_string(), "x\n1\n"));
+        files.push(("d/inner.csv".to_string(), "x\n1\n"));
+        let files: Vec<_> = files.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+        let (_tmpdir, store) = store_with_files(&files, Config::default());
+
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let listed: Vec<_> = store
+            .list_tables(root)
+            .expect("list_tables")
+            .into_iter()
+            .map(|node| (node.name.last().expect("name"), node.is_table()))
+            .collect();
+
+        let expected = vec![
+            ("a", true),
+            ("b", true),
+            ("c", true),
+            ("d", false),
+            ("k", false),
+            ("k", true),
+            ("m", true),
+            ("q", true),
+            ("y", true),
+            ("z", true),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(name, is_table)| (name.to_string(), is_table))
+            .collect();
+        assert_eq!(listed, expected);
+    }
+
+    #[tokio::test]
+    async fn test_overlay_flush() {
+        let config = Config {
+            overlay: true,
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(&[("t.csv", "id,name\n1,ann\n")], config);
+        let csv_path = store.data_dir.join("t.csv");
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, 'bob')")
+            .await
+            .expect("insert");
+
+        // The pending row is visible to queries but not yet on disk
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name FROM t WHERE id = 2").await,
+            vec![vec![Value::Str("bob".to_string())]]
+        );
+        let contents = std::fs::read_to_string(&csv_path).expect("read table");
+        assert_eq!(contents, "id,name\n1,ann\n");
+
+        let mut store = glue.storage.expect("storage");
+        assert_eq!(store.pending_edits(), 1);
+        assert_eq!(store.flush().expect("flush"), 1);
+        assert_eq!(store.pending_edits(), 0);
+
+        let contents = std::fs::read_to_string(&csv_path).expect("read table");
+        assert_eq!(contents, "id,name\n1,ann\n2,bob\n");
+
+        // If any table can't be written, none are, and the edits are kept
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (3, 'cy')")
+            .await
+            .expect("insert");
+        glue.execute_async(r#"CREATE TABLE "sub/u" (n INTEGER)"#)
+            .await
+            .expect("create");
+        std::fs::write(csv_path.with_file_name("sub"), "not a directory").expect("write");
+        let mut store = glue.storage.expect("storage");
+        assert!(store.flush().is_err());
+        assert_eq!(store.pending_edits(), 2);
+        let contents = std::fs::read_to_string(&csv_path).expect("read table");
+        assert_eq!(contents, "id,name\n1,ann\n2,bob\n");
+        assert!(!temp_path(&csv_path).exists());
+    }
+
+    #[test]
+    fn test_header_line() {
+        let contents = "id,\"full name\",score\n1,ann,2.5\n2,bob,3\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        let line = store.header_line("t").expect("header_line");
+        assert_eq!(line, "id,full name,score");
+    }
+
+    #[tokio::test]
+    async fn test_regex_delimiter() {
+        let config = Config {
+            regex_delimiter: Some("::".to_string()),
+            ..Config::default()
+        };
+        let contents = "id::name::score\n1::ann::2.5\n2::bob, jr::3\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name, score FROM t WHERE id = 2").await,
+            vec![vec![Value::Str("bob, jr".to_string()), Value::F64(3.0)]]
+        );
+
+        assert!(glue.execute_async("DELETE FROM t").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delimiter() {
+        let config = Config {
+            delimiter: '\t',
+            ..Config::default()
+        };
+        let contents = "id\tname\tscore\n1\tann, jr\t2.5\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], config);
+        assert_eq!(
+            store
+                .column_types_for_table(&store.table_path("t").expect("path"))
+                .expect("types"),
+            vec![
+                ("id".to_string(), ColumnType::Int),
+                ("name".to_string(), ColumnType::String),
+                ("score".to_string(), ColumnType::Float),
+            ]
+        );
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, 'bob', 3)")
+            .await
+            .expect("insert");
+        glue.execute_async("UPDATE t SET name = 'ann' WHERE id = 1")
+            .await
+            .expect("update");
+        glue.execute_async("CREATE TABLE u (a INT, b TEXT)")
+            .await
+            .expect("create");
+        let read = |name: &str| std::fs::read_to_string(tmpdir.path().join(name)).expect("read");
+        assert_eq!(read("t.csv"), "id\tname\tscore\n1\tann\t2.5\n2\tbob\t3\n");
+        assert_eq!(read("u.csv"), "a\tb\n");
+
+        for delimiter in ['"', '\n', 'é'] {
+            let config = Config {
+                delimiter,
+                ..Config::default()
+            };
+            assert!(CsvStore::try_new(config).is_err(), "{:?}", delimiter);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_extensions() {
+        let config = Config {
+            extensions: vec!["tsv".to_string(), ".txt".to_string()],
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(
+            &[
+                ("t.tsv", "id\tname\n1\tann, jr\n"),
+                ("t.txt", "other\n"),
+                ("notes.txt", "a,b\n1,2\n"),
+            ],
+            config,
+        );
+        let root = TableIdentifier::new(String::new(), store.data_dir.clone());
+        let names: Vec<_> = store
+            .list_tables(root.try_into().expect("root"))
+            .expect("list")
+            .into_iter()
+            .map(|node| node.name.last().expect("name"))
+            .collect();
+        assert_eq!(names, vec!["notes", "t"]);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, 'bob')")
+            .await
+            .expect("insert");
+        glue.execute_async("UPDATE notes SET b = 3")
+            .await
+            .expect("update");
+        glue.execute_async("CREATE TABLE u (a INT)")
+            .await
+            .expect("create");
+        let read = |name: &str| std::fs::read_to_string(tmpdir.path().join(name)).expect("read");
+        assert_eq!(read("t.tsv"), "id\tname\n1\tann, jr\n2\tbob\n");
+        assert_eq!(read("t.txt"), "other\n");
+        assert_eq!(read("notes.txt"), "a,b\n1,3\n");
+        assert_eq!(read("u.tsv"), "a\n");
+    }
+
+    /// Accepts `capacity` bytes, then fails as if the disk were full
+    struct FullDisk {
+        capacity: usize,
+    }
+
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.capacity == 0 {
+                return Err(std::io::Error::other("no space left on device"));
+            }
+            let n = buf.len().min(self.capacity);
+            self.capacity -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_batched_partial_failure() {
+        // Each record is 4 bytes; room for 2.5 batches of 2
+        let records = (0..10).map(|i| vec![i.to_string(), "a".to_string()]);
+        let mut writer = csv::WriterBuilder::new().from_writer(FullDisk { capacity: 20 });
+
+        let err = write_batched(&mut writer, records, Some(2)).expect_err("should fail");
+        assert!(
+            format!("{:#}", err).contains("write failed after 4 rows"),
+            "{:#}",
+            err
+        );
+
+        // Without batching, nothing is known to have been written
+        let records = (0..10).map(|i| vec![i.to_string(), "a".to_string()]);
+        let mut writer = csv::WriterBuilder::new().from_writer(FullDisk { capacity: 20 });
+
+        let err = write_batched(&mut writer, records, None).expect_err("should fail");
+        assert!(format!("{:#}", err).contains("write failed after 0 rows"));
+    }
+
+    #[tokio::test]
+    async fn test_latest_table() {
+        let (tmpdir, store) = store_with_files(
+            &[
+                ("logs/a.csv", "n\n1\n"),
+                ("logs/b.csv", "n\n2\n"),
+                ("logs/c.csv", "n\n3\n"),
+            ],
+            Config::default(),
+        );
+
+        // Make b the newest, regardless of creation order
+        let now = std::time::SystemTime::now();
+        for (fname, age) in [("a.csv", 30), ("b.csv", 10), ("c.csv", 20)] {
+            let file = File::options()
+                .write(true)
+                .open(tmpdir.path().join("logs").join(fname))
+                .expect("open fixture");
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .expect("set mtime");
+        }
+
+        let dir = TableName::try_from(TableIdentifier::new(
+            "logs".to_string(),
+            store.data_dir.clone(),
+        ))
+        .expect("dir name");
+        let latest = store
+            .latest_table(dir)
+            .expect("latest")
+            .expect("some table");
+        let latest_id: TableIdentifier = latest.try_into().expect("table id");
+        assert_eq!(&*latest_id, "logs/b");
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, r#"SELECT n FROM "logs/@latest""#).await,
+            vec![vec![Value::I32(2)]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sanitize_column_names() {
+        let headers = [" \"First Name\" ", "2nd (alt)", "", "First_Name"].map(String::from);
+        assert_eq!(
+            column_identifiers(&headers),
+            vec!["First_Name", "_2nd_alt", "col3", "First_Name_2"]
+        );
+
+        let config = Config {
+            sanitize_column_names: true,
+            overlay: true,
+            ..Config::default()
+        };
+        let contents = "\"First Name\",age\nAda,36\n";
+        let (tmpdir, store) = store_with_files(&[("people.csv", contents)], config);
+        let names = store
+            .original_column_names("people")
+            .expect("original names");
+        assert_eq!(
+            names,
+            Some([("First_Name".to_string(), "First Name".to_string())].into())
+        );
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO people VALUES ('Bob', 40)")
+            .await
+            .expect("insert");
+        assert_eq!(
+            select_rows(&mut glue, "SELECT First_Name FROM people WHERE age > 36").await,
+            vec![vec![Value::Str("Bob".to_string())]]
+        );
+
+        let store = glue.storage.as_mut().expect("storage");
+        store.flush().expect("flush");
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("people.csv")).expect("read");
+        assert_eq!(on_disk, "First Name,age\nAda,36\nBob,40\n");
+    }
+
+    #[tokio::test]
+    async fn test_drop_trailing_empty_column() {
+        let config = Config {
+            drop_trailing_empty_column: true,
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("t.csv", "id,name,\n1,a,\n2,b,\n")], config);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (3, 'c')")
+            .await
+            .expect("insert");
+        glue.execute_async("UPDATE t SET name = 'B' WHERE id = 2")
+            .await
+            .expect("update");
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(on_disk, "id,name,\n1,a,\n2,B,\n3,c,\n");
+
+        let payloads = glue
+            .execute_async("SELECT * FROM t WHERE id = 3")
+            .await
+            .expect("select");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["id".to_string(), "name".to_string()],
+                rows: vec![vec![Value::I32(3), Value::Str("c".to_string())]],
+            }]
+        );
+
+        // Replacing the table keeps the trailing column, whether or not
+        // the source has it
+        let store = glue.storage.as_ref().expect("storage");
+        for source in ["id,name\n4,d\n", "id,name,\n4,d,\n"] {
+            let (_source_dir, source_path) = source_file(source);
+            store
+                .replace_table(&source_path, "t", false)
+                .expect("replace");
+            let on_disk = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+            assert_eq!(on_disk, "id,name,\n4,d,\n", "{:?}", source);
+        }
+
+        // So does flushing an overlay
+        let config = Config {
+            drop_trailing_empty_column: true,
+            overlay: true,
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("t.csv", "id,name,\n1,a,\n")], config);
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, 'b')")
+            .await
+            .expect("insert");
+        let mut store = glue.storage.expect("storage");
+        store.flush().expect("flush");
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(on_disk, "id,name,\n1,a,\n2,b,\n");
+    }
+
+    #[tokio::test]
+    async fn test_modeline() {
+        let config = Config {
+            modelines: true,
+            ..Config::default()
+        };
+        let contents = "# feet: delimiter=\\t\nid\tname\n1\tann, jr\n2\tbob\n";
+        let (_tmpdir, store) = store_with_files(&[("people.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name FROM people WHERE id = 1").await,
+            vec![vec![Value::Str("ann, jr".to_string())]]
+        );
+
+        assert!(glue
+            .execute_async("INSERT INTO people VALUES (3, 'cy')")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_export_all_json() {
+        let (tmpdir, store) = store_with_files(
+            &[
+                ("data/a.csv", "id,name\n1,x\n2,\n"),
+                ("data/sub/b.csv", "f\n1.5\n"),
+                ("other.csv", "n\n1\n"),
+            ],
+            Config::default(),
+        );
+        let out_dir = tmpdir.path().join("export");
+
+        let data = TableName::new(vec!["data".to_string()], store.data_dir.clone());
+        let written = store
+            .export_all(data, &out_dir, ExportFormat::Json)
+            .expect("export");
+        assert_eq!(
+            written,
+            vec![out_dir.join("a.json"), out_dir.join("sub/b.json")]
+        );
+
+        let read_json = |path: PathBuf| -> serde_json::Value {
+            let contents = std::fs::read_to_string(path).expect("read export");
+            serde_json::from_str(&contents).expect("parse export")
+        };
+        assert_eq!(
+            read_json(out_dir.join("a.json")),
+            serde_json::json!([{ "id": 1, "name": "x" }, { "id": 2, "name": null }])
+        );
+        assert_eq!(
+            read_json(out_dir.join("sub/b.json")),
+            serde_json::json!([{ "f": 1.5 }])
+        );
+        assert!(!out_dir.join("other.json").exists());
+    }
+
+    #[test]
+    fn test_max_concurrency() {
+        let files: Vec<_> = (0..8)
+            .map(|i| {
+                let rows: String = (0..i).map(|j| format!("{},{}\n", j, j * i)).collect();
+                (format!("d{}/t{}.csv", i % 3, i), format!("a,b\n{}", rows))
+            })
+            .collect();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect();
+
+        let run = |max_concurrency| {
+            let config = Config {
+                max_concurrency: Some(max_concurrency),
+                ..Config::default()
+            };
+            let (_tmpdir, store) = store_with_files(&files, config);
+            let root = TableName::new(Vec::new(), store.data_dir.clone());
+            let inventory = store.inventory(root.clone()).expect("inventory");
+
+            let out_dir = tempdir::TempDir::new("feet-export").expect("out dir");
+            let written = store
+                .export_all(root, out_dir.path(), ExportFormat::Csv)
+                .expect("export");
+            let exported: Vec<_> = written
+                .iter()
+                .map(|path| {
+                    let rel = path.strip_prefix(out_dir.path()).expect("relative");
+                    (rel.to_owned(), std::fs::read_to_string(path).expect("read"))
+                })
+                .collect();
+
+            let mut inventory = serde_json::to_value(inventory).expect("inventory json");
+            // Each run has its own files
+            for info in inventory.as_array_mut().expect("array") {
+                info["mtime"] = serde_json::Value::Null;
+            }
+            (inventory, exported)
+        };
+
+        let sequential = run(1);
+        assert_eq!(sequential.1.len(), 8);
+        assert_eq!(run(4), sequential);
+    }
+
+    #[tokio::test]
+    async fn test_type_detectors() {
+        let contents = "name,zip,n\nann,02139,1\nbob,10001,2\n";
+        let config = Config {
+            type_detectors: vec![TypeDetectorSpec {
+                pattern: r"\d{5}".to_string(),
+                data_type: "TEXT".to_string(),
+            }],
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(&[("people.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        let payloads = glue
+            .execute_async("SHOW COLUMNS FROM people")
+            .await
+            .expect("show columns");
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![
+                ("name".to_string(), DataType::Text),
+                ("zip".to_string(), DataType::Text),
+                // Patterns match whole values
+                ("n".to_string(), DataType::Int32),
+            ])]
+        );
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT zip FROM people WHERE n = 1").await,
+            vec![vec![Value::Str("02139".to_string())]]
+        );
+    }
+
+    #[test]
+    fn test_grep_table() {
+        let contents = "id,email\n1,ann@example.com\n2,bob@test.org\n3,cy@example.com\n";
+        let (_tmpdir, store) = store_with_files(&[("users.csv", contents)], Config::default());
+
+        let pattern = Regex::new(r"@example\.com$").expect("regex");
+        let mut out = Vec::new();
+        let nmatched = store
+            .grep_table("users", "email", &pattern, &mut out)
+            .expect("grep");
+
+        assert_eq!(nmatched, 2);
+        assert_eq!(
+            String::from_utf8(out).expect("utf-8"),
+            "id,email\n1,ann@example.com\n3,cy@example.com\n"
+        );
+        assert!(store
+            .grep_table("users", "name", &pattern, Vec::new())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_writable_paths() {
+        let config = Config {
+            writable_paths: vec!["scratch/**".to_string()],
+            ..Config::default()
+        };
+        let files = [("scratch/t.csv", "a\n1\n"), ("ref/t.csv", "a\n1\n")];
+        let (tmpdir, store) = store_with_files(&files, config);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async(r#"INSERT INTO "scratch/t" VALUES (2)"#)
+            .await
+            .expect("insert into writable table");
+        let scratch = std::fs::read_to_string(tmpdir.path().join("scratch/t.csv")).expect("read");
+        assert_eq!(scratch, "a\n1\n2\n");
+
+        let err = glue
+            .execute_async(r#"INSERT INTO "ref/t" VALUES (2)"#)
+            .await
+            .expect_err("insert into read-only table");
+        assert!(err.to_string().contains("writable_paths"), "{}", err);
+        let reference = std::fs::read_to_string(tmpdir.path().join("ref/t.csv")).expect("read");
+        assert_eq!(reference, "a\n1\n");
+
+        // Commands that create tables are checked too
+        let store = glue.storage.take().expect("store");
+        let (_srcdir, source) = source_file("a\n3\n");
+        assert!(store
+            .import_table(&source, "ref/u", None, None, None)
+            .is_err());
+        store
+            .import_table(&source, "scratch/u", None, None, None)
+            .expect("import into writable path");
+        assert!(store.merge_tables("scratch/*", "ref/merged").is_err());
+        assert!(!tmpdir.path().join("ref/merged.csv").exists());
+
+        let scratch = TableName::new(vec!["scratch".to_string()], store.data_dir.clone());
+        assert!(store
+            .export_all(scratch, &tmpdir.path().join("ref"), ExportFormat::Csv)
+            .is_err());
+        assert!(store
+            .check_writable_file(&tmpdir.path().join("ref/x.json"))
+            .is_err());
+        store
+            .check_writable_file(&tmpdir.path().join("scratch/x.json"))
+            .expect("writable path");
+    }
+
+    #[tokio::test]
+    async fn test_computed_columns() {
+        let config = Config {
+            computed_columns: [(
+                "orders".to_string(),
+                vec![ComputedColumn {
+                    name: "total".to_string(),
+                    expr: "qty * price".to_string(),
+                    data_type: "FLOAT".to_string(),
+                }],
+            )]
+            .into(),
+            ..Config::default()
+        };
+        let contents = "item,qty,price\napple,3,0.5\npear,2,1.25\n";
+        let (tmpdir, store) = store_with_files(&[("orders.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT item, total FROM orders WHERE total > 2").await,
+            vec![vec![Value::Str("pear".to_string()), Value::F64(2.5)]]
+        );
+
+        // Writes set the stored columns, and the computed ones follow them
+        glue.execute_async("INSERT INTO orders (item, qty, price) VALUES ('fig', 4, 0.5)")
+            .await
+            .expect("insert");
+        glue.execute_async("UPDATE orders SET qty = 5 WHERE item = 'apple'")
+            .await
+            .expect("update");
+        glue.execute_async("DELETE FROM orders WHERE item = 'pear'")
+            .await
+            .expect("delete");
+        let err = glue
+            .execute_async("UPDATE orders SET total = 1")
+            .await
+            .expect_err("computed columns can't be set");
+        assert!(err.to_string().contains("computed"), "{}", err);
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("orders.csv")).expect("read");
+        assert_eq!(on_disk, "item,qty,price\napple,5,0.5\nfig,4,0.5\n");
+        assert_eq!(
+            select_rows(&mut glue, "SELECT item, total FROM orders").await,
+            vec![
+                vec![Value::Str("apple".to_string()), Value::F64(2.5)],
+                vec![Value::Str("fig".to_string()), Value::F64(2.0)],
+            ]
+        );
+
+        // Pending rows are computed from too
+        let mut store = glue.storage.expect("storage");
+        store.overlay = Some(Overlay::default());
+        let mut glue = Glue::new(store);
+        glue.execute_async("UPDATE orders SET qty = 1 WHERE item = 'fig'")
+            .await
+            .expect("update pending");
+        assert_eq!(
+            select_rows(&mut glue, "SELECT total FROM orders WHERE item = 'fig'").await,
+            vec![vec![Value::F64(0.5)]]
+        );
+        glue.storage.expect("storage").flush().expect("flush");
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("orders.csv")).expect("read");
+        assert_eq!(on_disk, "item,qty,price\napple,5,0.5\nfig,1,0.5\n");
+
+        // Expressions use the names queries do, not the raw headers
+        let config = Config {
+            computed_columns: [(
+                "orders".to_string(),
+                vec![ComputedColumn {
+                    name: "total".to_string(),
+                    expr: "qty * Unit_Price".to_string(),
+                    data_type: "FLOAT".to_string(),
+                }],
+            )]
+            .into(),
+            sanitize_column_names: true,
+            ..Config::default()
+        };
+        let contents = "item,qty,Unit Price\napple,3,0.5\n";
+        let files = [("orders.csv", contents)];
+        assert_eq!(
+            query_rows(&files, config, "SELECT total FROM orders").await,
+            vec![vec![Value::F64(1.5)]]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filename_policy() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let list = |policy| {
+            let config = Config {
+                filename_policy: policy,
+                ..Config::default()
+            };
+            let (tmpdir, store) = store_with_files(&[("good.csv", "n\n1\n")], config);
+            let bad_name = OsStr::from_bytes(b"bad\xff.csv");
+            std::fs::write(tmpdir.path().join(bad_name), "n\n2\n").expect("write");
+
+            let root = TableName::new(Vec::new(), store.data_dir.clone());
+            store.list_tables(root).map(|nodes| {
+                nodes
+                    .into_iter()
+                    .map(|node| node.name.last().expect("name"))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert!(list(FilenamePolicy::Strict).is_err());
+        assert_eq!(list(FilenamePolicy::Skip).expect("skip"), vec!["good"]);
+        assert_eq!(
+            list(FilenamePolicy::Lossy).expect("lossy"),
+            vec!["bad\u{FFFD}", "good"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_null() {
+        let config = Config {
+            infer_not_null: false,
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("t.csv", "id,note\n1,first\n")], config);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, NULL)")
+            .await
+            .expect("insert");
+        glue.execute_async("UPDATE t SET note = NULL WHERE id = 1")
+            .await
+            .expect("update");
+
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,note\n1,\n2,\n");
+
+        let payloads = glue
+            .execute_async("SELECT note FROM t WHERE note IS NULL")
+            .await
+            .expect("select");
+        match &payloads[..] {
+            [Payload::Select { rows, .. }] => {
+                assert_eq!(rows.len(), 2);
+                assert!(rows.iter().all(|row| matches!(row[..], [Value::Null])));
+            }
+            other => panic!("unexpected payloads {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_infer_not_null() {
+        let (_tmpdir, store) = store_with_files(
+            &[("t.csv", "id,note,score\n1,first,\n2,second,3\n")],
+            Config::default(),
+        );
+        let mut glue = Glue::new(store);
+
+        let err = glue
+            .execute_async("INSERT INTO t VALUES (3, NULL, 4)")
+            .await
+            .expect_err("NULL in a column without blanks");
+        assert!(
+            format!("{:?}", err).contains("NullValueOnNotNullField"),
+            "{:?}",
+            err
+        );
+        glue.execute_async("INSERT INTO t VALUES (3, 'third', NULL)")
+            .await
+            .expect("NULL in a column with blanks");
+        glue.execute_async("UPDATE t SET score = NULL WHERE id = 2")
+            .await
+            .expect("update a column with blanks");
+
+        // Without rows, nothing is known about the columns
+        glue.execute_async("CREATE TABLE u (a INT, b TEXT)")
+            .await
+            .expect("create");
+        glue.execute_async("INSERT INTO u VALUES (1, NULL)")
+            .await
+            .expect("NULL in an empty table");
+    }
 
-        let table_id = TableIdentifier::new(table_name.to_string(), self.data_dir.clone());
-        let path: TablePath = table_id.try_into()?;
-        let csv_path = path.as_csv();
+    #[test]
+    fn test_infer_type() {
+        let (_tmpdir, store) = store_with_files(&[], Config::default());
+        let values: Vec<_> = ["1", "-2", "2.5", "3", "1e3", "n/a", "4"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        let inferred = store.infer_type(&values);
 
-        let mut delete_row_nums: Vec<_> = keys
-            .iter()
-            .map(get_row_num)
-            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(inferred.column_type, "String");
+        assert_eq!(inferred.data_type, DataType::Text);
+        assert_eq!(
+            inferred.widened_by,
+            vec![
+                ("2.5".to_string(), "Float".to_string()),
+                ("n/a".to_string(), "String".to_string()),
+            ]
+        );
 
-        delete_row_nums.sort();
-        delete_row_nums.reverse();
+        assert!(store.infer_type(&[]).widened_by.is_empty());
 
-        let mut buf = Vec::new();
+        let times: Vec<_> = ["09:30:00", "17:05:59.5"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(store.infer_type(&times).data_type, DataType::Time);
 
-        let orig_file = BufReader::new(File::open(&csv_path)?);
+        let dates: Vec<_> = ["2023-01-15", "2024-02-29"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(store.infer_type(&dates).data_type, DataType::Date);
 
-        for (line_num, line_res) in orig_file.lines().enumerate() {
-            let line = line_res?;
-            if let Some(&next_skip_line_num) = delete_row_nums.last() {
-                if next_skip_line_num == line_num {
-                    delete_row_nums.pop();
-                    continue;
-                }
-            }
-            writeln!(buf, "{}", line)?;
+        let not_dates: Vec<_> = ["2023-01-15", "2023-02-30"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(store.infer_type(&not_dates).data_type, DataType::Text);
+
+        // The store's configured date formats are honored
+        let config = Config {
+            date_formats: vec!["%d/%m/%Y".to_string()],
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(&[], config);
+        let dates: Vec<_> = ["15/01/2023", "29/02/2024"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(store.infer_type(&dates).data_type, DataType::Date);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_table() {
+        let files = [
+            (
+                "orders/_manifest.toml",
+                "parts = [\"part-0001.csv\", \"part-0002.csv\"]\n",
+            ),
+            ("orders/part-0001.csv", "id,total\n1,9.5\n2,3\n"),
+            ("orders/part-0002.csv", "id,total\n3,12\n"),
+        ];
+        let (_tmpdir, store) = store_with_files(&files, Config::default());
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(
+                &mut glue,
+                "SELECT id, total FROM orders WHERE total > 5 ORDER BY id"
+            )
+            .await,
+            vec![
+                vec![Value::I32(1), Value::F64(9.5)],
+                vec![Value::I32(3), Value::F64(12.0)],
+            ]
+        );
+
+        glue.execute_async("DELETE FROM orders")
+            .await
+            .expect_err("manifest tables are read-only");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn test_encrypted_table() {
+        use age::secrecy::ExposeSecret;
+
+        let identity = age::x25519::Identity::generate();
+        let key_dir = tempdir::TempDir::new("feet-key").expect("tmpdir");
+        let key_path = key_dir.path().join("key.txt");
+        let key = identity.to_string();
+        std::fs::write(&key_path, format!("# test key\n{}\n", key.expose_secret())).expect("write");
+
+        let config = Config {
+            age_identity_file: Some(key_path.to_str().expect("path").to_owned()),
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[], config);
+        let ciphertext =
+            age::encrypt(&identity.to_public(), b"id,name\n1,ann\n2,bob\n").expect("encrypt");
+        std::fs::write(tmpdir.path().join("people.csv.age"), ciphertext).expect("write");
+
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let nodes = store.list_tables(root).expect("list");
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].is_table());
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name FROM people WHERE id = 2").await,
+            vec![vec![Value::Str("bob".to_string())]]
+        );
+
+        glue.execute_async("DELETE FROM people")
+            .await
+            .expect_err("encrypted tables are read-only");
+        let files: Vec<_> = std::fs::read_dir(tmpdir.path())
+            .expect("read dir")
+            .map(|entry| entry.expect("entry").file_name())
+            .collect();
+        assert_eq!(files, vec![OsStr::new("people.csv.age")]);
+    }
+
+    #[tokio::test]
+    async fn test_empty_cells_are_null() {
+        let contents = "id,n,note\n1,10,a\n2,,\n3,30,\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        let payloads = glue
+            .execute_async("SHOW COLUMNS FROM t")
+            .await
+            .expect("show columns");
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![
+                ("id".to_string(), DataType::Int32),
+                ("n".to_string(), DataType::Int32),
+                ("note".to_string(), DataType::Text),
+            ])]
+        );
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT id FROM t WHERE n IS NULL OR n > 20").await,
+            vec![vec![Value::I32(2)], vec![Value::I32(3)]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_rows() {
+        let (tmpdir, store) = store_with_files(&[("t.csv", "id,name\n1,a\n")], Config::default());
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (2, 'b'), (3, 'c')")
+            .await
+            .expect("insert");
+        glue.execute_async("DELETE FROM t WHERE id = 2")
+            .await
+            .expect("delete");
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT id, name FROM t").await,
+            vec![
+                vec![Value::I32(1), Value::Str("a".to_string())],
+                vec![Value::I32(3), Value::Str("c".to_string())],
+            ]
+        );
+
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,name\n1,a\n3,c\n");
+        assert!(!tmpdir.path().join("t.csv.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_multiline_and_blank() {
+        let contents = "id,note\n1,\"two\nlines\"\n2,b\r\n\n3,c\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+        let read = || std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+
+        let mut glue = Glue::new(store);
+        let payloads = glue
+            .execute_async("DELETE FROM t WHERE id = 3")
+            .await
+            .expect("delete");
+        assert_eq!(payloads, vec![Payload::Delete(1)]);
+        assert_eq!(read(), "id,note\n1,\"two\nlines\"\n2,b\r\n\n");
+
+        glue.execute_async("DELETE FROM t WHERE id = 2")
+            .await
+            .expect("delete");
+        assert_eq!(read(), "id,note\n1,\"two\nlines\"\n\n");
+
+        let config = Config {
+            comment: Some('#'),
+            delete_mode: DeleteMode::Tombstone,
+            ..Config::default()
+        };
+        let contents = "id,note\n\n1,\"two\nlines\"\n2,b\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], config);
+        let mut glue = Glue::new(store);
+        glue.execute_async("DELETE FROM t WHERE id = 1")
+            .await
+            .expect("delete");
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,note\n\n#1,\"two\n#lines\"\n2,b\n");
+        let payloads = glue.execute_async("SELECT id FROM t").await.expect("query");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["id".to_string()],
+                rows: vec![vec![Value::I32(2)]],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_multiline() {
+        let contents = "id,note\n1,\"two\nlines\"\n\n2,b\n3,c";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+        let read = || std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("UPDATE t SET note = 'z' WHERE id = 2")
+            .await
+            .expect("update");
+        assert_eq!(read(), "id,note\n1,\"two\nlines\"\n\n2,z\n3,c");
+
+        glue.execute_async("UPDATE t SET note = 'new\nline' WHERE id = 3")
+            .await
+            .expect("update");
+        glue.execute_async("UPDATE t SET note = 'one' WHERE id = 1")
+            .await
+            .expect("update");
+        assert_eq!(read(), "id,note\n1,one\n\n2,z\n3,\"new\nline\"");
+    }
+
+    #[tokio::test]
+    async fn test_update_rows() {
+        let contents = "id,name\n1,a\n2,b\n3,c\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("UPDATE t SET name = 'z' WHERE id = 2")
+            .await
+            .expect("update");
+
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,name\n1,a\n2,z\n3,c\n");
+        assert!(!tmpdir.path().join("t.csv.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_durable_writes() {
+        let config = Config {
+            durable_writes: true,
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("t.csv", "id,name\n1,a\n")], config);
+
+        let mut glue = Glue::new(store);
+        for sql in [
+            "INSERT INTO t VALUES (2, 'b'), (3, 'c')",
+            "UPDATE t SET name = 'z' WHERE id = 1",
+            "DELETE FROM t WHERE id = 3",
+        ] {
+            glue.execute_async(sql).await.expect(sql);
         }
 
-        let mut file = File::create(&csv_path)?;
-        file.write_all(&buf)?;
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,name\n1,z\n2,b\n");
+    }
+
+    #[test]
+    fn test_reindex_table() {
+        let contents = "id,name\n3,c\n,x\n1,a\n2,b\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
 
-        Ok(())
+        assert!(!store.reindex_table("t", None).expect("reindex by position"));
+        assert!(store.reindex_table("t", Some("id")).expect("reindex"));
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,name\n1,a\n2,b\n3,c\n,x\n");
+
+        assert!(!store.reindex_table("t", Some("id")).expect("reindex again"));
+        assert!(store.reindex_table("t", Some("missing")).is_err());
     }
-}
 
-#[async_trait(?Send)]
-impl StoreMut for CsvStore {
-    async fn insert_schema(self, schema: &Schema) -> MutResult<Self, ()> {
-        let mut storage = self;
-        CsvStore::insert_schema(&mut storage, schema)
+    #[test]
+    fn test_schema_cache() {
+        let (_tmpdir, store) = store_with_files(&[("t.csv", "n\n1\n")], Config::default());
+        let path = store.table_path("t").expect("path");
+        let csv_path = path.as_csv();
+        let types = |store: &CsvStore| {
+            let col_pairs = store.column_types_for_table(&path).expect("column types");
+            col_pairs
+                .into_iter()
+                .map(|(_name, typ)| typ)
+                .collect::<Vec<_>>()
+        };
+        // Rewrite the file behind the store's back, keeping its size and mtime
+        let modified = std::fs::metadata(&csv_path)
+            .and_then(|metadata| metadata.modified())
+            .expect("mtime");
+        let overwrite = |contents: &str| {
+            std::fs::write(&csv_path, contents).expect("write");
+            File::options()
+                .write(true)
+                .open(&csv_path)
+                .and_then(|file| file.set_modified(modified))
+                .expect("set mtime");
+        };
+        assert_eq!(types(&store), vec![ColumnType::Int]);
+
+        overwrite("n\nx\n");
+        assert_eq!(types(&store), vec![ColumnType::Int]);
+        store.cached_columns().clear();
+        assert_eq!(types(&store), vec![ColumnType::String]);
+
+        // A mutation invalidates the table's entry
+        overwrite("n\n2\n");
+        store
+            .begin_mutation(std::slice::from_ref(&csv_path))
+            .expect("begin")
+            .commit()
+            .expect("commit");
+        assert_eq!(types(&store), vec![ColumnType::Int]);
+    }
+
+    #[test]
+    fn test_ignores() {
+        let config = Config {
+            ignores: vec!["*.bak.csv".to_string(), "tmp*".to_string()],
+            ..Config::default()
+        };
+        let files = [
+            ("a.csv", "n\n1\n"),
+            ("a.bak.csv", "n\n1\n"),
+            ("tmp/b.csv", "n\n1\n"),
+        ];
+        let (_tmpdir, store) = store_with_files(&files, config);
+        assert!(store.should_ignore("tmp"));
+        assert!(!store.should_ignore("a.csv"));
+
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let nodes = store.list_tables(root).expect("list");
+        assert_eq!(nodes.len(), 1);
+
+        let config = Config {
+            data_dir: store.data_dir.to_str().expect("path").to_owned(),
+            ignores: vec!["[".to_string()],
+            ..Config::default()
+        };
+        assert!(CsvStore::try_new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tombstone_delete() {
+        let config = Config {
+            comment: Some('#'),
+            delete_mode: DeleteMode::Tombstone,
+            ..Config::default()
+        };
+        let contents = "id,name\n1,a\n# imported 2023\n2,b\n3,c\n";
+        let (tmpdir, store) = store_with_files(&[("t.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("DELETE FROM t WHERE id = 2")
             .await
-            .to_glue_err()
-            .into_mut_result(storage)
+            .expect("delete");
+        // Rows after a tombstone keep their place
+        glue.execute_async("UPDATE t SET name = 'z' WHERE id = 3")
+            .await
+            .expect("update");
+
+        let payloads = glue.execute_async("SELECT id FROM t").await.expect("query");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["id".to_string()],
+                rows: vec![vec![Value::I32(1)], vec![Value::I32(3)]],
+            }]
+        );
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "id,name\n1,a\n# imported 2023\n#2,b\n3,z\n");
+
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path").to_owned(),
+            delete_mode: DeleteMode::Tombstone,
+            ..Config::default()
+        };
+        assert!(CsvStore::try_new(config).is_err());
     }
 
-    async fn delete_schema(self, table_name: &str) -> MutResult<Self, ()> {
-        let mut storage = self;
-        CsvStore::delete_schema(&mut storage, table_name)
+    #[test]
+    fn test_slice_rows() {
+        let contents = "id,name\n0,a\n1,b\n2,c\n3,d\n4,e\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        let (labels, rows) = store.slice_rows("t", 1, 3).expect("slice");
+        assert_eq!(labels, vec!["id", "name"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::I32(1), Value::Str("b".to_string())],
+                vec![Value::I32(2), Value::Str("c".to_string())],
+            ]
+        );
+
+        let (_labels, rows) = store.slice_rows("t", 4, 10).expect("slice past the end");
+        assert_eq!(rows.len(), 1);
+        assert!(store.slice_rows("t", 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_count_rows() {
+        let files = [
+            ("t.csv", "id,note\n1,\"two\nlines\"\n2,x\n3,y\n"),
+            ("empty.csv", "id,note\n"),
+            ("parts/_manifest.toml", "parts = [\"a.csv\", \"b.csv\"]\n"),
+            ("parts/a.csv", "id\n1\n"),
+            ("parts/b.csv", "id\n2\n"),
+        ];
+        let (_tmpdir, store) = store_with_files(&files, Config::default());
+
+        assert_eq!(store.count_rows("t").expect("count"), 3);
+        assert_eq!(store.count_rows("empty").expect("count"), 0);
+        assert_eq!(store.count_rows("parts").expect("count"), 2);
+        assert!(store.count_rows("missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quarantine() {
+        let quarantine_dir = tempdir::TempDir::new("feet-quarantine").expect("tmpdir");
+        let quarantine = quarantine_dir.path().join("bad.csv");
+        let config = Config {
+            inference_sample_rows: 1,
+            quarantine: Some(quarantine.to_str().expect("path").to_owned()),
+            ..Config::default()
+        };
+        let contents = "n,note\n1,a\nx,b\n3,c\n4.5,d\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], config);
+
+        let mut glue = Glue::new(store);
+        let payloads = glue.execute_async("SELECT n FROM t").await.expect("query");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["n".to_string()],
+                rows: vec![vec![Value::I32(1)], vec![Value::I32(3)]],
+            }]
+        );
+
+        let store = glue.storage.expect("storage");
+        let (count, path) = store.take_quarantined().expect("quarantined");
+        assert_eq!((count, path), (2, quarantine.as_path()));
+        assert!(store.take_quarantined().is_none());
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&quarantine)
+            .expect("open quarantine");
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|res| {
+                let record = res.expect("record");
+                // Skip the error message
+                [0, 1, 3, 4].map(|i| record[i].to_string()).to_vec()
+            })
+            .collect();
+        assert_eq!(rows, vec![["t", "3", "x", "b"], ["t", "5", "4.5", "d"]]);
+    }
+
+    #[tokio::test]
+    async fn test_inference_sample_rows() {
+        let column_type = |inference_sample_rows| async move {
+            let config = Config {
+                inference_sample_rows,
+                ..Config::default()
+            };
+            let (_tmpdir, store) = store_with_files(&[("t.csv", "n\n1\n2\nabc\n")], config);
+            let mut glue = Glue::new(store);
+            let payloads = glue
+                .execute_async("SHOW COLUMNS FROM t")
+                .await
+                .expect("show columns");
+            let scanned = glue.execute_async("SELECT * FROM t").await;
+            (payloads, scanned.is_ok())
+        };
+
+        let (payloads, scanned) = column_type(2).await;
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![(
+                "n".to_string(),
+                DataType::Int32
+            )])]
+        );
+        // The unsampled value doesn't fit
+        assert!(!scanned);
+
+        let (payloads, scanned) = column_type(0).await;
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![(
+                "n".to_string(),
+                DataType::Text
+            )])]
+        );
+        assert!(scanned);
+    }
+
+    #[tokio::test]
+    async fn test_big_int_column() {
+        let contents = "id,n\n1,3000000000\n2,5\n3,-9000000000000000000\n";
+        let (_tmpdir, store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        let payloads = glue
+            .execute_async("SHOW COLUMNS FROM t")
             .await
-            .to_glue_err()
-            .into_mut_result(storage)
+            .expect("show columns");
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![
+                ("id".to_string(), DataType::Int32),
+                ("n".to_string(), DataType::Int),
+            ])]
+        );
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT id, n FROM t WHERE n > 10").await,
+            vec![vec![Value::I32(1), Value::I64(3_000_000_000)]]
+        );
     }
 
-    async fn append_data(self, table_name: &str, rows: Vec<Row>) -> MutResult<Self, ()> {
-        let mut storage = self;
-        CsvStore::append_data(&mut storage, table_name, rows)
+    #[tokio::test]
+    async fn test_date_column() {
+        let contents = "id,day\n1,2023-01-15\n2,2023-03-01\n3,2022-12-31\n";
+        let (_tmpdir, store) = store_with_files(&[("events.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(
+                &mut glue,
+                "SELECT id FROM events WHERE day >= DATE '2023-01-01' ORDER BY day"
+            )
+            .await,
+            vec![vec![Value::I32(1)], vec![Value::I32(2)]]
+        );
+
+        glue.execute_async("INSERT INTO events VALUES (4, DATE '2024-02-29')")
             .await
-            .to_glue_err()
-            .into_mut_result(storage)
+            .expect("insert");
+        let store = glue.storage.expect("storage");
+        let written = std::fs::read_to_string(store.data_dir.join("events.csv")).expect("read");
+        assert!(written.ends_with("4,2024-02-29\n"), "{}", written);
     }
 
-    async fn insert_data(self, table_name: &str, rows: Vec<(Key, Row)>) -> MutResult<Self, ()> {
-        let mut storage = self;
-        CsvStore::insert_data(&mut storage, table_name, rows)
+    #[tokio::test]
+    async fn test_bool_column() {
+        let contents = "name,active,code\nann,true,1\nbob,FALSE,true\ncy,True,0\n";
+        let (_tmpdir, store) = store_with_files(&[("users.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        let payloads = glue
+            .execute_async("SHOW COLUMNS FROM users")
             .await
-            .to_glue_err()
-            .into_mut_result(storage)
+            .expect("show columns");
+        assert_eq!(
+            payloads,
+            vec![Payload::ShowColumns(vec![
+                ("name".to_string(), DataType::Text),
+                ("active".to_string(), DataType::Boolean),
+                // Booleans don't widen to integers
+                ("code".to_string(), DataType::Text),
+            ])]
+        );
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT name, active FROM users WHERE NOT active").await,
+            vec![vec![Value::Str("bob".to_string()), Value::Bool(false)]]
+        );
     }
 
-    async fn delete_data(self, table_name: &str, keys: Vec<Key>) -> MutResult<Self, ()> {
-        let mut storage = self;
-        CsvStore::delete_data(&mut storage, table_name, keys)
+    #[test]
+    fn test_uuid_round_trip() {
+        let uuid = 0x936DA01F_9ABD_4D9D_80C7_02AF85C822A8;
+        let written = format_value_for_write(Value::Uuid(uuid), None);
+        assert_eq!(written, "936da01f-9abd-4d9d-80c7-02af85c822a8");
+
+        let read = value_from_str(
+            &written,
+            &ColumnType::Custom(DataType::Uuid),
+            FloatToInt::Error,
+        )
+        .expect("read");
+        assert_eq!(read, Value::Uuid(uuid));
+    }
+
+    #[tokio::test]
+    async fn test_time_round_trip() {
+        let contents = "name,opens\nbakery,07:30:00\n";
+        let (tmpdir, store) = store_with_files(&[("shops.csv", contents)], Config::default());
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO shops VALUES ('library', '09:15:30.25')")
             .await
-            .to_glue_err()
-            .into_mut_result(storage)
+            .expect("insert");
+        let on_disk = std::fs::read_to_string(tmpdir.path().join("shops.csv")).expect("read");
+        assert_eq!(
+            on_disk,
+            "name,opens\nbakery,07:30:00\nlibrary,09:15:30.250\n"
+        );
+
+        let payloads = glue
+            .execute_async("SELECT opens FROM shops")
+            .await
+            .expect("select");
+        let time = |h, m, s, ms| NaiveTime::from_hms_milli_opt(h, m, s, ms).expect("time");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["opens".to_string()],
+                rows: vec![
+                    vec![Value::Time(time(7, 30, 0, 0))],
+                    vec![Value::Time(time(9, 15, 30, 250))],
+                ],
+            }]
+        );
     }
-}
 
-impl GStore for CsvStore {}
-impl GStoreMut for CsvStore {}
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_cycle() {
+        let (tmpdir, store) = store_with_files(
+            &[("a/t.csv", "n\n1\n"), ("b/u.csv", "n\n2\n")],
+            Config::default(),
+        );
+        // a/to_b -> b, b/to_a -> a
+        std::os::unix::fs::symlink(tmpdir.path().join("b"), tmpdir.path().join("a/to_b"))
+            .expect("symlink");
+        std::os::unix::fs::symlink(tmpdir.path().join("a"), tmpdir.path().join("b/to_a"))
+            .expect("symlink");
 
-#[cfg(test)]
-mod tests {
-    use std::cell::RefCell;
-    use std::rc::Rc;
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let infos = store.inventory(root).expect("inventory");
+        let ids: Vec<_> = infos.into_iter().map(|info| info.identifier).collect();
+        // Each cycle is cut where it leads back to a directory being walked
+        assert_eq!(ids, vec!["a/t", "a/to_b/u", "b/to_a/t", "b/u"]);
+
+        let (_sources, nrows) = store.merge_tables("**/t", "merged").expect("merge");
+        assert_eq!(nrows, 2);
+    }
 
-    use gluesql::test_suite::*;
+    #[tokio::test]
+    async fn test_scan_limit() {
+        let contents: String = std::iter::once("n\n".to_string())
+            .chain((0..1000).map(|i| format!("{}\n", i)))
+            .collect();
+        let (_tmpdir, mut store) = store_with_files(&[("t.csv", &contents)], Config::default());
 
-    use super::*;
+        store.set_scan_limit(Some(3));
+        assert_eq!(scan_rows(&store, "t").await.len(), 3);
 
-    struct CsvTester {
-        storage: Rc<RefCell<Option<CsvStore>>>,
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT n FROM t LIMIT 2 OFFSET 1").await,
+            vec![vec![Value::I32(1)], vec![Value::I32(2)]]
+        );
     }
 
-    impl Tester<CsvStore> for CsvTester {
-        fn new(_: &str) -> Self {
-            let tmpdir = tempdir::TempDir::new("csv-store-tester").expect("tmpdir");
+    #[tokio::test]
+    async fn test_headerless_writes() {
+        let config = Config {
+            has_headers: false,
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("t.csv", "1,a\n2,b\n")], config);
+
+        let mut glue = Glue::new(store);
+        glue.execute_async("INSERT INTO t VALUES (3, 'c')")
+            .await
+            .expect("insert");
+        glue.execute_async("DELETE FROM t WHERE col1 = 1")
+            .await
+            .expect("delete");
+        let contents = std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read");
+        assert_eq!(contents, "2,b\n3,c\n");
+
+        assert_eq!(
+            select_rows(&mut glue, "SELECT col2 FROM t WHERE col1 = 2").await,
+            vec![vec![Value::Str("b".to_string())]]
+        );
+
+        glue.execute_async("CREATE TABLE new (x INT)")
+            .await
+            .expect("create");
+        let contents = std::fs::read_to_string(tmpdir.path().join("new.csv")).expect("read");
+        assert_eq!(contents, "");
+    }
+
+    #[tokio::test]
+    async fn test_headerless_line_readers() {
+        let configs = [
+            ("1,a\n2,b\n", Config::default()),
+            (
+                // Blank lines are skipped, as when reading strictly
+                "1,a\n\n2,b\n",
+                Config {
+                    lenient_parsing: true,
+                    ..Config::default()
+                },
+            ),
+            (
+                "1::a\n2::b\n",
+                Config {
+                    regex_delimiter: Some("::".to_string()),
+                    ..Config::default()
+                },
+            ),
+        ];
+        for (contents, config) in configs {
             let config = Config {
-                data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
-                ignores: vec![],
+                has_headers: false,
+                ..config
             };
-            let storage = CsvStore::new(config);
+            let rows = query_rows(&[("t.csv", contents)], config, "SELECT col1, col2 FROM t").await;
+            assert_eq!(
+                rows,
+                vec![
+                    vec![Value::I64(1), Value::Str("a".to_string())],
+                    vec![Value::I64(2), Value::Str("b".to_string())],
+                ],
+                "{:?}",
+                contents
+            );
+        }
+    }
 
-            CsvTester {
-                storage: Rc::new(RefCell::new(Some(storage))),
-            }
+    #[test]
+    fn test_literal_paths() {
+        let literal = expand_path("~feet/data", true).expect("literal");
+        assert_eq!(literal, PathBuf::from("~feet/data"));
+
+        let expanded = expand_path("~/data", false).expect("expanded");
+        assert!(!expanded.starts_with("~"));
+        assert!(expanded.ends_with("data"));
+    }
+
+    #[tokio::test]
+    async fn test_insert_json() {
+        let (_tmpdir, mut store) = store_with_files(
+            &[("t.csv", "id,score,note\n1,2.5,first\n")],
+            Config::default(),
+        );
+
+        let json = r#"[{"id": 2, "score": 3, "note": "second"}, {"id": 3, "score": 0.5}]"#;
+        let nrows = store.insert_json("t", json).await.expect("insert");
+        assert_eq!(nrows, 2);
+
+        assert!(store.insert_json("t", r#"[{"id": "four"}]"#).await.is_err());
+        assert!(store
+            .insert_json("t", r#"[{"id": 4, "extra": 1}]"#)
+            .await
+            .is_err());
+
+        let mut glue = Glue::new(store);
+        assert_eq!(
+            select_rows(&mut glue, "SELECT id, score FROM t WHERE id > 1").await,
+            vec![
+                vec![Value::I32(2), Value::F64(3.0)],
+                vec![Value::I32(3), Value::F64(0.5)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_float_to_int() {
+        let insert = |float_to_int| async move {
+            let config = Config {
+                float_to_int,
+                ..Config::default()
+            };
+            let (tmpdir, mut store) = store_with_files(&[("t.csv", "n\n1\n")], config);
+            store
+                .insert_json("t", r#"[{"n": 2.0}, {"n": 2.7}]"#)
+                .await
+                .map(|_| std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read"))
+        };
+
+        assert!(insert(FloatToInt::Error).await.is_err());
+        assert_eq!(
+            insert(FloatToInt::Truncate).await.expect("truncate"),
+            "n\n1\n2\n2\n"
+        );
+        assert_eq!(
+            insert(FloatToInt::Round).await.expect("round"),
+            "n\n1\n2\n3\n"
+        );
+
+        // Loading CSV rounds fractions the same way
+        let load = |float_to_int| {
+            let config = Config {
+                float_to_int,
+                ..Config::default()
+            };
+            let (tmpdir, store) = store_with_files(&[("t.csv", "n\n1\n")], config);
+            store
+                .load_csv("t", "2.0\n2.7\n".as_bytes(), ImportHeaders::No)
+                .map(|_| std::fs::read_to_string(tmpdir.path().join("t.csv")).expect("read"))
+        };
+        assert!(load(FloatToInt::Error).is_err());
+        assert_eq!(load(FloatToInt::Round).expect("round"), "n\n1\n2\n3\n");
+
+        // As does reading a whole number past the rows types are inferred from
+        let config = Config {
+            inference_sample_rows: 1,
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(&[("t.csv", "n\n1\n3.0\n")], config);
+        let rows = scan_rows(&store, "t").await;
+        assert_eq!(
+            rows,
+            vec![Row(vec![Value::I32(1)]), Row(vec![Value::I32(3)])]
+        );
+    }
+
+    #[test]
+    fn test_inventory() {
+        let config = Config {
+            fixed_width: [(
+                "fw".to_string(),
+                FixedWidthSpec {
+                    columns: vec![FixedWidthColumn {
+                        name: "code".to_string(),
+                        start: 0,
+                        end: 3,
+                    }],
+                    skip_lines: 0,
+                },
+            )]
+            .into(),
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(
+            &[
+                ("a.csv", "id,name\n1,x\n2,y\n"),
+                ("sub/b.csv", "f\n1.5\n"),
+                ("fw.txt", "abc\ndef\nghi\n"),
+            ],
+            config,
+        );
+
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let infos = store.inventory(root).expect("inventory");
+        let json = serde_json::to_value(&infos).expect("to json");
+
+        assert_eq!(json.as_array().map(Vec::len), Some(3));
+        assert_eq!(json[0]["identifier"], "a");
+        assert_eq!(json[0]["format"], "csv");
+        assert_eq!(json[0]["rows"], 2);
+        assert_eq!(json[0]["bytes"], 16);
+        assert_eq!(
+            json[0]["columns"],
+            serde_json::json!([
+                { "name": "id", "type": "INT32" },
+                { "name": "name", "type": "TEXT" },
+            ])
+        );
+        assert!(json[0]["mtime"]
+            .as_str()
+            .is_some_and(|t| { chrono::DateTime::parse_from_rfc3339(t).is_ok() }));
+
+        assert_eq!(json[1]["identifier"], "fw");
+        assert_eq!(json[1]["format"], "fixed_width");
+        assert_eq!(json[1]["rows"], 3);
+
+        assert_eq!(json[2]["identifier"], "sub/b");
+        assert_eq!(json[2]["columns"][0]["type"], "FLOAT");
+
+        // Inferring a's types counted its rows, so they weren't read again
+        let a_path = store.table_path("a").expect("path");
+        assert_eq!(store.cached_row_count(&a_path).expect("cached"), Some(2));
+
+        // A sample short of the whole file doesn't count it
+        let config = Config {
+            inference_sample_rows: 1,
+            ..Config::default()
+        };
+        let (_tmpdir, store) = store_with_files(&[("a.csv", "id\n1\n2\n")], config);
+        let root = TableName::new(Vec::new(), store.data_dir.clone());
+        let infos = store.inventory(root).expect("inventory");
+        let json = serde_json::to_value(&infos).expect("to json");
+        let a_path = store.table_path("a").expect("path");
+        assert_eq!(store.cached_row_count(&a_path).expect("cached"), None);
+        assert_eq!(json[0]["rows"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_partition_pruning() {
+        let config = Config {
+            partitions: [("events".to_string(), "month".to_string())].into(),
+            ..Config::default()
+        };
+        let (_tmpdir, mut store) = store_with_files(
+            &[
+                ("events/2022-12.csv", "n\n1\n"),
+                ("events/2023-01.csv", "n\n2\n"),
+                ("events/2023-02.csv", "n\n3\n4\n"),
+                ("events/2023-03.csv", "n\n5\n"),
+                // Unreadable as a partition, so the query fails if it's opened
+                ("events/2023-04.csv", "other,columns\n6,7\n"),
+            ],
+            config,
+        );
+
+        let query = "SELECT n, month FROM events WHERE month BETWEEN '2023-01' AND '2023-03'";
+        let statement = gluesql::core::translate::translate(
+            &gluesql::core::parse_sql::parse(query).expect("parse")[0],
+        )
+        .expect("translate");
+        store.set_partition_bounds(Some(&statement));
+
+        let (parts, _first) = store
+            .partitions_in_bounds("events", "month")
+            .expect("partitions");
+        let stems: Vec<_> = parts.into_iter().map(|(stem, _path)| stem).collect();
+        assert_eq!(stems, vec!["2023-01", "2023-02", "2023-03"]);
+
+        let mut glue = Glue::new(store);
+        let payloads = glue.execute_async(query).await.expect("query");
+        let month = |s: &str| Value::Str(s.to_string());
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["n".to_string(), "month".to_string()],
+                rows: vec![
+                    vec![Value::I32(2), month("2023-01")],
+                    vec![Value::I32(3), month("2023-02")],
+                    vec![Value::I32(4), month("2023-02")],
+                    vec![Value::I32(5), month("2023-03")],
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_merge_tables() {
+        let (tmpdir, store) = store_with_files(
+            &[
+                ("logs/2021.csv", "day,n\n1,10\n2,20\n"),
+                ("logs/2022.csv", "day,n\n1,30\n"),
+                ("other.csv", "x\nfoo\n"),
+            ],
+            Config::default(),
+        );
+
+        let (sources, nrows) = store.merge_tables("logs/*", "all_logs").expect("merge");
+        assert_eq!(sources, vec!["logs/2021", "logs/2022"]);
+        assert_eq!(nrows, 3);
+
+        let merged = std::fs::read_to_string(tmpdir.path().join("all_logs.csv")).expect("read");
+        assert_eq!(merged, "day,n\n1,10\n2,20\n1,30\n");
+
+        let err = store.merge_tables("*", "everything").expect_err("mismatch");
+        assert!(format!("{:#}", err).contains("don't match"));
+        assert!(!tmpdir.path().join("everything.csv").exists());
+    }
+
+    fn write_with_mtime(path: &Path, contents: &str, mtime: std::time::SystemTime) {
+        std::fs::write(path, contents).expect("write file");
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(mtime))
+            .expect("set mtime");
+    }
+
+    #[tokio::test]
+    async fn test_cache_dir() {
+        let cache = tempdir::TempDir::new("feet-cache").expect("tmpdir");
+        let config = Config {
+            cache_dir: Some(cache.path().to_str().expect("path").to_owned()),
+            ..Config::default()
+        };
+        let (tmpdir, store) = store_with_files(&[("sub/t.csv", "n\n1\n")], config);
+        let source = tmpdir.path().join("sub/t.csv");
+        let copy = cache.path().join("sub/t.csv");
+
+        let first = scan_rows(&store, "sub/t").await;
+        assert!(matches!(first[0].0[..], [Value::I32(1)]));
+        assert!(copy.exists());
+
+        // Tamper with the copy, keeping the source's mtime and size,
+        // to show that the next scan reads from the cache
+        let mtime = std::fs::metadata(&source)
+            .and_then(|meta| meta.modified())
+            .expect("source mtime");
+        write_with_mtime(&copy, "n\n7\n", mtime);
+        let second = scan_rows(&store, "sub/t").await;
+        assert!(matches!(second[0].0[..], [Value::I32(7)]));
+
+        // Changing the source invalidates the copy
+        write_with_mtime(&source, "n\n2\n", mtime + std::time::Duration::from_secs(1));
+        let third = scan_rows(&store, "sub/t").await;
+        assert!(matches!(third[0].0[..], [Value::I32(2)]));
+    }
+
+    #[tokio::test]
+    async fn test_projection_hint() {
+        let ncols = 20;
+        let header: Vec<_> = (0..ncols).map(|i| format!("col{:02}", i)).collect();
+        let mut contents = header.join(",") + "\n";
+        for row in 0..5 {
+            let values: Vec<_> = (0..ncols).map(|i| (row * 100 + i).to_string()).collect();
+            contents += &(values.join(",") + "\n");
         }
+        let (_tmpdir, mut store) = store_with_files(&[("wide.csv", &contents)], Config::default());
 
-        fn get_cell(&mut self) -> Rc<RefCell<Option<CsvStore>>> {
-            Rc::clone(&self.storage)
+        let query = "SELECT col03 FROM wide WHERE col07 > 200";
+        store.set_projection_hint(Some(hint(&["col03", "col07"])));
+
+        // Only the named columns are parsed
+        let rows = scan_rows(&store, "wide").await;
+        assert_eq!(rows.len(), 5);
+        for (i, value) in rows[1].0.iter().enumerate() {
+            match i {
+                3 => assert_eq!(value, &Value::I32(103)),
+                7 => assert_eq!(value, &Value::I32(107)),
+                _ => assert!(matches!(value, Value::Null)),
+            }
         }
+
+        let mut glue = Glue::new(store);
+        let payloads = glue.execute_async(query).await.expect("query");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["col03".to_string()],
+                rows: vec![
+                    vec![Value::I32(203)],
+                    vec![Value::I32(303)],
+                    vec![Value::I32(403)]
+                ],
+            }]
+        );
+    }
+
+    fn hint(columns: &[&str]) -> HashSet<String> {
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_projection_hint_unresolved() {
+        let contents = "a,b\n1,2\n";
+        let (_tmpdir, mut store) = store_with_files(&[("t.csv", contents)], Config::default());
+
+        // `x` isn't a column of `t`, so the hint is ignored
+        store.set_projection_hint(Some(hint(&["a", "x"])));
+        let rows = scan_rows(&store, "t").await;
+        assert_eq!(rows[0].0, vec![Value::I32(1), Value::I32(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_projection_hint_sanitized() {
+        let config = Config {
+            sanitize_column_names: true,
+            ..Config::default()
+        };
+        let contents = "First Name,Last Name\nann,lee\nbob,ray\n";
+        let (_tmpdir, mut store) = store_with_files(&[("t.csv", contents)], config);
+        let query = "SELECT First_Name FROM t WHERE First_Name = 'bob'";
+        store.set_projection_hint(Some(hint(&["first_name"])));
+
+        let mut glue = Glue::new(store);
+        let payloads = glue.execute_async(query).await.expect("query");
+        assert_eq!(
+            payloads,
+            vec![Payload::Select {
+                labels: vec!["First_Name".to_string()],
+                rows: vec![vec![Value::Str("bob".to_string())]],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_normalize_unicode() {
+        let nfd_name = "cafe\u{301}";
+        let nfc_name = "caf\u{e9}";
+        let config = Config {
+            normalize_unicode: true,
+            ..Config::default()
+        };
+        let contents = format!("{},price\n1,2.5\n", nfd_name);
+        let (_tmpdir, store) = store_with_files(&[("cafe\u{301}.csv", &contents)], config);
+
+        let schema = store
+            .fetch_schema(nfc_name)
+            .await
+            .expect("fetch_schema")
+            .expect("table should resolve");
+        assert_eq!(schema.table_name, nfc_name);
+        assert_eq!(schema.column_defs[0].name, nfc_name);
+
+        let rows = scan_rows(&store, nfc_name).await;
+        assert_eq!(rows.len(), 1);
     }
 
-    generate_store_tests!(tokio::test, CsvTester);
+    #[tokio::test]
+    async fn test_unicode_not_normalized_by_default() {
+        let (_tmpdir, store) =
+            store_with_files(&[("cafe\u{301}.csv", "a\n1\n")], Config::default());
+
+        let schema = store.fetch_schema("caf\u{e9}").await.expect("fetch_schema");
+        assert!(schema.is_none());
+    }
+
+    /// GlueSQL's generic storage conformance suite.
+    /// Column types are inferred from file contents, so much of it
+    /// doesn't apply to CSV storage; run it with `--features store-test-suite`.
+    #[cfg(feature = "store-test-suite")]
+    mod store_suite {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        use gluesql::test_suite::*;
+
+        use super::*;
+
+        struct CsvTester {
+            storage: Rc<RefCell<Option<CsvStore>>>,
+            // Keep the directory alive for the duration of the test
+            _tmpdir: tempdir::TempDir,
+        }
+
+        impl Tester<CsvStore> for CsvTester {
+            fn new(_: &str) -> Self {
+                let tmpdir = tempdir::TempDir::new("csv-store-tester").expect("tmpdir");
+                let config = Config {
+                    data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
+                    ..Config::default()
+                };
+                let storage = CsvStore::try_new(config).expect("CsvStore::try_new");
+
+                CsvTester {
+                    storage: Rc::new(RefCell::new(Some(storage))),
+                    _tmpdir: tmpdir,
+                }
+            }
+
+            fn get_cell(&mut self) -> Rc<RefCell<Option<CsvStore>>> {
+                Rc::clone(&self.storage)
+            }
+        }
+
+        generate_store_tests!(tokio::test, CsvTester);
+    }
 }
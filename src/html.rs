@@ -0,0 +1,127 @@
+use std::io::Write;
+
+use gluesql::prelude::Value;
+
+use crate::format_value;
+
+/// Class of the (empty) cells holding NULLs
+pub const NULL_CLASS: &str = "null";
+
+/// Minimal CSS included before a table by `--html-style`
+const STYLE: &str = "<style>
+table { border-collapse: collapse; font-family: sans-serif; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+th { background: #f0f0f0; }
+td.null { background: #fafafa; }
+</style>
+";
+
+/// Escape text for use in HTML content or attribute values
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write a self-contained `<table>` with a header row, and optionally
+/// minimal inline CSS. `None` cells are NULLs, which are left empty.
+/// Returns the number of rows written.
+pub fn write_table<W, I>(
+    mut out: W,
+    labels: &[String],
+    rows: I,
+    style: bool,
+) -> anyhow::Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = anyhow::Result<Vec<Option<String>>>>,
+{
+    if style {
+        write!(out, "{}", STYLE)?;
+    }
+    writeln!(out, "<table>")?;
+    writeln!(out, "<thead>")?;
+    write!(out, "<tr>")?;
+    for label in labels {
+        write!(out, "<th>{}</th>", escape(label))?;
+    }
+    writeln!(out, "</tr>")?;
+    writeln!(out, "</thead>")?;
+
+    writeln!(out, "<tbody>")?;
+    let mut nrows = 0;
+    for row in rows {
+        write!(out, "<tr>")?;
+        for cell in row? {
+            match cell {
+                Some(cell) => write!(out, "<td>{}</td>", escape(&cell))?,
+                None => write!(out, "<td class=\"{}\"></td>", NULL_CLASS)?,
+            }
+        }
+        writeln!(out, "</tr>")?;
+        nrows += 1;
+    }
+    writeln!(out, "</tbody>")?;
+    writeln!(out, "</table>")?;
+
+    Ok(nrows)
+}
+
+/// Selected rows as an HTML table
+pub fn render_rows(labels: &[String], rows: Vec<Vec<Value>>, style: bool) -> String {
+    let rows = rows.into_iter().map(|row| Ok(row_cells(row)));
+    let mut out = Vec::new();
+    write_table(&mut out, labels, rows, style).expect("writing to a Vec can't fail");
+    String::from_utf8(out).expect("HTML is written as UTF-8")
+}
+
+/// The cells of a row of values, with `None` for NULLs
+pub fn row_cells(row: Vec<Value>) -> Vec<Option<String>> {
+    row.into_iter()
+        .map(|value| match value {
+            Value::Null => None,
+            other => Some(format_value(other)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_rows() {
+        let labels = vec!["id".to_string(), "a<b".to_string()];
+        let rows = vec![
+            vec![Value::I32(1), Value::Str("x < y & z".to_string())],
+            vec![Value::I32(2), Value::Null],
+        ];
+
+        let html = render_rows(&labels, rows, false);
+        assert_eq!(
+            html,
+            "<table>\n\
+             <thead>\n\
+             <tr><th>id</th><th>a&lt;b</th></tr>\n\
+             </thead>\n\
+             <tbody>\n\
+             <tr><td>1</td><td>x &lt; y &amp; z</td></tr>\n\
+             <tr><td>2</td><td class=\"null\"></td></tr>\n\
+             </tbody>\n\
+             </table>\n"
+        );
+
+        let styled = render_rows(&labels, Vec::new(), true);
+        assert!(styled.starts_with("<style>"), "{}", styled);
+        assert!(styled.contains("<tbody>\n</tbody>"), "{}", styled);
+    }
+}
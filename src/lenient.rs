@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use csv::StringRecord;
+
+/// Parse a single line as CSV, returning `None` if it's malformed
+/// (e.g. it has an unterminated quoted field) or has the wrong number of fields
+fn parse_line(
+    line: &str,
+    delimiter: u8,
+    quoting: bool,
+    nfields: Option<usize>,
+) -> Option<StringRecord> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .quoting(quoting)
+        .from_reader(line.as_bytes());
+
+    let mut records = reader.records();
+    let record = records.next()?.ok()?;
+    if records.next().is_some() {
+        // A quoted field swallowed the line ending
+        return None;
+    }
+    match nfields {
+        Some(n) if record.len() != n => None,
+        _ => Some(record),
+    }
+}
+
+/// Files which have had a warning about malformed lines
+pub type Warned = Arc<Mutex<HashSet<PathBuf>>>;
+
+/// Open a CSV file one line at a time, so that a stray quote can't
+/// affect other rows. A line which doesn't parse as quoted CSV is split
+/// on the delimiter verbatim; if that doesn't give the right number of
+/// fields either, it's an error. The first such line of a file not yet
+/// in `warned` gets a warning giving its line number.
+/// Blank lines are skipped, and quoted fields can't contain line breaks.
+pub fn open(
+    path: &Path,
+    delimiter: u8,
+    warned: Warned,
+) -> anyhow::Result<(
+    Vec<String>,
+    impl Iterator<Item = anyhow::Result<StringRecord>>,
+)> {
+    let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let mut lines = BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.is_empty()));
+
+    let headers: Vec<String> = match lines.next() {
+        Some((_, line)) => {
+            let line = line?;
+            parse_line(&line, delimiter, true, None)
+                .or_else(|| parse_line(&line, delimiter, false, None))
+                .map(|record| record.iter().map(ToOwned::to_owned).collect())
+                .unwrap_or_default()
+        }
+        None => Vec::new(),
+    };
+
+    let nfields = headers.len();
+    let path = path.to_owned();
+    let records = lines.map(move |(i, line_res)| {
+        let line = line_res?;
+        let line_num = i + 1;
+
+        if let Some(record) = parse_line(&line, delimiter, true, Some(nfields)) {
+            return Ok(record);
+        }
+        match parse_line(&line, delimiter, false, Some(nfields)) {
+            Some(record) => {
+                let mut warned = warned.lock().unwrap_or_else(|err| err.into_inner());
+                if warned.insert(path.clone()) {
+                    eprintln!(
+                        "warning: {:?} line {}: malformed quoting, read fields verbatim \
+                         (further lines like this aren't reported)",
+                        path, line_num
+                    );
+                }
+                Ok(record)
+            }
+            None => anyhow::bail!(
+                "{:?} line {}: expected {} fields in {:?}",
+                path,
+                line_num,
+                nfields,
+                line
+            ),
+        }
+    });
+
+    Ok((headers, records))
+}
+
+/// Byte ranges of the non-blank lines of a file, each including its
+/// line ending, as `open` reads them: the first is the header row
+pub fn line_spans(bytes: &[u8]) -> Vec<Range<usize>> {
+    let mut start = 0;
+    bytes
+        .split_inclusive(|&b| b == b'\n')
+        .filter_map(|line| {
+            start += line.len();
+            let blank = matches!(line, b"\n" | b"\r\n");
+            (!blank).then(|| start - line.len()..start)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_lenient() {
+        let tmpdir = tempdir::TempDir::new("feet-lenient").expect("tmpdir");
+        let path = tmpdir.path().join("t.csv");
+        let contents = "a,b\n1,\"x, y\"\n\"2,oops\n\n3,z\n";
+        std::fs::write(&path, contents).expect("write");
+
+        // The stray quote swallows the rest of the file
+        let mut reader = csv::Reader::from_path(&path).expect("open strict");
+        let strict: Result<Vec<_>, _> = reader.records().collect();
+        assert!(strict.is_err());
+
+        let warned = Warned::default();
+        let (headers, records) = open(&path, b',', warned.clone()).expect("open lenient");
+        let records: Vec<_> = records.collect::<anyhow::Result<_>>().expect("records");
+
+        assert_eq!(headers, vec!["a", "b"]);
+        assert_eq!(
+            records,
+            vec![
+                StringRecord::from(vec!["1", "x, y"]),
+                StringRecord::from(vec!["\"2", "oops"]),
+                StringRecord::from(vec!["3", "z"]),
+            ]
+        );
+        assert!(warned.lock().expect("lock").contains(&path));
+
+        // Blank lines aren't records
+        let spans = line_spans(contents.as_bytes());
+        let lines: Vec<_> = spans.into_iter().map(|span| &contents[span]).collect();
+        assert_eq!(lines, vec!["a,b\n", "1,\"x, y\"\n", "\"2,oops\n", "3,z\n"]);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+
+/// Most lines listed for each kind of problem, so memory stays bounded
+const MAX_LINES: usize = 100;
+
+/// Structural problems in a CSV file, found without parsing its values
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LintReport {
+    /// Number of records, including any header
+    pub records: usize,
+    /// Number of records with each number of fields,
+    /// and the first lines they start on
+    widths: BTreeMap<usize, (usize, Vec<usize>)>,
+    /// Numbers of quoted and unquoted fields in each column, after any header
+    quoting: Vec<(usize, usize)>,
+    /// Lines with a `"` within an unquoted field, or after a closing quote
+    pub stray_quotes: Vec<usize>,
+    /// Line of a record whose quoted field is never closed
+    pub unterminated: Option<usize>,
+}
+
+impl LintReport {
+    /// The most common number of fields in a record
+    pub fn modal_width(&self) -> Option<usize> {
+        self.widths
+            .iter()
+            .max_by_key(|(_, (count, _))| *count)
+            .map(|(width, _)| *width)
+    }
+
+    /// Number of records whose number of fields isn't the modal one
+    pub fn ragged_count(&self) -> usize {
+        let modal = self.modal_width();
+        self.widths
+            .iter()
+            .filter(|(width, _)| Some(**width) != modal)
+            .map(|(_, (count, _))| count)
+            .sum()
+    }
+
+    /// The first lines of records whose number of fields isn't the
+    /// modal one, in order, with their number of fields
+    pub fn ragged(&self) -> Vec<(usize, usize)> {
+        let modal = self.modal_width();
+        let mut ragged: Vec<_> = self
+            .widths
+            .iter()
+            .filter(|(width, _)| Some(**width) != modal)
+            .flat_map(|(width, (_, lines))| lines.iter().map(move |line| (*line, *width)))
+            .collect();
+        ragged.sort_unstable();
+        ragged.truncate(MAX_LINES);
+        ragged
+    }
+
+    /// Columns, numbered from 1, which are quoted in some records but not others
+    pub fn mixed_quoting(&self) -> Vec<usize> {
+        self.quoting
+            .iter()
+            .enumerate()
+            .filter(|(_, (quoted, unquoted))| *quoted > 0 && *unquoted > 0)
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Whether no problems were found
+    pub fn is_clean(&self) -> bool {
+        self.ragged_count() == 0
+            && self.mixed_quoting().is_empty()
+            && self.stray_quotes.is_empty()
+            && self.unterminated.is_none()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// At the start of a field, or of a record
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// Just after a `"` within a quoted field, which either closes it
+    /// or, if another follows, is escaped
+    QuotedQuote,
+    /// After a quoted field's closing quote
+    AfterQuoted,
+    /// Within a comment line
+    Comment,
+}
+
+struct Linter {
+    report: LintReport,
+    has_headers: bool,
+    /// Current line, counting from 1
+    line: usize,
+    /// Line the current record started on
+    record_line: usize,
+    /// Whether each field of the current record so far was quoted
+    fields: Vec<bool>,
+    /// Whether nothing of the current record has been read yet
+    blank: bool,
+}
+
+impl Linter {
+    fn end_field(&mut self, quoted: bool) {
+        self.fields.push(quoted);
+    }
+
+    fn end_record(&mut self) {
+        let report = &mut self.report;
+        report.records += 1;
+        let (count, lines) = report.widths.entry(self.fields.len()).or_default();
+        *count += 1;
+        if lines.len() < MAX_LINES {
+            lines.push(self.record_line);
+        }
+        if !(self.has_headers && report.records == 1) {
+            if report.quoting.len() < self.fields.len() {
+                report.quoting.resize(self.fields.len(), (0, 0));
+            }
+            for (counts, quoted) in report.quoting.iter_mut().zip(&self.fields) {
+                match quoted {
+                    true => counts.0 += 1,
+                    false => counts.1 += 1,
+                }
+            }
+        }
+        self.fields.clear();
+        self.blank = true;
+    }
+
+    fn stray_quote(&mut self) {
+        let lines = &mut self.report.stray_quotes;
+        if lines.last() != Some(&self.line) && lines.len() < MAX_LINES {
+            lines.push(self.line);
+        }
+    }
+}
+
+/// Scan CSV for structural problems: records with an unusual number of
+/// fields, columns quoted inconsistently, and misplaced quotes. Lines
+/// starting with `comment` and blank lines are skipped, as when reading.
+pub fn lint<R: Read>(
+    source: R,
+    delimiter: u8,
+    comment: Option<u8>,
+    has_headers: bool,
+) -> std::io::Result<LintReport> {
+    let mut linter = Linter {
+        report: LintReport::default(),
+        has_headers,
+        line: 1,
+        record_line: 1,
+        fields: Vec::new(),
+        blank: true,
+    };
+    let mut state = State::FieldStart;
+
+    for byte in BufReader::new(source).bytes() {
+        let byte = byte?;
+        state = match (state, byte) {
+            (State::Comment, b'\n') => State::FieldStart,
+            (State::Comment, _) => State::Comment,
+            (State::FieldStart, b'\r') => State::FieldStart,
+            (State::FieldStart, b'\n') if linter.blank => State::FieldStart,
+            (State::FieldStart, _) if linter.blank && Some(byte) == comment => State::Comment,
+            (State::FieldStart, _) => {
+                if linter.blank {
+                    linter.blank = false;
+                    linter.record_line = linter.line;
+                }
+                match byte {
+                    b'"' => State::Quoted,
+                    _ if byte == delimiter => {
+                        linter.end_field(false);
+                        State::FieldStart
+                    }
+                    b'\n' => {
+                        linter.end_field(false);
+                        linter.end_record();
+                        State::FieldStart
+                    }
+                    _ => State::Unquoted,
+                }
+            }
+            (State::Unquoted, _) if byte == delimiter => {
+                linter.end_field(false);
+                State::FieldStart
+            }
+            (State::Unquoted, b'\n') => {
+                linter.end_field(false);
+                linter.end_record();
+                State::FieldStart
+            }
+            (State::Unquoted, b'"') => {
+                linter.stray_quote();
+                State::Unquoted
+            }
+            (State::Unquoted, _) => State::Unquoted,
+            (State::Quoted, b'"') => State::QuotedQuote,
+            (State::Quoted, _) => State::Quoted,
+            (State::QuotedQuote, b'"') => State::Quoted,
+            (State::QuotedQuote | State::AfterQuoted, _) if byte == delimiter => {
+                linter.end_field(true);
+                State::FieldStart
+            }
+            (State::QuotedQuote | State::AfterQuoted, b'\n') => {
+                linter.end_field(true);
+                linter.end_record();
+                State::FieldStart
+            }
+            (State::QuotedQuote | State::AfterQuoted, b'\r') => State::AfterQuoted,
+            (State::QuotedQuote | State::AfterQuoted, _) => {
+                linter.stray_quote();
+                State::AfterQuoted
+            }
+        };
+        if byte == b'\n' {
+            linter.line += 1;
+        }
+    }
+
+    // The last record, if it doesn't end in a newline
+    match state {
+        State::FieldStart | State::Unquoted if !linter.blank => {
+            linter.end_field(false);
+            linter.end_record();
+        }
+        State::Quoted => {
+            linter.report.unterminated = Some(linter.record_line);
+            linter.end_field(true);
+            linter.end_record();
+        }
+        State::QuotedQuote | State::AfterQuoted => {
+            linter.end_field(true);
+            linter.end_record();
+        }
+        _ => {}
+    }
+
+    Ok(linter.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_ragged_rows() {
+        let csv = "id,note,n\n1,\"two\nlines\",3\n2,short\n\n# a comment, here\n3,\"x\",4\n4,y,5,6";
+        let report = lint(csv.as_bytes(), b',', Some(b'#'), true).expect("lint");
+
+        assert_eq!(report.records, 5);
+        assert_eq!(report.modal_width(), Some(3));
+        assert_eq!(report.ragged_count(), 2);
+        assert_eq!(report.ragged(), vec![(4, 2), (8, 4)]);
+        assert_eq!(report.mixed_quoting(), vec![2]);
+        assert!(report.stray_quotes.is_empty());
+        assert_eq!(report.unterminated, None);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_lint_quotes() {
+        let report = lint("a,b\r\n\"x\",\"y\"\r\n".as_bytes(), b',', None, true).expect("lint");
+        assert!(report.is_clean(), "{:?}", report);
+
+        let report = lint(
+            "a,b\nx\"y,1\n\"z\"w,2\n\"open,3\n".as_bytes(),
+            b',',
+            None,
+            true,
+        )
+        .expect("lint");
+        assert_eq!(report.stray_quotes, vec![2, 3]);
+        assert_eq!(report.unterminated, Some(4));
+    }
+}
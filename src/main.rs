@@ -1,24 +1,67 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Stdio};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use clap::{Parser, Subcommand};
-use error::Sendify;
-use gluesql::prelude::{Glue, Payload, Value};
+use error::{error_json, ErrorKind, Sendify};
+use gluesql::core::ast::{AstLiteral, Expr, SelectItem, SetExpr, Statement, TableFactor};
+use gluesql::core::parse_sql::parse;
+use gluesql::core::plan::plan;
+use gluesql::core::sqlparser::ast::{AlterTableOperation, Statement as SqlStatement};
+use gluesql::core::translate::translate;
+use gluesql::prelude::{DataType, Glue, Payload, Value};
 use names::TableIdentifier;
 use ptree::{item::StringItem, TreeBuilder};
+use regex::Regex;
 use rustyline::error::ReadlineError;
 
 // use gluesql::core::store::{GStore, GStoreMut};
 
-use crate::config::Config;
-use crate::glue::{TableData, TableNode};
+use crate::completion::SqlHelper;
+use crate::config::{Config, TransposeRows};
+use crate::glue::{ExportFormat, ImportHeaders, TableData, TableNode};
 use crate::names::TableName;
+use crate::outfile::Outfile;
+use crate::output::{
+    declared_types, gutter_payload, parse_out_delimiter, parse_renames, payload_json,
+    payload_json_typed, relabel_table_columns, rename_columns, reorder_columns, rows_csv,
+    selects_all, single_value, source_table, value_json, with_rowid_gutter, Output, OutputFormat,
+};
 
+mod aggregate_cache;
+mod completion;
+mod computed;
+mod concurrency;
 mod config;
+mod diff;
+#[cfg(feature = "encryption")]
+mod encrypted;
 mod error;
+mod fixed_width;
+mod glob_table;
 mod glue;
-mod line_injector;
+mod html;
+mod lenient;
+mod lint;
+mod manifest;
+mod modeline;
 mod names;
+mod outfile;
+mod output;
+mod overlay;
+#[cfg(feature = "parquet")]
+mod parquet_file;
+mod partition;
+mod progress;
+mod quarantine;
+mod regex_delimited;
+mod schema_file;
+mod stats;
+mod table_factors;
+mod views;
+mod wal;
 
 use crate::glue::CsvStore;
 
@@ -27,6 +70,97 @@ struct Opts {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// Hold edits in memory until `.flush`ed (see `Config::overlay`)
+    #[arg(long)]
+    overlay: bool,
+
+    /// Don't expand `~` in configured paths (see `Config::literal_paths`)
+    #[arg(long)]
+    literal_paths: bool,
+
+    /// Print errors to stderr as JSON objects with `code`, `kind` and `message`.
+    /// Exit codes: 1 SQL/validation or usage, 2 I/O, 3 config, 4 table not found.
+    #[arg(long)]
+    json_errors: bool,
+
+    /// Log every storage read and write to stderr. `RUST_LOG` overrides this.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// How to print query results and table listings
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        default_value_if("json_typed", "true", "json")
+    )]
+    format: OutputFormat,
+
+    /// Field delimiter of CSV written by `--format csv`, `export` and
+    /// `INTO OUTFILE`, e.g. `;` or `\t`. Tables are still read and
+    /// written with the `delimiter` setting.
+    #[arg(long, value_name = "CHAR", value_parser = parse_out_delimiter)]
+    out_delimiter: Option<u8>,
+
+    /// Indent JSON output
+    #[arg(long)]
+    pretty: bool,
+
+    /// With `--format html`, include minimal inline CSS
+    #[arg(long)]
+    html_style: bool,
+
+    /// Show a single selected row as `field | value` pairs. Several rows
+    /// are shown as `Config::transpose_rows` says.
+    #[arg(long)]
+    transpose: bool,
+
+    /// Repeat the header row of result tables after every N rows,
+    /// so columns stay identifiable while scrolling
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    repeat_header: Option<u64>,
+
+    /// Show result tables in sections, one for each value of this column,
+    /// sorted by it. Only affects `--format table`.
+    #[arg(long, value_name = "COLUMN")]
+    group_by: Option<String>,
+
+    /// Allow statements and commands which delete or overwrite data
+    /// (see `Config::require_confirm_destructive`)
+    #[arg(long)]
+    confirm: bool,
+
+    /// Print the result of a SELECT of a single value (e.g. a count)
+    /// as just that value, for use in scripts. Other SELECTs fail.
+    #[arg(long, visible_alias = "one-line")]
+    value: bool,
+
+    /// Show a progress bar on stderr while reading large files
+    #[arg(long)]
+    progress: bool,
+
+    /// Skip rows whose values can't be read, appending them to this file
+    /// (see `Config::quarantine`)
+    #[arg(long, value_name = "PATH", visible_alias = "follow-errors")]
+    quarantine: Option<String>,
+
+    /// Show columns by other names in query results and exports,
+    /// e.g. `cst_nm=customer,amt=amount`. Queries still use the
+    /// original names. See also `Config::column_aliases`.
+    #[arg(long, value_name = "OLD=NEW,...", value_parser = parse_renames)]
+    rename: Option<HashMap<String, String>>,
+
+    /// Prefix each row of a result table with its row id.
+    /// Toggle in the repl with `.gutter on|off`.
+    #[arg(long)]
+    gutter: bool,
+
+    /// Print query results as JSON of the form
+    /// `{"columns": [{"name", "type"}, ...], "rows": [[...], ...]}`.
+    /// Implies `--format json`.
+    #[arg(long)]
+    json_typed: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -34,13 +168,167 @@ struct Opts {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Query data
-    Query { query: String },
+    Query {
+        query: String,
+        /// Write the output to the stdin of this shell command
+        #[arg(long)]
+        pipe: Option<String>,
+        /// Let `INTO OUTFILE 'path'` overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
     /// List tables
     List { subdir: Option<String> },
     /// List tables
     Tree { subdir: Option<String> },
     /// SQL repl
     Repl,
+    /// Import a CSV file as a new table
+    Import {
+        source: PathBuf,
+        table: String,
+        /// Treat the source as headerless, using the columns of this table
+        #[arg(long)]
+        columns_from: Option<String>,
+        /// Whether the source has a header row
+        /// [default: yes, or no with --columns-from]
+        #[arg(long, value_enum)]
+        headers: Option<ImportHeaders>,
+        /// Fix the table's column types with this schema file, which is
+        /// copied beside it, rather than inferring them
+        #[arg(long, conflicts_with = "columns_from")]
+        schema_file: Option<PathBuf>,
+    },
+    /// Replace the contents of an existing table with a CSV file
+    Replace {
+        table: String,
+        source: PathBuf,
+        /// Allow the file's columns to differ from the table's
+        #[arg(long)]
+        allow_schema_change: bool,
+    },
+    /// Rewrite a table with its rows in order of a key column, e.g. to
+    /// repair its order after a botched edit. Rows are otherwise keyed by
+    /// their position, so they're always in order.
+    Reindex {
+        table: String,
+        /// Sort rows by this column
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Concatenate tables whose names match a glob into a new table
+    Merge {
+        glob: String,
+        #[arg(long)]
+        into: String,
+    },
+    /// Append rows given as a JSON array of objects
+    Insert {
+        table: String,
+        /// Inline JSON, or `-` to read from stdin
+        json: String,
+    },
+    /// Append CSV rows from a file or stdin to an existing table,
+    /// checking that each fits its columns
+    Load {
+        table: String,
+        #[arg(required_unless_present = "stdin")]
+        source: Option<PathBuf>,
+        /// Read rows from stdin
+        #[arg(long, conflicts_with = "source")]
+        stdin: bool,
+        /// Whether the input starts with a header row
+        /// [default: auto, skipping a row the same as the table's header]
+        #[arg(long, value_enum, default_value = "auto", hide_default_value = true)]
+        headers: ImportHeaders,
+    },
+    /// Write the result of a query to a file, e.g. a new table
+    /// in the data directory
+    Export {
+        query: String,
+        out: PathBuf,
+        /// [default: from the file's extension, or csv]
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+        /// Write only the column names, e.g. as a CSV header row
+        #[arg(long)]
+        header_only: bool,
+    },
+    /// Print metadata about every table as a JSON array
+    Inventory { subdir: Option<String> },
+    /// Show the type inferred for a column of these values
+    #[command(hide = true)]
+    InferType { values: Vec<String> },
+    /// Print a table's header row
+    Columns { table: String },
+    /// Show a table's columns and their types
+    Describe { table: String },
+    /// Check a table's file for structural problems: rows with an unusual
+    /// number of fields, inconsistent quoting and misplaced quotes.
+    /// Values aren't parsed.
+    Lint { table: String },
+    /// Check the configuration for problems, such as missing directories,
+    /// invalid patterns and conflicting options, without reading any tables
+    ConfigCheck,
+    /// Print the number of rows in a table
+    Count { table: String },
+    /// Show the rows with ids from `start` up to (not including) `end`,
+    /// reading no further into the table
+    Slice {
+        table: String,
+        start: usize,
+        end: usize,
+    },
+    /// List the views defined in the config, with their SQL
+    Views,
+    /// Print the name of the most recently modified table.
+    /// In queries, `subdir/@latest` refers to the same table.
+    Latest { subdir: Option<String> },
+    /// Count distinct values in a column
+    Values {
+        table: String,
+        column: String,
+        /// Estimate the number of distinct values in bounded memory
+        #[arg(long)]
+        approx: bool,
+    },
+    /// Print a histogram of a numeric column's values
+    Stats {
+        table: String,
+        column: String,
+        /// Number of equal-width bins in the histogram
+        #[arg(long, default_value_t = 10)]
+        bins: usize,
+    },
+    /// Print the rows of a table whose column matches a regex, as CSV
+    Grep {
+        table: String,
+        column: String,
+        pattern: String,
+    },
+    /// Write every table in a subdirectory to files in another directory,
+    /// keeping their relative paths
+    ExportAll {
+        subdir: Option<String>,
+        #[arg(long)]
+        out_dir: PathBuf,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+    },
+    /// Roll back mutations left incomplete by a crash,
+    /// using the write-ahead log (see the `wal` setting)
+    Recover,
+    /// Compare the columns and rows of two tables
+    Diff {
+        left: String,
+        right: String,
+        /// Match rows by this column, rather than by position
+        #[arg(long)]
+        key: Option<String>,
+    },
 }
 
 fn get_xdg_dirs() -> anyhow::Result<xdg::BaseDirectories> {
@@ -64,73 +352,710 @@ fn get_config<P: AsRef<Path>>(path: Option<P>) -> anyhow::Result<Config> {
     Ok(parsed_config)
 }
 
-fn print_payload(payload: Payload) {
+fn render_payload(payload: Payload, output: &Output) -> String {
     match payload {
         Payload::ShowColumns(cols) => {
-            print!("SHOW COLUMNS: ");
-            if let Some((last, most)) = cols.split_last() {
-                for col in most {
-                    print!("{} ({}), ", col.0, col.1);
-                }
-
-                println!("{} ({})", last.0, last.1);
-            }
+            let cols: Vec<_> = cols
+                .iter()
+                .map(|(name, typ)| format!("{} ({})", name, typ))
+                .collect();
+            format!("SHOW COLUMNS: {}", cols.join(", "))
         }
-        Payload::Create => println!("Created table"),
-        Payload::Insert(n) => println!("Inserted {} rows", n),
+        Payload::Create => "Created table".to_string(),
+        Payload::Insert(n) => format!("Inserted {} rows", n),
         Payload::Select { labels, rows } => {
             let mut table_builder = tabled::builder::Builder::new();
-            table_builder.set_columns(labels);
-            for row in rows {
-                table_builder.add_record(row.into_iter().map(format_value));
+            table_builder.set_columns(labels.clone());
+            for (i, row) in rows.into_iter().enumerate() {
+                if output.repeat_header.is_some_and(|n| i > 0 && i % n == 0) {
+                    table_builder.add_record(labels.clone());
+                }
+                table_builder.add_record(row.into_iter().map(|value| output.display_value(value)));
             }
 
             let mut table = table_builder.build();
 
             table.with(tabled::style::Style::modern());
 
-            println!("{}", table);
+            table.to_string()
+        }
+        Payload::Delete(n) => format!("Deleted {} rows", n),
+        Payload::Update(n) => format!("Updated {} rows", n),
+        Payload::DropTable => "Dropped table.".to_string(),
+    }
+}
+
+/// Render a statement's result in the chosen output format,
+/// without a trailing newline. `types` are the selected columns'
+/// declared types, where known, for typed JSON.
+fn render_output(payload: Payload, output: &Output, types: &[Option<DataType>]) -> String {
+    match output.format {
+        OutputFormat::Table if output.transpose => render_transposed(payload, output),
+        OutputFormat::Table => match &output.group_by {
+            Some(column) => render_grouped(payload, column, output),
+            None => render_payload(payload, output),
+        },
+        OutputFormat::Json => {
+            let json = if output.typed {
+                payload_json_typed(payload, types)
+            } else {
+                payload_json(payload)
+            };
+            output.json_string(&json)
+        }
+        OutputFormat::Html => match payload {
+            Payload::Select { labels, rows } => {
+                let table = html::render_rows(&labels, rows, output.html_style);
+                table.trim_end().to_string()
+            }
+            other => render_payload(other, output),
+        },
+        OutputFormat::Csv => match payload {
+            Payload::Select { labels, rows } => {
+                let csv = rows_csv(&labels, rows, output.out_delimiter);
+                csv.strip_suffix('\n').unwrap_or(&csv).to_string()
+            }
+            other => render_payload(other, output),
+        },
+    }
+}
+
+/// Render a single selected row as a two-column `field | value` table.
+/// Several rows are each rendered that way, separated by blank lines, if
+/// `output.transpose_rows` says so. Any other payload is rendered as usual.
+fn render_transposed(payload: Payload, output: &Output) -> String {
+    match payload {
+        Payload::Select { labels, rows }
+            if rows.len() == 1
+                || (!rows.is_empty() && output.transpose_rows == TransposeRows::Each) =>
+        {
+            let tables: Vec<_> = rows
+                .into_iter()
+                .map(|row| {
+                    let mut table_builder = tabled::builder::Builder::new();
+                    table_builder.set_columns(["field", "value"]);
+                    for (label, value) in labels.iter().zip(row) {
+                        table_builder.add_record([label.clone(), output.display_value(value)]);
+                    }
+
+                    let mut table = table_builder.build();
+
+                    table.with(tabled::style::Style::modern());
+
+                    table.to_string()
+                })
+                .collect();
+            tables.join("\n\n")
+        }
+        other => render_payload(other, output),
+    }
+}
+
+/// Render selected rows in sections, one for each distinct value of
+/// `column`, each headed by the value and showing the other columns.
+/// Sections are sorted by the value: numbers first, then text, then NULL.
+/// Results without such a column, or without rows, are rendered as usual.
+fn render_grouped(payload: Payload, column: &str, output: &Output) -> String {
+    let (mut labels, rows) = match payload {
+        Payload::Select { labels, rows } if !rows.is_empty() => (labels, rows),
+        other => return render_payload(other, output),
+    };
+    let index = match labels.iter().position(|label| label == column) {
+        Some(index) => index,
+        None => return render_payload(Payload::Select { labels, rows }, output),
+    };
+    let label = labels.remove(index);
+
+    // (value, as shown, rows), in order of first appearance
+    let mut groups: Vec<(Value, String, Vec<Vec<Value>>)> = Vec::new();
+    let mut group_index = HashMap::new();
+    for mut row in rows {
+        let value = row.remove(index);
+        let shown = output.display_value(value.clone());
+        let i = *group_index.entry(shown.clone()).or_insert_with(|| {
+            groups.push((value, shown, Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].2.push(row);
+    }
+
+    let number = |value: &Value| f64::try_from(value).ok();
+    groups.sort_by(|(a, a_shown, _), (b, b_shown, _)| {
+        let is_null = |value: &Value| matches!(value, Value::Null);
+        is_null(a)
+            .cmp(&is_null(b))
+            .then_with(|| match (number(a), number(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            })
+            .then_with(|| a_shown.cmp(b_shown))
+    });
+
+    let sections: Vec<_> = groups
+        .into_iter()
+        .map(|(_value, shown, rows)| {
+            let labels = labels.clone();
+            let table = render_payload(Payload::Select { labels, rows }, output);
+            format!("{}: {}\n{}", label, shown, table)
+        })
+        .collect();
+    sections.join("\n\n")
+}
+
+/// Columns not named anywhere in a SELECT can't affect its result,
+/// so their values needn't be parsed. Wildcards and mutating statements,
+/// which write back whole rows, get no hint.
+fn projection_hint(statement: &Statement) -> Option<HashSet<String>> {
+    match statement {
+        Statement::Query(_) => referenced_columns(statement),
+        _ => None,
+    }
+}
+
+/// The identifiers a statement refers to, in lower case, or `None` if it
+/// has a wildcard. The AST is walked in its serialized form, so that no
+/// kind of expression (e.g. a function argument) can be missed.
+fn referenced_columns(statement: &Statement) -> Option<HashSet<String>> {
+    let json = serde_json::to_value(statement).ok()?;
+    let mut columns = HashSet::new();
+    collect_identifiers(&json, &mut columns).then_some(columns)
+}
+
+/// Add the identifiers within `json` to `columns`, returning false
+/// if there's a wildcard, e.g. `SELECT *` or `SELECT t.*`
+fn collect_identifiers(json: &serde_json::Value, columns: &mut HashSet<String>) -> bool {
+    use serde_json::Value as JsonValue;
+
+    match json {
+        JsonValue::Object(fields) => fields.iter().all(|(key, value)| match (&**key, value) {
+            ("Identifier", JsonValue::String(name)) => {
+                columns.insert(name.to_lowercase());
+                true
+            }
+            ("CompoundIdentifier", compound) => {
+                if let Some(JsonValue::String(name)) = compound.get("ident") {
+                    columns.insert(name.to_lowercase());
+                }
+                true
+            }
+            ("QualifiedWildcard", _) => false,
+            _ => collect_identifiers(value, columns),
+        }),
+        JsonValue::Array(items) => items
+            .iter()
+            .all(|item| item != "Wildcard" && collect_identifiers(item, columns)),
+        _ => true,
+    }
+}
+
+/// The first statement of a query which deletes or overwrites data, if
+/// any, as SQL. Queries which can't be parsed have none; running them
+/// will fail anyway.
+fn destructive_statement(query: &str) -> Option<String> {
+    let sql = match outfile::split_outfile(query) {
+        Some((sql, _path, _format)) => sql,
+        None => query,
+    };
+    let statements = parse(sql).ok()?;
+    statements
+        .into_iter()
+        .find(|statement| match statement {
+            SqlStatement::Delete { .. }
+            | SqlStatement::Drop { .. }
+            | SqlStatement::Truncate { .. }
+            | SqlStatement::Update {
+                selection: None, ..
+            } => true,
+            SqlStatement::AlterTable { operation, .. } => {
+                matches!(operation, AlterTableOperation::DropColumn { .. })
+            }
+            _ => false,
+        })
+        .map(|statement| statement.to_string())
+}
+
+/// Refuse a query which deletes or overwrites data unless it's been
+/// confirmed, including writing over an existing `INTO OUTFILE` file
+fn check_confirmed(query: &str, output: &Output, confirmed: bool) -> anyhow::Result<()> {
+    if let Some(statement) = destructive_statement(query).filter(|_| !confirmed) {
+        bail!(
+            "{:?} deletes or overwrites data; pass --confirm to run it",
+            statement
+        );
+    }
+    match outfile::split_outfile(query) {
+        Some((_sql, path, _format)) => check_overwrite(&path, output.overwrite, confirmed),
+        None => Ok(()),
+    }
+}
+
+/// Refuse to overwrite an existing file unless it's been confirmed
+fn check_overwrite(path: &Path, overwrite: bool, confirmed: bool) -> anyhow::Result<()> {
+    if overwrite && !confirmed && path.exists() {
+        bail!(
+            "overwriting {:?} deletes its contents; pass --confirm to do it",
+            path
+        );
+    }
+    Ok(())
+}
+
+/// Run the `query` command, writing its output to `out` or piping it
+/// to a shell command, once it's been checked with `check_confirmed`
+async fn query_command(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    pipe: Option<&str>,
+    output: &Output,
+    confirmed: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    check_confirmed(query, output, confirmed)?;
+    match pipe {
+        Some(command) => {
+            let mut pipe = Pipe::spawn(command)?;
+            let written = write_query(glue, query, output, &mut pipe.stdin).await;
+            pipe.finish(written)
+        }
+        None => write_query(glue, query, output, out).await,
+    }
+}
+
+/// A simple `SELECT ... LIMIT n` (with no filtering, ordering, grouping or
+/// joins) needs only the first `n` rows (plus any OFFSET) of its table.
+fn scan_limit(statement: &Statement) -> Option<usize> {
+    let query = match statement {
+        Statement::Query(query) => query,
+        _ => return None,
+    };
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        SetExpr::Values(_) => return None,
+    };
+
+    let literal = |expr: &Option<Expr>| match expr {
+        Some(Expr::Literal(AstLiteral::Number(n))) => n.to_string().parse::<usize>().ok(),
+        _ => None,
+    };
+    let limit = literal(&query.limit)?;
+    let offset = match query.offset {
+        Some(_) => literal(&query.offset)?,
+        None => 0,
+    };
+
+    let simple_projection = select.projection.iter().all(|item| match item {
+        SelectItem::Expr { expr, .. } => {
+            matches!(expr, Expr::Identifier(_) | Expr::CompoundIdentifier { .. })
         }
-        Payload::Delete(n) => println!("Deleted {} rows", n),
-        Payload::Update(n) => println!("Updated {} rows", n),
-        Payload::DropTable => println!("Dropped table."),
+        SelectItem::QualifiedWildcard(_) | SelectItem::Wildcard => true,
+    });
+    let single_table =
+        select.from.joins.is_empty() && matches!(select.from.relation, TableFactor::Table { .. });
+
+    let unfiltered = select.selection.is_none()
+        && select.group_by.is_empty()
+        && select.having.is_none()
+        && select.order_by.is_empty();
+
+    (simple_projection && single_table && unfiltered).then_some(limit + offset)
+}
+
+/// Parse each statement in a query, like `Glue::plan` does before planning,
+/// but allowing `glob('pattern')` tables (see `glob_table::rewrite`)
+/// and configured views (see `views::Views::rewrite`). Statements are
+/// planned as they're run, since earlier ones may change the tables.
+fn parse_query(glue: &Glue<CsvStore>, query: &str) -> anyhow::Result<Vec<Statement>> {
+    let store = glue.storage.as_ref().expect("no underlying storage??");
+
+    let mut statements = Vec::new();
+    for mut parsed in parse(query).sendify()?? {
+        store.views().rewrite(&mut parsed)?;
+        glob_table::rewrite(&mut parsed)?;
+        statements.push(translate(&parsed).sendify()??);
     }
+
+    Ok(statements)
 }
 
-async fn handle_query(glue: &mut Glue<CsvStore>, query: &str) -> anyhow::Result<()> {
-    let statements = glue.plan(query).await.sendify()??;
+/// Run each statement in the query, writing its rendered output to `out`
+/// as soon as it's done, so that an error in a later statement doesn't
+/// lose the output of earlier ones. A trailing `INTO OUTFILE 'path'`
+/// writes the result of a single SELECT to a file instead
+/// (see `outfile::split_outfile`).
+async fn write_query(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    output: &Output,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    match outfile::split_outfile(query) {
+        Some((sql, path, format)) => {
+            let outfile = Outfile {
+                path,
+                format,
+                header_only: false,
+                overwrite: output.overwrite,
+            };
+            run_query_into(glue, sql, output, Some(outfile), out).await
+        }
+        None => run_query_into(glue, query, output, None, out).await,
+    }
+}
+
+/// Run each statement in the query, returning the rendered output
+#[cfg(test)]
+async fn run_query(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    output: &Output,
+) -> anyhow::Result<String> {
+    let mut out = Vec::new();
+    write_query(glue, query, output, &mut out).await?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// Whether REPL input makes up whole statements, ending in a `;`
+/// which isn't within quotes
+fn statement_complete(text: &str) -> bool {
+    let mut quote = None;
+    let mut last = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            last = Some(c);
+        }
+    }
+
+    quote.is_none() && last == Some(';')
+}
+
+/// Write the result of a query to a file, in the given format or else
+/// the one its extension implies, or CSV. Existing files are only
+/// overwritten if `force` is set.
+async fn export_query(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    out: PathBuf,
+    format: Option<ExportFormat>,
+    force: bool,
+    header_only: bool,
+    output: &Output,
+) -> anyhow::Result<String> {
+    let format = format
+        .or_else(|| ExportFormat::from_path(&out))
+        .unwrap_or(ExportFormat::Csv);
+    let outfile = Outfile {
+        path: out,
+        format,
+        header_only,
+        overwrite: force,
+    };
+    let mut out = Vec::new();
+    run_query_into(glue, query, output, Some(outfile), &mut out).await?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// Run each statement in the query, writing the rendered output of each
+/// to `out` once it's done, or writing the result of a single SELECT
+/// to `outfile` if given
+async fn run_query_into(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    output: &Output,
+    outfile: Option<Outfile>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    if let Some(outfile) = &outfile {
+        if outfile.path.exists() && !outfile.overwrite {
+            bail!(
+                "{:?} already exists; use --force to overwrite it",
+                outfile.path
+            );
+        }
+    }
+    let statements = parse_query(glue, query)?;
+    if outfile.is_some() && statements.len() != 1 {
+        bail!("only a single statement's result can be written to a file");
+    }
 
     for statement in statements {
-        let payload = glue.execute_stmt_async(&statement).await.sendify()??;
+        let store = glue.storage.as_ref().expect("no underlying storage??");
+        let statement = plan(store, statement).await.sendify()??;
+        let hint = projection_hint(&statement);
+        let gutter_statement = match (output.gutter, output.format) {
+            (true, OutputFormat::Table) if outfile.is_none() => with_rowid_gutter(&statement),
+            _ => None,
+        };
+        let store = glue.storage.as_ref().expect("no underlying storage??");
+        let cache_key = match &gutter_statement {
+            None => store.aggregate_cache_key(&statement)?,
+            Some(_) => None,
+        };
+        let cached = cache_key
+            .as_ref()
+            .zip(store.aggregate_cache())
+            .and_then(|(key, cache)| cache.get(key));
+        if let Some(store) = glue.storage.as_mut() {
+            store.set_projection_hint(hint);
+            store.set_partition_bounds(Some(&statement));
+            // Only the labels are needed for a header
+            match outfile.as_ref().is_some_and(|outfile| outfile.header_only) {
+                true => store.set_scan_limit(Some(0)),
+                false => store.set_scan_limit(scan_limit(&statement)),
+            }
+            store.set_rowid_column(gutter_statement.is_some());
+        }
+        let result = match cached {
+            Some(payload) => Ok(payload),
+            None => {
+                glue.execute_stmt_async(gutter_statement.as_ref().unwrap_or(&statement))
+                    .await
+            }
+        };
+        if let Some(store) = glue.storage.as_mut() {
+            store.set_projection_hint(None);
+            store.set_partition_bounds(None);
+            store.set_scan_limit(None);
+            store.set_rowid_column(false);
+            store.end_statement();
+        }
+        let mut payload = result.sendify()??;
 
-        print_payload(payload);
+        let store = glue.storage.as_ref().expect("no underlying storage??");
+        if let (Some(key), Some(cache)) = (cache_key, store.aggregate_cache()) {
+            if let Err(err) = cache.put(key, &payload) {
+                eprintln!("warning: not caching aggregate: {:#}", err);
+            }
+        }
+        // An explicit list of columns is shown in the order it gives
+        let display_order = source_table(&statement)
+            .filter(|_| selects_all(&statement))
+            .and_then(|table| store.display_order(table));
+        if let Some(order) = display_order {
+            payload = reorder_columns(payload, order);
+        }
+        // By the labels the statement gave them, before any renames
+        let types = match (output.typed, source_table(&statement), &payload) {
+            (true, Some(table), Payload::Select { labels, .. }) => {
+                let declared = declared_types(&statement, &store.describe_table(table)?);
+                labels
+                    .iter()
+                    .map(|label| declared.get(label).cloned())
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        if !output.rename.is_empty() {
+            payload = rename_columns(payload, &output.rename);
+        }
+        if let Some(aliases) =
+            source_table(&statement).and_then(|table| store.column_aliases(table))
+        {
+            payload = relabel_table_columns(payload, aliases, &statement);
+        }
+        if let Some(table) = source_table(&statement) {
+            if let Some(names) = store.original_column_names(table)? {
+                payload = relabel_table_columns(payload, &names, &statement);
+            }
+        }
+        if gutter_statement.is_some() {
+            payload = gutter_payload(payload);
+        }
+
+        if let Some(outfile) = &outfile {
+            store.check_writable_file(&outfile.path)?;
+            let nrows = outfile::write_payload(payload, outfile, output.out_delimiter)?;
+            writeln!(out, "Wrote {} rows to {:?}", nrows, outfile.path)?;
+            out.flush()?;
+            continue;
+        }
+
+        if output.value {
+            if let Some(value) = single_value(&payload, output)? {
+                writeln!(out, "{}", value)?;
+                out.flush()?;
+                continue;
+            }
+        }
+
+        writeln!(out, "{}", render_output(payload, output, &types))?;
+        out.flush()?;
+    }
+
+    let store = glue.storage.as_ref().expect("no underlying storage??");
+    if let Some((n, path)) = store.take_quarantined() {
+        eprintln!("Quarantined {} unreadable rows to {:?}", n, path);
     }
 
     Ok(())
 }
 
+async fn handle_query(
+    glue: &mut Glue<CsvStore>,
+    query: &str,
+    output: &Output,
+) -> anyhow::Result<()> {
+    write_query(glue, query, output, &mut std::io::stdout()).await
+}
+
+/// A shell command to write output to, through its stdin.
+/// The command inherits our stdout and stderr.
+struct Pipe {
+    command: String,
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl Pipe {
+    fn spawn(command: &str) -> anyhow::Result<Self> {
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("spawning {:?}", command))?;
+        let stdin = child.stdin.take().expect("child stdin should be piped");
+
+        Ok(Self {
+            command: command.to_owned(),
+            child,
+            stdin,
+        })
+    }
+
+    /// Close the command's stdin, so it sees EOF, and wait for it to exit.
+    /// `written` is the outcome of writing to it, which fails if the command
+    /// stopped reading early (e.g. `head`), but that's fine.
+    fn finish(mut self, written: anyhow::Result<()>) -> anyhow::Result<()> {
+        drop(self.stdin);
+        let status = self.child.wait()?;
+
+        if let Err(err) = written {
+            let broken_pipe = err.chain().any(|cause| {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+            });
+            if !broken_pipe {
+                return Err(err);
+            }
+        }
+        if !status.success() {
+            bail!("{:?} exited with {}", self.command, status);
+        }
+
+        Ok(())
+    }
+}
+
 /// Special commands, starting with `.` at the repl
-fn handle_command(glue: &mut Glue<CsvStore>, command: &str) -> anyhow::Result<()> {
-    let store = glue.storage.as_ref().expect("no underlying storage??");
+/// The REPL's `.` commands: name, arguments and description,
+/// as listed by `.help`
+const DOT_COMMANDS: &[(&str, &str, &str)] = &[
+    ("help", "", "Show this list"),
+    (
+        "tree",
+        "[subdir]",
+        "Show the tables in a directory as a tree",
+    ),
+    ("list", "[subdir]", "List the tables in a directory"),
+    ("columns", "<table>", "Print a table's header row"),
+    (
+        "describe",
+        "<table>",
+        "Show a table's columns and their types",
+    ),
+    (
+        "slice",
+        "<table> <start> <end>",
+        "Show the rows with ids from start up to (not including) end",
+    ),
+    ("flush", "", "Write edits held in overlay mode to disk"),
+    ("discard", "", "Drop edits held in overlay mode"),
+    ("gutter", "on|off", "Show each row's id before it"),
+];
+
+/// How a `.` command is used, e.g. `.columns <table>`
+fn dot_usage(name: &str) -> String {
+    match DOT_COMMANDS.iter().find(|(command, _, _)| *command == name) {
+        Some((_, args, _)) if !args.is_empty() => format!(".{} {}", name, args),
+        _ => format!(".{}", name),
+    }
+}
+
+/// The `.help` listing
+fn render_dot_help() -> String {
+    let usages: Vec<_> = DOT_COMMANDS
+        .iter()
+        .map(|(name, _, _)| dot_usage(name))
+        .collect();
+    let width = usages.iter().map(String::len).max().unwrap_or_default();
+    let lines: Vec<_> = usages
+        .iter()
+        .zip(DOT_COMMANDS)
+        .map(|(usage, (_, _, description))| format!("{:width$}  {}", usage, description))
+        .collect();
+    lines.join("\n")
+}
+
+fn handle_command(
+    glue: &mut Glue<CsvStore>,
+    command: &str,
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    let store = glue.storage.as_mut().expect("no underlying storage??");
     let words: Vec<_> = command.split_whitespace().collect();
     if let Some((first, rest)) = words.split_first() {
         match *first {
             "tree" => {
-                let subdir = rest.first().map(|&s| s);
+                let subdir = rest.first().copied();
                 print_tree(subdir, store)?;
             }
             "list" => {
-                let subdir = rest.first().map(|&s| s);
-                print_list(subdir, store)?;
+                let subdir = rest.first().copied();
+                print_list(subdir, store, output)?;
+            }
+            "columns" => match rest.first() {
+                Some(table) => println!("{}", store.header_line(table)?),
+                None => bail!("Usage: {}", dot_usage("columns")),
+            },
+            "slice" => match rest {
+                [table, start, end] => {
+                    let (start, end) = (start.parse()?, end.parse()?);
+                    println!("{}", render_slice(store, table, start, end, output)?);
+                }
+                _ => bail!("Usage: {}", dot_usage("slice")),
+            },
+            "describe" => match rest.first() {
+                Some(table) => println!("{}", render_describe(store, table, output)?),
+                None => bail!("Usage: {}", dot_usage("describe")),
+            },
+            "flush" => {
+                let ntables = store.flush()?;
+                println!("Flushed edits to {} tables", ntables);
             }
-            "help" => {
-                // TODO: Automate this
-                println!("Current options:");
-                println!("* .help");
-                println!("* .tree <subdir>");
-                println!("* .list <subdir>");
+            "discard" => {
+                let ntables = store.discard()?;
+                println!("Discarded edits to {} tables", ntables);
+            }
+            "gutter" => match rest.first().copied() {
+                Some("on") => output.gutter = true,
+                Some("off") => output.gutter = false,
+                _ => bail!("Usage: {}", dot_usage("gutter")),
+            },
+            "help" => println!("{}", render_dot_help()),
+            other => {
+                let names: Vec<_> = DOT_COMMANDS.iter().map(|(name, _, _)| *name).collect();
+                bail!(
+                    "Unrecognized command {:?}; try one of .{} (see .help)",
+                    other,
+                    names.join(", .")
+                )
             }
-            other => bail!("Unrecognized command {:?}", other),
         };
     } else {
         bail!("No command following `.`");
@@ -152,6 +1077,7 @@ fn add_node_to_tree(
     store: &CsvStore,
     tree: &mut TreeBuilder,
     node: TableNode,
+    ancestors: &mut Vec<PathBuf>,
 ) -> anyhow::Result<()> {
     // Last component of name
     let mut last_name = node.name.last().unwrap_or("/".to_string());
@@ -161,11 +1087,14 @@ fn add_node_to_tree(
         }
         TableData::Dir => {
             // TODO: don't parse schema for every file
-            let subtables = store.list_tables(node.name)?;
             last_name.push('/');
             tree.begin_child(last_name);
-            for subtable in subtables {
-                add_node_to_tree(store, tree, subtable)?;
+            if let Some((canonical, subtables)) = store.list_within(node.name, ancestors)? {
+                ancestors.push(canonical);
+                for subtable in subtables {
+                    add_node_to_tree(store, tree, subtable, ancestors)?;
+                }
+                ancestors.pop();
             }
             tree.end_child();
         }
@@ -175,13 +1104,16 @@ fn add_node_to_tree(
 }
 
 fn build_table_tree(store: &CsvStore, sub_name: TableName) -> anyhow::Result<StringItem> {
-    let tables = store.list_tables(sub_name.clone())?;
+    let (canonical, tables) = store
+        .list_within(sub_name.clone(), &[])?
+        .expect("a directory can't be its own ancestor");
 
     let tree_title: TableIdentifier = sub_name.try_into()?;
     let mut tree = TreeBuilder::new(tree_title.to_string());
 
+    let mut ancestors = vec![canonical];
     for node in tables {
-        add_node_to_tree(&store, &mut tree, node)?;
+        add_node_to_tree(store, &mut tree, node, &mut ancestors)?;
     }
 
     Ok(tree.build())
@@ -194,14 +1126,14 @@ fn print_tree(subdir: Option<&str>, store: &CsvStore) -> anyhow::Result<()> {
     );
     let sub_name: TableName = sub_id.try_into()?;
 
-    let tree = build_table_tree(&store, sub_name)?;
+    let tree = build_table_tree(store, sub_name)?;
 
     ptree::print_tree(&tree)?;
 
     Ok(())
 }
 
-fn print_list(subdir: Option<&str>, store: &CsvStore) -> anyhow::Result<()> {
+fn print_list(subdir: Option<&str>, store: &CsvStore, output: &Output) -> anyhow::Result<()> {
     let sub_id = TableIdentifier::new(
         subdir.unwrap_or_default().to_owned(),
         store.data_dir.clone(),
@@ -210,6 +1142,23 @@ fn print_list(subdir: Option<&str>, store: &CsvStore) -> anyhow::Result<()> {
 
     let tables = store.list_tables(sub_name)?;
 
+    if output.format == OutputFormat::Json {
+        let entries: Vec<_> = tables
+            .into_iter()
+            .map(|node| {
+                let kind = if node.is_table() {
+                    "table"
+                } else {
+                    "directory"
+                };
+                let table_id: TableIdentifier = node.name.try_into()?;
+                Ok(serde_json::json!({ "name": &*table_id, "kind": kind }))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        println!("{}", output.json_string(&serde_json::Value::Array(entries)));
+        return Ok(());
+    }
+
     for node in tables {
         let table_id: TableIdentifier = node.name.try_into()?;
         match node.data {
@@ -221,46 +1170,323 @@ fn print_list(subdir: Option<&str>, store: &CsvStore) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The rows of a table in a range of row ids, in the chosen output format
+fn render_slice(
+    store: &CsvStore,
+    table: &str,
+    start: usize,
+    end: usize,
+    output: &Output,
+) -> anyhow::Result<String> {
+    let (labels, rows) = store.slice_rows(table, start, end)?;
+    let types = match output.typed {
+        true => {
+            let columns = store.describe_table(table)?;
+            labels
+                .iter()
+                .map(|label| {
+                    columns
+                        .iter()
+                        .find(|(name, _)| name == label)
+                        .map(|(_, typ)| typ.clone())
+                })
+                .collect()
+        }
+        false => Vec::new(),
+    };
+    Ok(render_output(
+        Payload::Select { labels, rows },
+        output,
+        &types,
+    ))
+}
+
+/// A table's columns and their types, as a table or JSON
+fn render_describe(store: &CsvStore, table: &str, output: &Output) -> anyhow::Result<String> {
+    let columns = store.describe_table(table)?;
+    if output.format == OutputFormat::Json {
+        let json = payload_json(Payload::ShowColumns(columns));
+        return Ok(output.json_string(&json));
+    }
+
+    let mut table_builder = tabled::builder::Builder::new();
+    table_builder.set_columns(["column", "type"]);
+    for (name, data_type) in columns {
+        table_builder.add_record([name, data_type.to_string()]);
+    }
+    let mut table = table_builder.build();
+    table.with(tabled::style::Style::modern());
+
+    Ok(table.to_string())
+}
+
+fn print_latest(subdir: Option<&str>, store: &CsvStore) -> anyhow::Result<()> {
+    let sub_id = TableIdentifier::new(
+        subdir.unwrap_or_default().to_owned(),
+        store.data_dir.clone(),
+    );
+    let sub_name: TableName = sub_id.try_into()?;
+
+    match store.latest_table(sub_name)? {
+        Some(name) => {
+            let table_id: TableIdentifier = name.try_into()?;
+            println!("{}", &*table_id);
+            Ok(())
+        }
+        None => bail!("no tables in {:?}", subdir.unwrap_or_default()),
+    }
+}
+
+fn print_values(store: &CsvStore, table: &str, column: &str, approx: bool) -> anyhow::Result<()> {
+    let values = store.column_values(table, column)?;
+
+    if approx {
+        let estimate = stats::approx_distinct(values)?;
+        let margin = estimate * stats::approx_error_rate();
+        println!("~{:.0} distinct values (± {:.0})", estimate, margin);
+    } else {
+        let tally = stats::tally_values(values)?;
+
+        let mut table_builder = tabled::builder::Builder::new();
+        table_builder.set_columns([column, "count"]);
+        for (value, count) in tally {
+            table_builder.add_record([value, count.to_string()]);
+        }
+
+        let mut table = table_builder.build();
+        table.with(tabled::style::Style::modern());
+
+        println!("{}", table);
+    }
+
+    Ok(())
+}
+
+/// Width of the longest bar in a histogram
+const HISTOGRAM_WIDTH: usize = 40;
+
+fn print_histogram(store: &CsvStore, table: &str, column: &str, bins: usize) -> anyhow::Result<()> {
+    let hist = stats::histogram(|| store.column_values(table, column), bins)
+        .with_context(|| format!("column {:?} of table {:?}", column, table))?;
+    let Some(hist) = hist else {
+        bail!("no values in column {:?} of table {:?}", column, table);
+    };
+
+    let starts: Vec<_> = hist.bin_starts().map(|x| format!("{}", x)).collect();
+    let label_width = starts.iter().map(String::len).max().unwrap_or_default();
+    let most = hist.counts.iter().copied().max().unwrap_or_default().max(1);
+    for (start, count) in starts.iter().zip(&hist.counts) {
+        let bar = "#".repeat(count * HISTOGRAM_WIDTH / most);
+        println!("{:>width$} | {} {}", start, bar, count, width = label_width);
+    }
+    println!("max {}", hist.max);
+
+    Ok(())
+}
+
+fn print_lint(store: &CsvStore, table: &str) -> anyhow::Result<()> {
+    let report = store.lint_table(table)?;
+    match report.modal_width() {
+        Some(width) => println!("{} records; most have {} fields", report.records, width),
+        None => println!("no records"),
+    }
+    let ragged = report.ragged();
+    for (line, width) in &ragged {
+        println!("line {}: {} fields", line, width);
+    }
+    if report.ragged_count() > ragged.len() {
+        println!("... and {} more", report.ragged_count() - ragged.len());
+    }
+    for column in report.mixed_quoting() {
+        println!("column {}: quoted in some rows but not others", column);
+    }
+    for line in &report.stray_quotes {
+        println!("line {}: stray quote", line);
+    }
+    if let Some(line) = report.unterminated {
+        println!("line {}: quoted field is never closed", line);
+    }
+
+    if !report.is_clean() {
+        bail!("found structural problems in {:?}", table);
+    }
+    Ok(())
+}
+
+/// Print each problem with the configuration, failing if there are any
+fn print_config_check(config: &Config) -> anyhow::Result<()> {
+    let problems = config.validate();
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    if !problems.is_empty() {
+        bail!("found {} problems in the configuration", problems.len());
+    }
+    println!("no problems found");
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let opts = Opts::parse();
+async fn main() {
+    let opts = Opts::try_parse().unwrap_or_else(|err| exit_unparsed(err));
+    let json_errors = opts.json_errors;
 
-    let config = get_config(opts.config.as_ref())?;
+    let level = if opts.verbose {
+        log::LevelFilter::Trace
+    } else {
+        log::LevelFilter::Warn
+    };
+    // Other crates' logs (e.g. the SQL parser's) are mostly noise
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .filter_module(module_path!(), level)
+        .parse_default_env()
+        .init();
+
+    if let Err(err) = run(opts).await {
+        if json_errors {
+            eprintln!("{}", error_json(&err));
+        } else {
+            eprintln!("Error: {:?}", err);
+        }
+        std::process::exit(ErrorKind::of(&err).exit_code());
+    }
+}
+
+/// Exit after a command line which isn't a command to run. Help and
+/// version exit 0; usage errors exit with `ErrorKind::Usage`'s code, and
+/// are described as JSON with `--json-errors`.
+fn exit_unparsed(err: clap::Error) -> ! {
+    if !err.use_stderr() {
+        err.exit();
+    }
+    if std::env::args_os().any(|arg| arg == "--json-errors") {
+        eprintln!("{}", error_json(&err.into()));
+    } else {
+        // The error can't be reported if stderr is closed
+        let _ = err.print();
+    }
+    std::process::exit(ErrorKind::Usage.exit_code());
+}
+
+async fn run(opts: Opts) -> anyhow::Result<()> {
+    let mut config = get_config(opts.config.as_ref()).context(ErrorKind::Config)?;
+    config.overlay |= opts.overlay;
+    config.literal_paths |= opts.literal_paths;
+    config.progress |= opts.progress;
+    if opts.quarantine.is_some() {
+        config.quarantine = opts.quarantine;
+    }
+
+    // Before the store is created, which fails at the first problem
+    if let Command::ConfigCheck = opts.command {
+        return print_config_check(&config).context(ErrorKind::Config);
+    }
 
     // TODO: Parse during Opts::parse
     let history_file = get_or_create_data_file("history.txt")?;
 
-    let store = CsvStore::try_new(config)?;
+    if opts.json_typed && opts.format != OutputFormat::Json {
+        bail!("--json-typed only applies to --format json");
+    }
+    let mut output = Output {
+        format: opts.format,
+        pretty: opts.pretty,
+        typed: opts.json_typed,
+        transpose: opts.transpose,
+        transpose_rows: config.transpose_rows,
+        gutter: opts.gutter,
+        value: opts.value,
+        null_string: config.null_string.clone(),
+        html_style: opts.html_style,
+        rename: opts.rename.unwrap_or_default(),
+        repeat_header: opts.repeat_header.map(|n| n as usize),
+        out_delimiter: opts.out_delimiter.unwrap_or(b','),
+        group_by: opts.group_by,
+        overwrite: false,
+    };
+
+    let confirmed = opts.confirm || !config.require_confirm_destructive;
+    let store = CsvStore::try_new(config).context(ErrorKind::Config)?;
     let mut glue = Glue::new(store);
 
     match opts.command {
         Command::Repl => {
-            let mut repl = rustyline::Editor::<()>::new()?;
+            let mut repl = rustyline::Editor::<SqlHelper>::new()?;
+            repl.set_helper(Some(SqlHelper::default()));
             if repl.load_history(&history_file).is_err() {
                 println!("No previous history.");
             }
+            // Lines of a statement read so far, until it ends in `;`
+            let mut statement = String::new();
+            // Whether the hint on how to exit has been shown
+            let mut interrupted = false;
             loop {
-                let readline = repl.readline("> ");
+                if statement.is_empty() {
+                    // Tables may have been created or dropped since the last statement
+                    let store = glue.storage.as_ref().expect("No underlying storage??");
+                    if let (Ok(tables), Some(helper)) =
+                        (completion::table_identifiers(store), repl.helper_mut())
+                    {
+                        helper.tables = tables;
+                    }
+                }
+                let prompt = if statement.is_empty() { "> " } else { "...> " };
+                let readline = repl.readline(prompt);
 
                 match readline {
                     Ok(line) => {
-                        repl.add_history_entry(line.as_str());
-                        repl.save_history(&history_file)?;
-
-                        if let Some(command) = line.strip_prefix('.') {
-                            if let Err(err) = handle_command(&mut glue, &command) {
-                                eprintln!("{:#}", err);
+                        if statement.is_empty() {
+                            if let Some(command) = line.strip_prefix('.') {
+                                repl.add_history_entry(line.as_str());
+                                repl.save_history(&history_file)?;
+                                if let Err(err) = handle_command(&mut glue, command, &mut output) {
+                                    eprintln!("{:#}", err);
+                                }
+                                println!();
+                                continue;
+                            }
+                            if line.trim().is_empty() {
+                                continue;
                             }
                         } else {
-                            if let Err(err) = handle_query(&mut glue, &line).await {
-                                eprintln!("{:#}", err);
+                            statement.push('\n');
+                        }
+                        statement.push_str(&line);
+                        if !statement_complete(&statement) {
+                            continue;
+                        }
+
+                        let query = std::mem::take(&mut statement);
+                        repl.add_history_entry(query.as_str());
+                        repl.save_history(&history_file)?;
+                        let run = match destructive_statement(&query).filter(|_| !confirmed) {
+                            Some(destructive) => {
+                                let prompt = format!(
+                                    "{} deletes or overwrites data; run it? [y/N] ",
+                                    destructive
+                                );
+                                let answer = repl.readline(&prompt).unwrap_or_default();
+                                matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
                             }
+                            None => true,
+                        };
+                        if !run {
+                            eprintln!("Cancelled");
+                        } else if let Err(err) = handle_query(&mut glue, &query, &output).await {
+                            eprintln!("{:#}", err);
+                        }
+                    }
+                    // Cancel the statement being typed, rather than exiting
+                    Err(ReadlineError::Interrupted) => {
+                        statement.clear();
+                        if !interrupted {
+                            eprintln!("(press Ctrl-D to exit)");
+                            interrupted = true;
                         }
+                        continue;
                     }
-                    // Err(ReadlineError::Interrupted) => {
-                    //     eprintln!("CTRL-C");
-                    //     break;
-                    // }
                     Err(ReadlineError::Eof) => {
                         eprintln!("CTRL-D");
                         break;
@@ -272,15 +1498,216 @@ async fn main() -> anyhow::Result<()> {
                 }
                 println!();
             }
+
+            let store = glue.storage.as_ref().expect("No underlying storage??");
+            let pending = store.pending_edits();
+            if pending > 0 {
+                eprintln!("Discarding unflushed edits to {} tables", pending);
+            }
+        }
+        Command::Query { query, pipe, force } => {
+            output.overwrite = force;
+            let mut stdout = std::io::stdout();
+            query_command(
+                &mut glue,
+                &query,
+                pipe.as_deref(),
+                &output,
+                confirmed,
+                &mut stdout,
+            )
+            .await?;
+        }
+        Command::Export {
+            query,
+            out,
+            format,
+            force,
+            header_only,
+        } => {
+            check_confirmed(&query, &output, confirmed)?;
+            check_overwrite(&out, force, confirmed)?;
+            let rendered =
+                export_query(&mut glue, &query, out, format, force, header_only, &output).await?;
+            print!("{}", rendered);
         }
-        Command::Query { query } => handle_query(&mut glue, &query).await?,
         Command::Tree { subdir } => {
             let store = glue.storage.expect("No underlying storage??");
             print_tree(subdir.as_deref(), &store)?;
         }
         Command::List { subdir } => {
             let store = glue.storage.expect("No underlying storage??");
-            print_list(subdir.as_deref(), &store)?;
+            print_list(subdir.as_deref(), &store, &output)?;
+        }
+        Command::Import {
+            source,
+            table,
+            columns_from,
+            headers,
+            schema_file,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let imported = store.import_table(
+                &source,
+                &table,
+                columns_from.as_deref(),
+                headers,
+                schema_file.as_deref(),
+            )?;
+            if headers == Some(ImportHeaders::Auto) {
+                match imported.header {
+                    true => println!("Detected a header row"),
+                    false => println!("Detected no header row"),
+                }
+            }
+            println!("Imported {} rows", imported.rows);
+        }
+        Command::Replace {
+            table,
+            source,
+            allow_schema_change,
+        } => {
+            if !confirmed {
+                bail!(
+                    "replacing {:?} deletes its rows; pass --confirm to do it",
+                    table
+                );
+            }
+            let store = glue.storage.expect("No underlying storage??");
+            let nrows = store.replace_table(&source, &table, allow_schema_change)?;
+            println!("Replaced {:?} with {} rows", table, nrows);
+        }
+        Command::Reindex { table, key } => {
+            let store = glue.storage.expect("No underlying storage??");
+            match store.reindex_table(&table, key.as_deref())? {
+                true => println!("Reordered the rows of {:?}", table),
+                false => println!("{:?} was already in order", table),
+            }
+        }
+        Command::Load {
+            table,
+            source,
+            stdin: _,
+            headers,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let nrows = match source {
+                Some(source) => {
+                    let file = std::fs::File::open(&source)
+                        .with_context(|| format!("opening {:?}", source))?;
+                    store.load_csv(&table, file, headers)?
+                }
+                None => store.load_csv(&table, std::io::stdin().lock(), headers)?,
+            };
+            println!("Loaded {} rows", nrows);
+        }
+        Command::Merge { glob, into } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let (sources, nrows) = store.merge_tables(&glob, &into)?;
+            println!("Merged {} rows from {} tables", nrows, sources.len());
+        }
+        Command::Insert { table, json } => {
+            let json = if json == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                json
+            };
+            let store = glue.storage.as_mut().expect("No underlying storage??");
+            let nrows = store.insert_json(&table, &json).await?;
+            if store.pending_edits() > 0 {
+                store.flush()?;
+            }
+            println!("Inserted {} rows", nrows);
+        }
+        Command::InferType { values } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let inferred = store.infer_type(&values);
+            println!("{} ({})", inferred.column_type, inferred.data_type);
+            for (value, typ) in inferred.widened_by {
+                println!("* {:?} widened to {}", value, typ);
+            }
+        }
+        Command::Inventory { subdir } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let sub_id = TableIdentifier::new(subdir.unwrap_or_default(), store.data_dir.clone());
+            let infos = store.inventory(sub_id.try_into()?)?;
+            println!("{}", output.json_string(&serde_json::to_value(infos)?));
+        }
+        Command::Columns { table } => {
+            let store = glue.storage.expect("No underlying storage??");
+            println!("{}", store.header_line(&table)?);
+        }
+        Command::Slice { table, start, end } => {
+            let store = glue.storage.expect("No underlying storage??");
+            println!("{}", render_slice(&store, &table, start, end, &output)?);
+        }
+        Command::ConfigCheck => unreachable!("the config is checked before creating the store"),
+        Command::Lint { table } => {
+            let store = glue.storage.expect("No underlying storage??");
+            print_lint(&store, &table)?;
+        }
+        Command::Count { table } => {
+            let store = glue.storage.expect("No underlying storage??");
+            println!("{}", store.count_rows(&table)?);
+        }
+        Command::Describe { table } => {
+            let store = glue.storage.expect("No underlying storage??");
+            println!("{}", render_describe(&store, &table, &output)?);
+        }
+        Command::Views => {
+            let store = glue.storage.expect("No underlying storage??");
+            for (name, sql) in store.views().iter() {
+                println!("{}: {}", name, sql);
+            }
+        }
+        Command::Latest { subdir } => {
+            let store = glue.storage.expect("No underlying storage??");
+            print_latest(subdir.as_deref(), &store)?;
+        }
+        Command::Values {
+            table,
+            column,
+            approx,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            print_values(&store, &table, &column, approx)?;
+        }
+        Command::Stats {
+            table,
+            column,
+            bins,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            print_histogram(&store, &table, &column, bins)?;
+        }
+        Command::Grep {
+            table,
+            column,
+            pattern,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let pattern = Regex::new(&pattern).context("parsing pattern")?;
+            store.grep_table(&table, &column, &pattern, std::io::stdout().lock())?;
+        }
+        Command::ExportAll {
+            subdir,
+            out_dir,
+            format,
+        } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let sub_id = TableIdentifier::new(subdir.unwrap_or_default(), store.data_dir.clone());
+            let written = store.export_all(sub_id.try_into()?, &out_dir, format)?;
+            println!("Exported {} tables to {:?}", written.len(), out_dir);
+        }
+        Command::Recover => {
+            let store = glue.storage.expect("No underlying storage??");
+            let nrecovered = store.recover()?;
+            println!("Rolled back {} incomplete mutations", nrecovered);
+        }
+        Command::Diff { left, right, key } => {
+            let store = glue.storage.expect("No underlying storage??");
+            let mut stdout = std::io::stdout().lock();
+            diff::diff_tables(&store, &left, &right, key.as_deref(), &mut stdout)?;
         }
     }
 
@@ -299,13 +1726,1005 @@ fn format_value(value: Value) -> String {
         Value::F64(x) => format!("{}", x),
         Value::Decimal(x) => format!("{}", x),
         Value::Bytea(x) => format!("{:?}", x),
-        Value::Date(_) => todo!(),
-        Value::Timestamp(_) => todo!(),
-        Value::Time(_) => todo!(),
-        Value::Interval(_) => todo!(),
-        Value::Uuid(_) => todo!(),
-        Value::Map(_) => todo!(),
-        Value::List(_) => todo!(),
+        Value::Date(x) => x.format(glue::DATE_FORMAT).to_string(),
+        Value::Timestamp(x) => x.format(glue::TIMESTAMP_FORMAT).to_string(),
+        Value::Time(x) => x.format(glue::TIME_FORMAT).to_string(),
+        Value::Interval(x) => String::from(&x),
+        // Canonical hyphenated form, e.g. 936da01f-9abd-4d9d-80c7-02af85c822a8
+        value @ Value::Uuid(_) => String::from(&value),
+        Value::Map(_) | Value::List(_) => value_json(value).to_string(),
         Value::Null => "NULL".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FloatToInt;
+
+    fn store_in(tmpdir: &tempdir::TempDir) -> CsvStore {
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
+            ..Config::default()
+        };
+        CsvStore::try_new(config).expect("CsvStore::try_new")
+    }
+
+    #[tokio::test]
+    async fn test_confirm_destructive() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let csv_path = tmpdir.path().join("t.csv");
+        std::fs::write(&csv_path, "a\n1\n2\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output::default();
+        let mut out = Vec::new();
+
+        query_command(
+            &mut glue,
+            "SELECT * FROM t; UPDATE t SET a = 3 WHERE a = 1",
+            None,
+            &output,
+            false,
+            &mut out,
+        )
+        .await
+        .expect("harmless");
+        for query in [
+            "DELETE FROM t WHERE a = 1",
+            "SELECT * FROM t; DROP TABLE t",
+            "TRUNCATE TABLE t",
+            "UPDATE t SET a = 0",
+        ] {
+            let err = query_command(&mut glue, query, None, &output, false, &mut out)
+                .await
+                .expect_err(query);
+            assert!(err.to_string().contains("--confirm"), "{}", err);
+        }
+        assert_eq!(
+            std::fs::read_to_string(&csv_path).expect("read"),
+            "a\n3\n2\n"
+        );
+
+        let query = "DELETE FROM t WHERE a = 3";
+        query_command(&mut glue, query, None, &output, true, &mut out)
+            .await
+            .expect("confirmed");
+        assert_eq!(std::fs::read_to_string(&csv_path).expect("read"), "a\n2\n");
+
+        // Overwriting a file with --force needs confirming too
+        let out_path = tmpdir.path().join("out.csv");
+        std::fs::write(&out_path, "keep\n").expect("write");
+        let query = format!("SELECT a FROM t INTO OUTFILE {:?}", out_path);
+        let query = query.replace('"', "'");
+        let forced = Output {
+            overwrite: true,
+            ..Output::default()
+        };
+        query_command(&mut glue, &query, None, &forced, false, &mut out)
+            .await
+            .expect_err("unconfirmed overwrite");
+        assert_eq!(std::fs::read_to_string(&out_path).expect("read"), "keep\n");
+        query_command(&mut glue, &query, None, &forced, true, &mut out)
+            .await
+            .expect("confirmed overwrite");
+        assert_eq!(std::fs::read_to_string(&out_path).expect("read"), "a\n2\n");
+    }
+
+    #[tokio::test]
+    async fn test_referenced_columns() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "a,b,ab\n1,2,3\n").expect("write");
+        let glue = Glue::new(store_in(&tmpdir));
+
+        let columns = |names: &[&str]| Some(names.iter().map(|name| name.to_string()).collect());
+
+        // `ab` is referenced, but `a` and `b` aren't, despite being substrings
+        for (query, expected) in [
+            ("SELECT ab FROM t", columns(&["ab"])),
+            (
+                "SELECT t.a AS total FROM t WHERE UPPER(b) = 'X'",
+                columns(&["a", "b"]),
+            ),
+            ("SELECT * FROM t", None),
+            ("SELECT t.* FROM t", None),
+            ("DELETE FROM t WHERE a = 1", None),
+        ] {
+            let statements = parse_query(&glue, query).expect("parse");
+            assert_eq!(projection_hint(&statements[0]), expected, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_not_found_error() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let mut glue = Glue::new(store_in(&tmpdir));
+
+        let err = run_query(&mut glue, "SELECT * FROM missing", &Output::default())
+            .await
+            .expect_err("missing table");
+        assert_eq!(ErrorKind::of(&err).exit_code(), 4);
+
+        let json = error_json(&err);
+        assert_eq!(json["code"], 4);
+        assert_eq!(json["kind"], "table_not_found");
+        assert!(json["message"]
+            .as_str()
+            .expect("message")
+            .contains("missing"));
+    }
+
+    #[tokio::test]
+    async fn test_gutter_shows_rowids() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(
+            tmpdir.path().join("people.csv"),
+            "name,age\ncarol,40\nalice,30\n",
+        )
+        .expect("write table");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            gutter: true,
+            ..Output::default()
+        };
+
+        run_query(&mut glue, "INSERT INTO people VALUES ('bob', 35)", &output)
+            .await
+            .expect("insert");
+        let rendered = run_query(&mut glue, "SELECT * FROM people ORDER BY name", &output)
+            .await
+            .expect("select");
+
+        let rows: Vec<Vec<_>> = rendered
+            .lines()
+            .filter(|line| line.contains(|c: char| c.is_alphanumeric()))
+            .map(|line| {
+                line.split('│')
+                    .map(str::trim)
+                    .filter(|cell| !cell.is_empty())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["#", "name", "age"],
+                vec!["1", "alice", "30"],
+                vec!["2", "bob", "35"],
+                vec!["0", "carol", "40"],
+            ],
+            "{}",
+            rendered
+        );
+    }
+
+    #[tokio::test]
+    async fn test_select_to_outfile() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(
+            tmpdir.path().join("people.csv"),
+            "name,age\ncarol,40\nalice,30\n",
+        )
+        .expect("write table");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let out_path = tmpdir.path().join("out dir").join("old people.csv");
+        std::fs::create_dir(out_path.parent().expect("parent")).expect("create out dir");
+
+        let query = format!(
+            "SELECT name FROM people WHERE age > 35 INTO OUTFILE '{}'",
+            out_path.display()
+        );
+        let rendered = run_query(&mut glue, &query, &Output::default())
+            .await
+            .expect("select to file");
+        assert!(rendered.starts_with("Wrote 1 rows"), "{}", rendered);
+        let written = std::fs::read_to_string(&out_path).expect("read outfile");
+        assert_eq!(written, "name\ncarol\n");
+
+        // An existing file is only replaced with --force
+        let query = format!(
+            "SELECT name FROM people INTO OUTFILE '{}'",
+            out_path.display()
+        );
+        let err = run_query(&mut glue, &query, &Output::default())
+            .await
+            .expect_err("file exists");
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&out_path).expect("read"), written);
+        let output = Output {
+            overwrite: true,
+            ..Output::default()
+        };
+        run_query(&mut glue, &query, &output)
+            .await
+            .expect("overwrite");
+        let written = std::fs::read_to_string(&out_path).expect("read outfile");
+        assert_eq!(written, "name\ncarol\nalice\n");
+
+        // A comparison with a file name is left alone
+        let rendered = run_query(
+            &mut glue,
+            "SELECT name FROM people WHERE age > 35 > 'x.csv'",
+            &Output::default(),
+        )
+        .await;
+        assert!(!tmpdir.path().join("x.csv").exists(), "{:?}", rendered);
+    }
+
+    #[tokio::test]
+    async fn test_json_typed() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "a,b\n1,\n2,\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Json,
+            typed: true,
+            ..Output::default()
+        };
+
+        // Types come from the table, even without any values to go by
+        let rendered = run_query(&mut glue, "SELECT * FROM t WHERE a > 5", &output)
+            .await
+            .expect("empty");
+        assert_eq!(
+            rendered.trim(),
+            r#"{"columns":[{"name":"a","type":"Int32"},{"name":"b","type":"Int32"}],"rows":[]}"#
+        );
+
+        let rendered = run_query(
+            &mut glue,
+            "SELECT b, CAST(a AS TEXT) AS s, a + 1 AS n FROM t",
+            &output,
+        )
+        .await
+        .expect("select");
+        assert_eq!(
+            rendered.trim(),
+            concat!(
+                r#"{"columns":[{"name":"b","type":"Int32"},{"name":"s","type":"Text"},"#,
+                r#"{"name":"n","type":"Int"}],"rows":[[null,"1",2],[null,"2",3]]}"#
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_display_order() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "a,b,c\n1,2,3\n").expect("write");
+        let config = Config {
+            data_dir: tmpdir.path().to_str().expect("path conversion").to_owned(),
+            display_order: [("t".to_string(), vec!["c".to_string(), "a".to_string()])].into(),
+            ..Config::default()
+        };
+        let mut glue = Glue::new(CsvStore::try_new(config).expect("CsvStore::try_new"));
+        let output = Output {
+            format: OutputFormat::Csv,
+            ..Output::default()
+        };
+
+        let rendered = run_query(&mut glue, "SELECT * FROM t", &output)
+            .await
+            .expect("all");
+        assert_eq!(rendered, "c,a,b\n3,1,2\n");
+
+        // Explicitly listed columns are shown as listed
+        let rendered = run_query(&mut glue, "SELECT b, a FROM t", &output)
+            .await
+            .expect("listed");
+        assert_eq!(rendered, "b,a\n2,1\n");
+    }
+
+    #[tokio::test]
+    async fn test_glob_table() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let events = tmpdir.path().join("events");
+        std::fs::create_dir(&events).expect("create dir");
+        std::fs::write(events.join("2022-12.csv"), "id,kind\n1,old\n").expect("write");
+        std::fs::write(events.join("2023-01.csv"), "id,kind\n2,a\n3,b\n").expect("write");
+        std::fs::write(events.join("2023-02.csv"), "id,kind\n4,a\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Json,
+            ..Output::default()
+        };
+
+        let rendered = run_query(
+            &mut glue,
+            "SELECT COUNT(*) AS n FROM glob('events/2023-*')",
+            &output,
+        )
+        .await
+        .expect("count");
+        assert_eq!(rendered.trim(), r#"[{"n":3}]"#);
+
+        std::fs::write(events.join("2023-03.csv"), "id,note\n5,x\n").expect("write");
+        run_query(&mut glue, "SELECT * FROM glob('events/2023-*')", &output)
+            .await
+            .expect_err("mismatched columns");
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_cache() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let table = tmpdir.path().join("big.csv");
+        std::fs::write(&table, "id,ts\n1,2024-01-01\n2,2024-03-01\n").expect("write table");
+        let config = Config {
+            data_dir: tmpdir.path().to_string_lossy().into(),
+            aggregate_cache: true,
+            ..Config::default()
+        };
+        let mut glue = Glue::new(CsvStore::try_new(config).expect("CsvStore::try_new"));
+        let output = Output {
+            format: OutputFormat::Json,
+            ..Output::default()
+        };
+        let hits = |glue: &Glue<CsvStore>| {
+            let store = glue.storage.as_ref().expect("storage");
+            store.aggregate_cache().expect("cache").hits()
+        };
+        let query = "SELECT COUNT(*) AS n, MAX(ts) AS latest FROM big";
+
+        let rendered = run_query(&mut glue, query, &output)
+            .await
+            .expect("first aggregate");
+        assert_eq!(rendered.trim(), r#"[{"latest":"2024-03-01","n":2}]"#);
+        assert_eq!(hits(&glue), 0);
+        let repeated = run_query(&mut glue, query, &output)
+            .await
+            .expect("repeat aggregate");
+        assert_eq!(repeated, rendered);
+        assert_eq!(hits(&glue), 1);
+
+        // Not a simple aggregate, so never cached
+        for _ in 0..2 {
+            run_query(&mut glue, "SELECT COUNT(*) FROM big WHERE id > 1", &output)
+                .await
+                .expect("filtered aggregate");
+        }
+        assert_eq!(hits(&glue), 1);
+
+        std::fs::write(&table, "id,ts\n1,2024-01-01\n2,2024-03-01\n3,2024-05-01\n")
+            .expect("rewrite table");
+        let rendered = run_query(&mut glue, query, &output)
+            .await
+            .expect("aggregate after a change");
+        assert_eq!(rendered.trim(), r#"[{"latest":"2024-05-01","n":3}]"#);
+        assert_eq!(hits(&glue), 1);
+
+        // Rounding fractions in an integer column differently changes the sum
+        std::fs::write(tmpdir.path().join("nums.csv"), "n\n1\n2.5\n").expect("write table");
+        let query = "SELECT SUM(n) AS total FROM nums";
+        for (float_to_int, expected) in [
+            (FloatToInt::Truncate, r#"[{"total":3}]"#),
+            (FloatToInt::Round, r#"[{"total":4}]"#),
+        ] {
+            let config = Config {
+                data_dir: tmpdir.path().to_string_lossy().into(),
+                aggregate_cache: true,
+                inference_sample_rows: 1,
+                float_to_int,
+                ..Config::default()
+            };
+            let mut glue = Glue::new(CsvStore::try_new(config).expect("CsvStore::try_new"));
+            let rendered = run_query(&mut glue, query, &output)
+                .await
+                .expect("aggregate");
+            assert_eq!(rendered.trim(), expected);
+            assert_eq!(hits(&glue), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_views() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(
+            tmpdir.path().join("orders.csv"),
+            "id,total\n1,50\n2,150\n3,300\n",
+        )
+        .expect("write table");
+        let views = |defs: &[(&str, &str)]| Config {
+            data_dir: tmpdir.path().to_string_lossy().into(),
+            views: defs
+                .iter()
+                .map(|(name, sql)| (name.to_string(), sql.to_string()))
+                .collect(),
+            ..Config::default()
+        };
+        let config = views(&[
+            ("big", "SELECT id, total FROM orders WHERE total > 100"),
+            ("huge", "SELECT id FROM big WHERE total > 200"),
+        ]);
+        let mut glue = Glue::new(CsvStore::try_new(config).expect("CsvStore::try_new"));
+        let output = Output {
+            format: OutputFormat::Json,
+            ..Output::default()
+        };
+
+        let rendered = run_query(&mut glue, "SELECT id FROM big ORDER BY id", &output)
+            .await
+            .expect("select from view");
+        assert_eq!(rendered.trim(), r#"[{"id":2},{"id":3}]"#);
+        let rendered = run_query(
+            &mut glue,
+            "SELECT h.id FROM huge h JOIN orders o ON h.id = o.id",
+            &output,
+        )
+        .await
+        .expect("join view of a view");
+        assert_eq!(rendered.trim(), r#"[{"id":3}]"#);
+
+        let recursive = views(&[("a", "SELECT * FROM b"), ("b", "SELECT * FROM a")]);
+        let err = CsvStore::try_new(recursive).err().expect("recursive views");
+        assert!(format!("{:#}", err).contains("defined in terms of itself"));
+        assert!(CsvStore::try_new(views(&[("bad", "DELETE FROM orders")])).is_err());
+    }
+
+    #[test]
+    fn test_format_temporal_values() {
+        use gluesql::core::chrono::{NaiveDate, NaiveTime};
+        use gluesql::core::data::Interval;
+
+        let date = NaiveDate::from_ymd_opt(2023, 1, 15).expect("date");
+        assert_eq!(format_value(Value::Date(date)), "2023-01-15");
+        assert_eq!(
+            format_value(Value::Timestamp(
+                date.and_hms_opt(9, 5, 30).expect("timestamp")
+            )),
+            "2023-01-15 09:05:30"
+        );
+        assert_eq!(
+            format_value(Value::Time(
+                NaiveTime::from_hms_opt(9, 5, 30).expect("time")
+            )),
+            "09:05:30"
+        );
+        assert_eq!(
+            format_value(Value::Interval(Interval::Month(14))),
+            r#""1-2" YEAR TO MONTH"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_value_output() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "n\n1\n2\n3\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            value: true,
+            ..Output::default()
+        };
+
+        let rendered = run_query(&mut glue, "SELECT COUNT(*) FROM t", &output)
+            .await
+            .expect("count");
+        assert_eq!(rendered, "3\n");
+
+        run_query(&mut glue, "SELECT * FROM t", &output)
+            .await
+            .expect_err("several values");
+    }
+
+    #[tokio::test]
+    async fn test_csv_output() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(
+            tmpdir.path().join("t.csv"),
+            "id,note\n1,\"a, b\"\n2,\n3,\"say \"\"hi\"\"\"\n",
+        )
+        .expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Csv,
+            ..Output::default()
+        };
+
+        let rendered = run_query(&mut glue, "SELECT * FROM t WHERE id > 0", &output)
+            .await
+            .expect("select");
+        assert_eq!(rendered, "id,note\n1,\"a, b\"\n2,\n3,\"say \"\"hi\"\"\"\n");
+    }
+
+    #[tokio::test]
+    async fn test_html_output() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "id,note\n1,<b>\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Html,
+            ..Output::default()
+        };
+
+        let rendered = run_query(&mut glue, "SELECT id, note FROM t", &output)
+            .await
+            .expect("select");
+        assert!(
+            rendered.contains("<tr><th>id</th><th>note</th></tr>"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.contains("<tr><td>1</td><td>&lt;b&gt;</td></tr>"),
+            "{}",
+            rendered
+        );
+
+        let path = tmpdir.path().join("out.html");
+        let query = format!("SELECT * FROM t INTO OUTFILE '{}'", path.display());
+        run_query(&mut glue, &query, &Output::default())
+            .await
+            .expect("write html");
+        let written = std::fs::read_to_string(&path).expect("read");
+        assert!(written.starts_with("<table>"), "{}", written);
+    }
+
+    #[test]
+    fn test_repeat_header() {
+        let payload = || Payload::Select {
+            labels: vec!["id".to_string(), "name".to_string()],
+            rows: (0..25)
+                .map(|i| vec![Value::I32(i), Value::Str("x".to_string())])
+                .collect(),
+        };
+        let headers = |rendered: String| rendered.lines().filter(|l| l.contains("name")).count();
+
+        assert_eq!(headers(render_payload(payload(), &Output::default())), 1);
+        let output = Output {
+            repeat_header: Some(10),
+            ..Output::default()
+        };
+        // Before rows 10 and 20, but not after the last row
+        assert_eq!(headers(render_payload(payload(), &output)), 3);
+    }
+
+    #[test]
+    fn test_describe() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "id,score,note\n1,2.5,a\n").expect("write");
+        let store = store_in(&tmpdir);
+
+        let rendered = render_describe(&store, "t", &Output::default()).expect("describe");
+        let lines: Vec<_> = rendered.lines().collect();
+        assert!(lines[1].contains("column") && lines[1].contains("type"));
+        assert!(
+            lines[3].contains("id") && lines[3].contains("INT"),
+            "{}",
+            rendered
+        );
+        assert!(lines[5].contains("score") && lines[5].contains("FLOAT"));
+        assert!(lines[7].contains("note") && lines[7].contains("TEXT"));
+
+        let output = Output {
+            format: OutputFormat::Json,
+            ..Output::default()
+        };
+        let rendered = render_describe(&store, "t", &output).expect("describe");
+        assert!(
+            rendered.starts_with(r#"{"columns":[{"name":"id","#),
+            "{}",
+            rendered
+        );
+
+        assert!(render_describe(&store, "missing", &output).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rename_columns() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "cst_nm,amt\nann,5\nbob,7\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Json,
+            rename: parse_renames("cst_nm=customer, amt=amount").expect("parse"),
+            ..Output::default()
+        };
+
+        let rendered = run_query(
+            &mut glue,
+            "SELECT cst_nm, amt FROM t WHERE amt > 6",
+            &output,
+        )
+        .await
+        .expect("select");
+        assert_eq!(rendered.trim(), r#"[{"amount":7,"customer":"bob"}]"#);
+
+        let path = tmpdir.path().join("out.csv");
+        let query = format!("SELECT cst_nm FROM t INTO OUTFILE '{}'", path.display());
+        run_query(&mut glue, &query, &output)
+            .await
+            .expect("write csv");
+        let written = std::fs::read_to_string(&path).expect("read");
+        assert_eq!(written, "customer\nann\nbob\n");
+
+        assert!(parse_renames("cst_nm").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_select_labels_in_exports() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "a,b,total\n1,2,0\n3,4,0\n").expect("write");
+        let config = Config {
+            data_dir: tmpdir.path().to_string_lossy().into(),
+            column_aliases: [(
+                "t".to_string(),
+                [("total".to_string(), "Stored Total".to_string())].into(),
+            )]
+            .into(),
+            ..Config::default()
+        };
+        let mut glue = Glue::new(CsvStore::try_new(config).expect("CsvStore::try_new"));
+
+        let path = tmpdir.path().join("out.csv");
+        let query = format!(
+            "SELECT a + b AS total, a * b FROM t INTO OUTFILE '{}'",
+            path.display()
+        );
+        run_query(&mut glue, &query, &Output::default())
+            .await
+            .expect("write csv");
+        let written = std::fs::read_to_string(&path).expect("read");
+        assert_eq!(written, "total,a * b\n3,2\n7,12\n");
+
+        let output = Output {
+            format: OutputFormat::Json,
+            ..Output::default()
+        };
+        let rendered = run_query(&mut glue, "SELECT a + b AS total FROM t", &output)
+            .await
+            .expect("select alias");
+        assert_eq!(rendered.trim(), r#"[{"total":3},{"total":7}]"#);
+        let rendered = run_query(&mut glue, "SELECT total FROM t LIMIT 1", &output)
+            .await
+            .expect("select column");
+        assert_eq!(rendered.trim(), r#"[{"Stored Total":0}]"#);
+    }
+
+    #[test]
+    fn test_dot_commands() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let mut output = Output::default();
+
+        // Each listed command is handled, even if it fails for lack of arguments
+        for (name, _, _) in DOT_COMMANDS {
+            if let Err(err) = handle_command(&mut glue, name, &mut output) {
+                assert!(!err.to_string().contains("Unrecognized"), "{}", name);
+            }
+        }
+        let err = handle_command(&mut glue, "describe", &mut output).expect_err("usage");
+        assert_eq!(err.to_string(), "Usage: .describe <table>");
+        let err = handle_command(&mut glue, "nope", &mut output).expect_err("unknown");
+        assert!(err.to_string().contains(".slice"), "{}", err);
+
+        let help = render_dot_help();
+        assert_eq!(help.lines().count(), DOT_COMMANDS.len());
+        assert!(help.contains(".gutter on|off"), "{}", help);
+    }
+
+    #[test]
+    fn test_statement_complete() {
+        assert!(statement_complete("SELECT 1;"));
+        assert!(statement_complete("SELECT *\nFROM t\nWHERE a = 1;  \n"));
+        assert!(statement_complete("SELECT 'it''s;';"));
+        assert!(!statement_complete("SELECT *\nFROM t"));
+        assert!(!statement_complete("SELECT 'a;"));
+        assert!(!statement_complete("SELECT ';'"));
+        assert!(!statement_complete(""));
+    }
+
+    #[tokio::test]
+    async fn test_export_query() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "id,kind\n1,a\n2,b\n3,a\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output::default();
+        let query = "SELECT id FROM t WHERE kind = 'a'";
+
+        let out = tmpdir.path().join("a_ids.csv");
+        export_query(&mut glue, query, out.clone(), None, false, false, &output)
+            .await
+            .expect("export");
+        assert_eq!(std::fs::read_to_string(&out).expect("read"), "id\n1\n3\n");
+        let rendered = run_query(&mut glue, "SELECT COUNT(*) FROM a_ids", &output)
+            .await
+            .expect("query the new table");
+        assert!(rendered.contains('2'), "{}", rendered);
+
+        let query = "SELECT id FROM t WHERE kind = 'b'";
+        export_query(&mut glue, query, out.clone(), None, false, false, &output)
+            .await
+            .expect_err("exists");
+        let format = Some(ExportFormat::Json);
+        export_query(&mut glue, query, out.clone(), format, true, false, &output)
+            .await
+            .expect("overwrite");
+        assert_eq!(
+            std::fs::read_to_string(&out).expect("read"),
+            "[{\"id\":2}]\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_out_delimiter() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "id,note\n1,\"a;b\"\n2,c\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            out_delimiter: parse_out_delimiter(";").expect("delimiter"),
+            ..Output::default()
+        };
+        let query = "SELECT id, note FROM t ORDER BY id";
+
+        let out = tmpdir.path().join("semi.csv");
+        export_query(&mut glue, query, out.clone(), None, false, false, &output)
+            .await
+            .expect("export");
+        assert_eq!(
+            std::fs::read_to_string(&out).expect("read"),
+            "id;note\n1;\"a;b\"\n2;c\n"
+        );
+        let output = Output {
+            format: OutputFormat::Csv,
+            ..output
+        };
+        let rendered = run_query(&mut glue, query, &output).await.expect("query");
+        assert_eq!(rendered.trim(), "id;note\n1;\"a;b\"\n2;c");
+
+        // Only the header, in the same format
+        let out = tmpdir.path().join("header.csv");
+        export_query(&mut glue, query, out.clone(), None, false, true, &output)
+            .await
+            .expect("export header");
+        assert_eq!(std::fs::read_to_string(&out).expect("read"), "id;note\n");
+        let out = tmpdir.path().join("header.json");
+        export_query(&mut glue, query, out.clone(), None, false, true, &output)
+            .await
+            .expect("export header");
+        assert_eq!(
+            std::fs::read_to_string(&out).expect("read"),
+            "[\"id\",\"note\"]\n"
+        );
+
+        assert_eq!(parse_out_delimiter("\\t").expect("tab"), b'\t');
+        assert!(parse_out_delimiter("\"").is_err());
+        assert!(parse_out_delimiter(";;").is_err());
+        assert!(parse_out_delimiter("é").is_err());
+    }
+
+    #[test]
+    fn test_format_nested_values() {
+        let list = Value::List(vec![
+            Value::I64(1),
+            Value::Null,
+            Value::Str("a".to_string()),
+        ]);
+        assert_eq!(format_value(list.clone()), r#"[1,null,"a"]"#);
+
+        let map = Value::Map([("k".to_string(), Value::I64(1)), ("l".to_string(), list)].into());
+        assert_eq!(format_value(map), r#"{"k":1,"l":[1,null,"a"]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_date_values() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("events.csv"), "day\n2023-01-15\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+
+        let rendered = run_query(
+            &mut glue,
+            "SELECT CAST(day AS DATE) AS d FROM events",
+            &Output::default(),
+        )
+        .await
+        .expect("select");
+        assert!(rendered.contains("2023-01-15"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_render_grouped() {
+        let row = |category: &str, name: &str| {
+            vec![
+                Value::Str(category.to_string()),
+                Value::Str(name.to_string()),
+            ]
+        };
+        let payload = || Payload::Select {
+            labels: vec!["category".to_string(), "name".to_string()],
+            rows: vec![
+                row("veg", "leek"),
+                row("fruit", "fig"),
+                row("veg", "kale"),
+                row("fruit", "pear"),
+            ],
+        };
+
+        let rendered = render_grouped(payload(), "category", &Output::default());
+        let lines: Vec<_> = rendered.lines().collect();
+        // Each section: its header, then a grid of a header and two rows
+        assert_eq!(lines.len(), 2 * 8 + 1, "{}", rendered);
+        assert_eq!(lines[0], "category: fruit");
+        assert!(lines[2].contains("name") && !lines[2].contains("category"));
+        assert!(lines[4].contains("fig"));
+        assert!(lines[6].contains("pear"));
+        assert_eq!(lines[8], "");
+        assert_eq!(lines[9], "category: veg");
+        assert!(lines[13].contains("leek"));
+        assert!(lines[15].contains("kale"));
+
+        // An unknown column leaves the table as it is
+        assert_eq!(
+            render_grouped(payload(), "nope", &Output::default()),
+            render_payload(payload(), &Output::default())
+        );
+    }
+
+    #[test]
+    fn test_render_transposed() {
+        let labels = ["id", "name", "score", "note"].map(String::from).to_vec();
+        let row = vec![
+            Value::I32(7),
+            Value::Str("ann".to_string()),
+            Value::F64(2.5),
+            Value::Null,
+        ];
+        let payload = Payload::Select {
+            labels: labels.clone(),
+            rows: vec![row.clone()],
+        };
+
+        let rendered = render_transposed(payload, &Output::default());
+        let lines: Vec<_> = rendered.lines().collect();
+        // Header, 4 fields, and borders between/around them
+        assert_eq!(lines.len(), 11, "{}", rendered);
+        assert!(lines[1].contains("field") && lines[1].contains("value"));
+        assert!(lines[3].contains("id") && lines[3].contains('7'));
+        assert!(lines[9].contains("note") && lines[9].contains("NULL"));
+
+        let output = Output {
+            null_string: "∅".to_string(),
+            ..Output::default()
+        };
+        let payload = Payload::Select {
+            labels: labels.clone(),
+            rows: vec![row.clone()],
+        };
+        let rendered = render_transposed(payload, &output);
+        assert!(rendered.lines().nth(9).is_some_and(|l| l.contains('∅')));
+
+        // Multiple rows fall back to the usual grid
+        let payload = || Payload::Select {
+            labels: labels.clone(),
+            rows: vec![row.clone(), row.clone()],
+        };
+        assert!(render_transposed(payload(), &Output::default())
+            .lines()
+            .nth(1)
+            .is_some_and(|l| l.contains("score")));
+
+        // ...unless each is to be transposed
+        let output = Output {
+            transpose_rows: TransposeRows::Each,
+            ..Output::default()
+        };
+        let rendered = render_transposed(payload(), &output);
+        let tables: Vec<_> = rendered.split("\n\n").collect();
+        assert_eq!(tables.len(), 2, "{}", rendered);
+        assert!(tables
+            .iter()
+            .all(|table| table.lines().count() == 11 && table.contains("field")));
+    }
+
+    #[test]
+    fn test_scan_limit() {
+        let limit = |sql: &str| {
+            let parsed = gluesql::core::parse_sql::parse(sql).expect("parse");
+            let statement = gluesql::core::translate::translate(&parsed[0]).expect("translate");
+            scan_limit(&statement)
+        };
+
+        assert_eq!(limit("SELECT * FROM t LIMIT 10"), Some(10));
+        assert_eq!(limit("SELECT a, t.b FROM t LIMIT 10 OFFSET 5"), Some(15));
+        assert_eq!(limit("SELECT * FROM t"), None);
+        assert_eq!(limit("SELECT * FROM t WHERE a = 1 LIMIT 10"), None);
+        assert_eq!(limit("SELECT * FROM t ORDER BY a LIMIT 10"), None);
+        assert_eq!(limit("SELECT COUNT(*) FROM t LIMIT 1"), None);
+        assert_eq!(limit("SELECT * FROM t JOIN u ON t.a = u.a LIMIT 1"), None);
+    }
+
+    #[test]
+    fn test_config_error() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        let config_path = tmpdir.path().join("config.toml");
+        std::fs::write(&config_path, "data_dir = [not toml").expect("write config");
+
+        let err = get_config(Some(&config_path))
+            .context(ErrorKind::Config)
+            .expect_err("broken config");
+        assert_eq!(ErrorKind::of(&err).exit_code(), 3);
+
+        let json = error_json(&err);
+        assert_eq!(json["code"], 3);
+        assert_eq!(json["kind"], "config");
+    }
+
+    #[test]
+    fn test_usage_error() {
+        let err = Opts::try_parse_from(["feet", "--json-errors", "--no-such-flag", "query", "x"])
+            .expect_err("unknown flag");
+        assert!(err.use_stderr());
+        let json = error_json(&err.into());
+        assert_eq!(json["code"], 1);
+        assert_eq!(json["kind"], "usage");
+        assert!(json["message"]
+            .as_str()
+            .expect("message")
+            .contains("--no-such-flag"));
+
+        let help = Opts::try_parse_from(["feet", "--help"]).expect_err("help");
+        assert!(!help.use_stderr());
+    }
+
+    #[test]
+    fn test_stats_args() {
+        let opts = Opts::try_parse_from(["feet", "stats", "t", "n"]).expect("stats without --bins");
+        assert!(matches!(
+            opts.command,
+            Command::Stats { table, column, bins: 10 } if table == "t" && column == "n"
+        ));
+    }
+
+    fn pipe_bytes(command: &str, input: &[u8]) -> anyhow::Result<()> {
+        let mut pipe = Pipe::spawn(command)?;
+        let written = pipe.stdin.write_all(input).map_err(Into::into);
+        pipe.finish(written)
+    }
+
+    #[test]
+    fn test_pipe_to_command() {
+        let tmpdir = tempdir::TempDir::new("feet-pipe").expect("tmpdir");
+        let out_path = tmpdir.path().join("out.txt");
+        let command = format!("wc -l > {:?}", out_path);
+
+        pipe_bytes(&command, b"one\ntwo\nthree\n").expect("pipe");
+
+        let out = std::fs::read_to_string(out_path).expect("read output");
+        assert_eq!(out.trim(), "3");
+    }
+
+    #[test]
+    fn test_pipe_to_command_exits_early() {
+        // `true` never reads its stdin
+        let input = vec![b'x'; 1 << 20];
+        pipe_bytes("true", &input).expect("broken pipe should be ignored");
+    }
+
+    #[test]
+    fn test_pipe_to_command_failure() {
+        assert!(pipe_bytes("exit 3", b"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_output_before_error() {
+        let tmpdir = tempdir::TempDir::new("feet-main").expect("tmpdir");
+        std::fs::write(tmpdir.path().join("t.csv"), "a\n1\n").expect("write");
+        let mut glue = Glue::new(store_in(&tmpdir));
+        let output = Output {
+            format: OutputFormat::Csv,
+            ..Output::default()
+        };
+
+        // The first statement's output is written before the second fails
+        let mut out = Vec::new();
+        let query = "SELECT a FROM t; SELECT a FROM missing";
+        write_query(&mut glue, query, &output, &mut out)
+            .await
+            .expect_err("missing table");
+        assert_eq!(String::from_utf8(out).expect("utf-8"), "a\n1\n");
+
+        // Likewise through a pipe
+        let out_path = tmpdir.path().join("piped.txt");
+        let mut pipe = Pipe::spawn(&format!("cat > {:?}", out_path)).expect("spawn");
+        let written = write_query(&mut glue, query, &output, &mut pipe.stdin).await;
+        assert!(pipe.finish(written).is_err());
+        let piped = std::fs::read_to_string(out_path).expect("read");
+        assert_eq!(piped, "a\n1\n");
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use csv::StringRecord;
+use serde::Deserialize;
+
+use crate::glue::RecordIter;
+
+/// Name of the file which makes a directory a single table,
+/// made up of the parts it lists
+pub const FILE_NAME: &str = "_manifest.toml";
+
+/// Which parts of a sharded table start with a header row
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderPolicy {
+    /// Every part repeats the same header row
+    #[default]
+    EveryPart,
+    /// Only the first part has a header row
+    FirstPart,
+    /// No part has a header row; the manifest lists the `columns`
+    None,
+}
+
+/// A table whose rows are split across several CSV files, e.g.
+///
+/// ```toml
+/// parts = ["part-0001.csv", "part-0002.csv"]
+/// header = "every_part"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// The files holding the table's rows, in order,
+    /// relative to the manifest's directory
+    pub parts: Vec<PathBuf>,
+    #[serde(default)]
+    pub header: HeaderPolicy,
+    /// Column names, when `header = "none"`
+    pub columns: Option<Vec<String>>,
+}
+
+impl Manifest {
+    /// Read the manifest in a directory, if it has one
+    pub fn find(dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = dir.join(FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let bytes = std::fs::read(&path)?;
+        let manifest: Manifest =
+            toml::from_slice(&bytes).with_context(|| format!("parsing {:?}", path))?;
+        manifest
+            .validate()
+            .with_context(|| format!("invalid manifest {:?}", path))?;
+
+        Ok(Some(manifest))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.parts.is_empty() {
+            bail!("no parts are listed");
+        }
+        if let Some(part) = self.parts.iter().find(|part| part.is_absolute()) {
+            bail!("part {:?} must be relative to the manifest", part);
+        }
+        match (self.header, &self.columns) {
+            (HeaderPolicy::None, None) => bail!("columns are required with header = \"none\""),
+            (HeaderPolicy::EveryPart | HeaderPolicy::FirstPart, Some(_)) => {
+                bail!("columns are only allowed with header = \"none\"")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The table's column names, and the records of every part in order.
+    /// With `header = "every_part"`, each part's header must match the first.
+    pub fn open(&self, dir: &Path, delimiter: u8) -> anyhow::Result<(Vec<String>, RecordIter)> {
+        let paths: Vec<_> = self.parts.iter().map(|part| dir.join(part)).collect();
+        let headers = match &self.columns {
+            Some(columns) => columns.clone(),
+            None => {
+                let first = open_part(&paths[0], delimiter)?
+                    .next()
+                    .transpose()?
+                    .with_context(|| format!("{:?} has no header row", paths[0]))?;
+                first.iter().map(ToString::to_string).collect()
+            }
+        };
+
+        let header = self.header;
+        let expected = StringRecord::from(headers.clone());
+        let records = paths.into_iter().enumerate().flat_map(move |(i, path)| {
+            let has_header = match header {
+                HeaderPolicy::EveryPart => true,
+                HeaderPolicy::FirstPart => i == 0,
+                HeaderPolicy::None => false,
+            };
+            let records: RecordIter = match open_part(&path, delimiter) {
+                Ok(mut records) if has_header => match records.next() {
+                    Some(Ok(part_headers)) if part_headers != expected => {
+                        Box::new(std::iter::once(Err(anyhow::anyhow!(
+                            "columns of part {:?} ({:?}) don't match {:?}",
+                            path,
+                            part_headers,
+                            expected
+                        ))))
+                    }
+                    Some(Err(err)) => Box::new(std::iter::once(Err(err))),
+                    _ => records,
+                },
+                Ok(records) => records,
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            };
+            records
+        });
+
+        Ok((headers, Box::new(records)))
+    }
+}
+
+/// Every record of a part, including any header row
+fn open_part(path: &Path, delimiter: u8) -> anyhow::Result<RecordIter> {
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_path(path)
+        .with_context(|| format!("opening part {:?}", path))?;
+    let records = reader.into_records().map(|res| res.map_err(Into::into));
+
+    Ok(Box::new(records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_policies() {
+        let tmpdir = tempdir::TempDir::new("feet-manifest").expect("tmpdir");
+        let dir = tmpdir.path();
+        std::fs::write(dir.join("a.csv"), "id,name\n1,ann\n").expect("write");
+        std::fs::write(dir.join("b.csv"), "2,bob\n3,cy\n").expect("write");
+
+        let manifest = Manifest {
+            parts: vec!["a.csv".into(), "b.csv".into()],
+            header: HeaderPolicy::FirstPart,
+            columns: None,
+        };
+        let (headers, records) = manifest.open(dir, b',').expect("open");
+        let records: Vec<_> = records.collect::<anyhow::Result<_>>().expect("records");
+        assert_eq!(headers, vec!["id", "name"]);
+        assert_eq!(
+            records,
+            vec![
+                StringRecord::from(vec!["1", "ann"]),
+                StringRecord::from(vec!["2", "bob"]),
+                StringRecord::from(vec!["3", "cy"]),
+            ]
+        );
+
+        // b.csv's first row isn't a matching header
+        let manifest = Manifest {
+            header: HeaderPolicy::EveryPart,
+            ..manifest
+        };
+        let (_headers, records) = manifest.open(dir, b',').expect("open");
+        assert!(records.collect::<anyhow::Result<Vec<_>>>().is_err());
+
+        std::fs::write(dir.join(FILE_NAME), "parts = []\n").expect("write");
+        assert!(Manifest::find(dir).is_err());
+        assert!(Manifest::find(&dir.join("missing"))
+            .expect("find")
+            .is_none());
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+/// Marks the first line of a file as a modeline
+const PREFIX: &str = "# feet:";
+
+/// Parsing options set for a single file by its first line,
+/// e.g. `# feet: delimiter=\t has_headers=false`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modeline {
+    pub delimiter: Option<u8>,
+    pub has_headers: Option<bool>,
+}
+
+impl Modeline {
+    /// Parse a line, which is only a modeline if it starts with `# feet:`.
+    /// Options are space-separated `key=value` pairs.
+    pub fn parse(line: &str) -> anyhow::Result<Option<Self>> {
+        let options = match line.trim_end().strip_prefix(PREFIX) {
+            Some(options) => options,
+            None => return Ok(None),
+        };
+
+        let mut modeline = Self::default();
+        for option in options.split_whitespace() {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("expected key=value, found {:?}", option))?;
+            match key {
+                "delimiter" => modeline.delimiter = Some(parse_delimiter(value)?),
+                "has_headers" => {
+                    let has_headers = value
+                        .parse()
+                        .with_context(|| format!("invalid has_headers {:?}", value))?;
+                    modeline.has_headers = Some(has_headers);
+                }
+                other => bail!("unknown modeline option {:?}", other),
+            }
+        }
+
+        Ok(Some(modeline))
+    }
+
+    /// Read the modeline from the first line of a file, if it has one
+    pub fn read(path: &Path) -> anyhow::Result<Option<Self>> {
+        let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+        let mut first = String::new();
+        BufReader::new(file).read_line(&mut first)?;
+
+        Self::parse(&first).with_context(|| format!("{:?} modeline", path))
+    }
+}
+
+/// A single-byte delimiter, written literally or as `\t` or `tab`
+fn parse_delimiter(value: &str) -> anyhow::Result<u8> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => bail!("delimiter must be a single byte, not {:?}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_modeline() {
+        let parsed = Modeline::parse("# feet: delimiter=\\t has_headers=false\n").expect("parse");
+        assert_eq!(
+            parsed,
+            Some(Modeline {
+                delimiter: Some(b'\t'),
+                has_headers: Some(false),
+            })
+        );
+
+        assert_eq!(Modeline::parse("id,name").expect("parse"), None);
+        assert!(Modeline::parse("# feet: delimiter=;; ").is_err());
+        assert!(Modeline::parse("# feet: quote=\"").is_err());
+    }
+}
@@ -1,12 +1,17 @@
 use std::{convert::TryFrom, ops::Deref, path::PathBuf};
 
 use anyhow::bail;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::schema_file;
 
 /// Path of the corresponding file (w/ extension)
 #[derive(Debug, Clone)]
 pub struct TablePath {
     path: PathBuf,
     root: PathBuf,
+    /// Of the table's file, without the dot
+    extension: String,
 }
 
 /// As used in SQL queries
@@ -56,7 +61,6 @@ impl TryFrom<TablePath> for TableName {
                 };
                 let parts: Vec<_> = rel
                     .components()
-                    .into_iter()
                     .map(comp_to_str)
                     .collect::<anyhow::Result<_>>()?;
 
@@ -69,6 +73,23 @@ impl TryFrom<TablePath> for TableName {
     }
 }
 
+impl TableName {
+    /// Like `TryFrom<TablePath>`, but replacing any invalid UTF-8
+    /// in the path with U+FFFD. The name may not resolve back to the path.
+    pub fn from_path_lossy(table_path: TablePath) -> anyhow::Result<Self> {
+        match table_path.path.strip_prefix(&table_path.root) {
+            Ok(rel) => {
+                let parts = rel
+                    .components()
+                    .map(|comp| comp.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                Ok(Self::new(parts, table_path.root))
+            }
+            Err(_) => bail!("path is not in data directory"),
+        }
+    }
+}
+
 // Identifier <-> Name
 
 impl TryFrom<TableName> for TableIdentifier {
@@ -130,27 +151,145 @@ impl TableName {
     pub fn last(&self) -> Option<String> {
         self.parts.iter().last().cloned()
     }
+
+    /// Name of the table or directory `part` within this directory
+    pub fn join(mut self, part: String) -> Self {
+        self.parts.retain(|p| !p.is_empty());
+        self.parts.push(part);
+        self
+    }
+
+    /// The directory containing this table
+    pub fn parent(&self) -> Self {
+        let mut parts = self.parts.clone();
+        parts.pop();
+        Self::new(parts, self.root.clone())
+    }
+
+    /// Replace each part with the spelling used on disk by the
+    /// file or directory whose name has the same NFC form.
+    /// Parts with no such entry are left in NFC form.
+    /// Files are matched by their names without any of `extensions`.
+    pub fn resolve_unicode(self, extensions: &[String]) -> Self {
+        let mut dir = self.root.clone();
+        let mut parts = Vec::new();
+
+        for part in self.parts {
+            let wanted = nfc(&part);
+            let on_disk = std::fs::read_dir(&dir).ok().and_then(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.file_name().to_str().map(ToOwned::to_owned))
+                    .map(|fname| strip_extension(&fname, extensions).to_owned())
+                    .find(|stem| wanted == nfc(stem))
+            });
+            let resolved = on_disk.unwrap_or(wanted);
+
+            dir.push(&resolved);
+            parts.push(resolved);
+        }
+
+        Self::new(parts, self.root)
+    }
 }
 
-impl TablePath {
-    pub fn try_new(path: PathBuf, root: PathBuf) -> anyhow::Result<Self> {
-        if let Some(ext) = path.extension() {
-            if ext != "csv" {
-                bail!("table path with non-csv extension");
+/// Column names which can be used in SQL without quoting, made from raw
+/// CSV headers: a leading byte order mark and surrounding whitespace and
+/// quotes are removed, each run of other characters which aren't letters,
+/// digits or `_` becomes `_`, and a leading digit gets a `_` prefix, e.g.
+/// ` "First Name" ` -> `First_Name` and `2nd (alt)` -> `_2nd_alt`.
+/// A header left empty is named `colN` after its position, and a name
+/// that's already taken gets a `_2`, `_3`... suffix. SQL keywords
+/// (e.g. `order`) must still be quoted, as in `"order"`.
+pub fn column_identifiers(headers: &[String]) -> Vec<String> {
+    let mut identifiers: Vec<String> = Vec::with_capacity(headers.len());
+    for (i, header) in headers.iter().enumerate() {
+        let trimmed = header
+            .trim_start_matches('\u{feff}')
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+
+        let mut name = String::with_capacity(trimmed.len());
+        for c in trimmed.chars() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+            } else if !name.is_empty() && !name.ends_with('_') {
+                name.push('_');
             }
         }
-        let path = path.with_extension(""); // drop .csv
-        let new = Self { path, root };
+        let name = name.trim_end_matches('_');
+        let name = match name.chars().next() {
+            None => format!("col{}", i + 1),
+            Some(c) if c.is_ascii_digit() => format!("_{}", name),
+            Some(_) => name.to_owned(),
+        };
+
+        let mut unique = name.clone();
+        for n in 2.. {
+            if !identifiers.contains(&unique) {
+                break;
+            }
+            unique = format!("{}_{}", name, n);
+        }
+        identifiers.push(unique);
+    }
+
+    identifiers
+}
+
+/// A file name without the first of `extensions` it ends with, if any
+fn strip_extension<'a>(fname: &'a str, extensions: &[String]) -> &'a str {
+    extensions
+        .iter()
+        .find_map(|ext| fname.strip_suffix(ext.as_str())?.strip_suffix('.'))
+        .unwrap_or(fname)
+}
+
+/// Unicode NFC (canonical composition) form of a string
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+impl TablePath {
+    pub fn try_new(path: PathBuf, root: PathBuf) -> anyhow::Result<Self> {
+        // Drop .csv, leaving other dots (e.g. in directory names) alone
+        let path = match path.extension() {
+            Some(ext) if ext == "csv" => path.with_extension(""),
+            _ => path,
+        };
+        let extension = "csv".to_string();
+        let new = Self {
+            path,
+            root,
+            extension,
+        };
 
         Ok(new)
     }
 
-    pub fn as_csv(self) -> PathBuf {
-        self.path.with_extension("csv")
+    /// The same table, stored in a file with this extension (e.g. `tsv`)
+    pub fn with_file_extension(mut self, extension: &str) -> Self {
+        self.extension = extension.to_string();
+        self
+    }
+
+    /// The table's file, which holds CSV whatever its extension
+    pub fn as_csv(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".");
+        path.push(&self.extension);
+        path.into()
+    }
+
+    /// The table's schema file (see `schema_file::SUFFIX`)
+    pub fn as_schema_file(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(schema_file::SUFFIX);
+        path.into()
     }
 
-    pub fn as_dir(self) -> PathBuf {
-        self.path
+    pub fn as_dir(&self) -> PathBuf {
+        self.path.clone()
     }
 }
 
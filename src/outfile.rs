@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use gluesql::prelude::{Payload, Value};
+
+use crate::format_value;
+use crate::glue::ExportFormat;
+use crate::html;
+use crate::output::payload_json;
+#[cfg(feature = "parquet")]
+use crate::parquet_file;
+
+/// Split a trailing `INTO OUTFILE path` clause off a query, e.g.
+/// `SELECT * FROM t INTO OUTFILE 'out dir/t.json'`. The path must be quoted
+/// with `'` or `"`. Its extension sets the format, or else it's CSV.
+pub fn split_outfile(query: &str) -> Option<(&str, PathBuf, ExportFormat)> {
+    let trimmed = query.trim_end().trim_end_matches(';').trim_end();
+    let quote = trimmed.chars().last().filter(|c| matches!(c, '\'' | '"'))?;
+    let quoted = &trimmed[..trimmed.len() - 1];
+    let open = quoted.rfind(quote)?;
+    let path = &quoted[open + 1..];
+
+    let clause = quoted[..open].trim_end();
+    let clause = strip_keyword(clause, "OUTFILE")?.trim_end();
+    let sql = strip_keyword(clause, "INTO")?;
+    // The keywords mustn't be within a string, e.g. `WHERE a = ' INTO OUTFILE '`
+    if !sql.ends_with(char::is_whitespace) || within_quotes(sql) {
+        return None;
+    }
+
+    let path = PathBuf::from(path);
+    let format = ExportFormat::from_path(&path).unwrap_or(ExportFormat::Csv);
+    Some((sql, path, format))
+}
+
+/// `text` without `keyword` at its end, ignoring case
+fn strip_keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let split = text.len().checked_sub(keyword.len())?;
+    let (rest, end) = (text.get(..split)?, &text[split..]);
+    end.eq_ignore_ascii_case(keyword).then_some(rest)
+}
+
+/// Whether `text` ends within a quoted string or identifier
+fn within_quotes(text: &str) -> bool {
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    quote.is_some()
+}
+
+/// Where to write the result of a query, rather than showing it
+pub struct Outfile {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    /// Write the column names alone, as a header row in CSV,
+    /// an array in JSON, and an empty table otherwise
+    pub header_only: bool,
+    /// Replace the file if it exists
+    pub overwrite: bool,
+}
+
+/// Write the rows selected by a query to a file, with `delimiter`
+/// between the fields of CSV
+pub fn write_payload(payload: Payload, outfile: &Outfile, delimiter: u8) -> anyhow::Result<usize> {
+    let (labels, mut rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        _ => bail!("only the results of a SELECT can be written to a file"),
+    };
+    if outfile.header_only {
+        rows.clear();
+    }
+    let nrows = rows.len();
+
+    let path = &outfile.path;
+    let file = File::create(path).with_context(|| format!("creating {:?}", path))?;
+    let mut out = BufWriter::new(file);
+    match outfile.format {
+        ExportFormat::Json if outfile.header_only => {
+            serde_json::to_writer(&mut out, &labels)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(out);
+            writer.write_record(&labels)?;
+            for row in rows {
+                writer.write_record(row.into_iter().map(|value| match value {
+                    Value::Null => String::new(),
+                    other => format_value(other),
+                }))?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Json => {
+            let json = payload_json(Payload::Select { labels, rows });
+            serde_json::to_writer(&mut out, &json)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        ExportFormat::Html => {
+            let rows = rows.into_iter().map(|row| Ok(html::row_cells(row)));
+            html::write_table(&mut out, &labels, rows, false)?;
+            out.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => parquet_file::write_rows(out, &labels, &rows)?,
+    }
+
+    Ok(nrows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_outfile() {
+        let (sql, path, format) =
+            split_outfile("SELECT * FROM t WHERE a > 5 into outfile 'my dir/out.json';")
+                .expect("outfile");
+        assert_eq!(sql, "SELECT * FROM t WHERE a > 5 ");
+        assert_eq!(path, PathBuf::from("my dir/out.json"));
+        assert_eq!(format, ExportFormat::Json);
+
+        let (_, path, format) = split_outfile("SELECT 1 INTO OUTFILE \"out\"").expect("outfile");
+        assert_eq!(path, PathBuf::from("out"));
+        assert_eq!(format, ExportFormat::Csv);
+
+        // Comparisons aren't mistaken for redirections
+        assert!(split_outfile("SELECT * FROM t WHERE a > 'x.csv'").is_none());
+        assert!(split_outfile("SELECT * FROM t WHERE a > 5 > 'x.csv'").is_none());
+        assert!(split_outfile("SELECT * FROM t WHERE name = ' INTO OUTFILE 'x.csv'").is_none());
+        assert!(split_outfile("SELECT * FROM t WHERE name = 'INTO OUTFILE' AND b = 'x'").is_none());
+        assert!(split_outfile("SELECT * FROM t WHERE name = 'a INTO OUTFILE x.csv'").is_none());
+    }
+}
@@ -0,0 +1,581 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+use gluesql::core::ast::{Expr, SelectItem, SetExpr, Statement, TableFactor};
+use gluesql::prelude::{DataType, Payload, Value};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::config::TransposeRows;
+use crate::format_value;
+use crate::glue::ROWID_COLUMN;
+
+/// Header of the row id gutter
+const GUTTER_LABEL: &str = "#";
+
+/// How query results and listings are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable tables
+    #[default]
+    Table,
+    /// One JSON document per statement
+    Json,
+    /// An HTML `<table>` per statement
+    Html,
+    /// Selected rows as CSV, with a header row of column labels
+    Csv,
+}
+
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// Indent JSON output
+    pub pretty: bool,
+    /// Emit selected rows as arrays, alongside column names and types
+    pub typed: bool,
+    /// Show a single selected row as `field | value` pairs
+    pub transpose: bool,
+    /// How to show several rows when transposing
+    pub transpose_rows: TransposeRows,
+    /// Prefix each row of a table with its row id
+    pub gutter: bool,
+    /// Print a selected cell's bare value, rather than a table
+    pub value: bool,
+    /// Shown for NULL values in tables and bare values
+    pub null_string: String,
+    /// Precede HTML tables with minimal inline CSS
+    pub html_style: bool,
+    /// Names to show columns by, keyed by column name (see `--rename`)
+    pub rename: HashMap<String, String>,
+    /// Repeat the header row of a table after every this many rows
+    pub repeat_header: Option<usize>,
+    /// Field delimiter of CSV output and exports
+    pub out_delimiter: u8,
+    /// Show tables in sections by the values of this column (see `--group-by`)
+    pub group_by: Option<String>,
+    /// Let `INTO OUTFILE` replace existing files (see `query --force`)
+    pub overwrite: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::default(),
+            pretty: false,
+            typed: false,
+            transpose: false,
+            transpose_rows: TransposeRows::default(),
+            gutter: false,
+            value: false,
+            null_string: "NULL".to_string(),
+            html_style: false,
+            rename: HashMap::new(),
+            repeat_header: None,
+            out_delimiter: b',',
+            group_by: None,
+            overwrite: false,
+        }
+    }
+}
+
+impl Output {
+    /// A value as shown in a table or by itself
+    pub fn display_value(&self, value: Value) -> String {
+        match value {
+            Value::Null => self.null_string.clone(),
+            other => format_value(other),
+        }
+    }
+
+    /// Serialize a JSON document, compact unless `pretty` is set
+    pub fn json_string(&self, value: &JsonValue) -> String {
+        if self.pretty {
+            serde_json::to_string_pretty(value).expect("JSON values always serialize")
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+/// The only value selected, for a SELECT of exactly one row and column
+pub fn single_value(payload: &Payload, output: &Output) -> anyhow::Result<Option<String>> {
+    let (labels, rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        _ => return Ok(None),
+    };
+
+    match rows.as_slice() {
+        [row] if labels.len() == 1 => Ok(row
+            .first()
+            .cloned()
+            .map(|value| output.display_value(value))),
+        _ => bail!(
+            "expected a single value, but selected {} rows of {} columns",
+            rows.len(),
+            labels.len()
+        ),
+    }
+}
+
+/// Write selected rows as CSV with this field delimiter, quoting fields
+/// as needed. NULL is an empty field, as when rows are stored.
+pub fn rows_csv(labels: &[String], rows: Vec<Vec<Value>>, delimiter: u8) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    let records = std::iter::once(labels.to_vec()).chain(rows.into_iter().map(|row| {
+        row.into_iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                other => format_value(other),
+            })
+            .collect()
+    }));
+    for record in records {
+        writer
+            .write_record(&record)
+            .expect("writing to a Vec can't fail");
+    }
+    let out = writer.into_inner().expect("writing to a Vec can't fail");
+    String::from_utf8(out).expect("CSV of strings is UTF-8")
+}
+
+/// Represent a SQL value as JSON, keeping numbers and booleans unquoted
+/// and maps and lists nested
+pub fn value_json(value: Value) -> JsonValue {
+    match value {
+        Value::Map(map) => JsonValue::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, value_json(value)))
+                .collect(),
+        ),
+        Value::List(list) => JsonValue::Array(list.into_iter().map(value_json).collect()),
+        Value::Null => JsonValue::Null,
+        Value::Bool(x) => json!(x),
+        Value::I8(x) => json!(x),
+        Value::I16(x) => json!(x),
+        Value::I32(x) => json!(x),
+        Value::I64(x) => json!(x),
+        Value::F64(x) => json!(x),
+        other => JsonValue::String(format_value(other)),
+    }
+}
+
+/// Represent a statement's result as JSON.
+/// Selected rows become objects keyed by column label.
+pub fn payload_json(payload: Payload) -> JsonValue {
+    match payload {
+        Payload::ShowColumns(cols) => {
+            let cols: Vec<_> = cols
+                .into_iter()
+                .map(|(name, typ)| json!({ "name": name, "type": typ.to_string() }))
+                .collect();
+            json!({ "columns": cols })
+        }
+        Payload::Create => json!({ "created": true }),
+        Payload::Insert(n) => json!({ "inserted": n }),
+        Payload::Select { labels, rows } => {
+            let rows: Vec<_> = rows
+                .into_iter()
+                .map(|row| {
+                    let object: Map<_, _> = labels
+                        .iter()
+                        .cloned()
+                        .zip(row.into_iter().map(value_json))
+                        .collect();
+                    JsonValue::Object(object)
+                })
+                .collect();
+            JsonValue::Array(rows)
+        }
+        Payload::Delete(n) => json!({ "deleted": n }),
+        Payload::Update(n) => json!({ "updated": n }),
+        Payload::DropTable => json!({ "dropped": true }),
+    }
+}
+
+/// The table a query selects from, if it reads from exactly one table
+pub fn source_table(statement: &Statement) -> Option<&str> {
+    let query = match statement {
+        Statement::Query(query) => query,
+        _ => return None,
+    };
+    let select = match &query.body {
+        SetExpr::Select(select) => select,
+        SetExpr::Values(_) => return None,
+    };
+    if !select.from.joins.is_empty() {
+        return None;
+    }
+    match &select.from.relation {
+        TableFactor::Table { name, .. } => name.0.last().map(String::as_str),
+        _ => None,
+    }
+}
+
+/// Rename the selected columns which appear in `names`,
+/// e.g. to show columns by their original headers
+pub fn relabel_columns(payload: Payload, names: &HashMap<String, String>) -> Payload {
+    match payload {
+        Payload::Select { labels, rows } => {
+            let labels = labels
+                .into_iter()
+                .map(|label| names.get(&label).cloned().unwrap_or(label))
+                .collect();
+            Payload::Select { labels, rows }
+        }
+        other => other,
+    }
+}
+
+/// Like `relabel_columns`, but only for columns selected from the table
+/// itself: labels given with `AS`, or to computed values, are kept as
+/// written, so e.g. `a + b AS total` stays `total` when exported
+pub fn relabel_table_columns(
+    payload: Payload,
+    names: &HashMap<String, String>,
+    statement: &Statement,
+) -> Payload {
+    let explicit = explicit_labels(statement);
+    let names = names
+        .iter()
+        .filter(|(label, _)| !explicit.contains(*label))
+        .map(|(label, name)| (label.clone(), name.clone()))
+        .collect();
+    relabel_columns(payload, &names)
+}
+
+/// Labels of the selected values which aren't just a column by its name
+fn explicit_labels(statement: &Statement) -> HashSet<String> {
+    let select = match statement {
+        Statement::Query(query) => match &query.body {
+            SetExpr::Select(select) => select,
+            SetExpr::Values(_) => return HashSet::new(),
+        },
+        _ => return HashSet::new(),
+    };
+
+    select
+        .projection
+        .iter()
+        .filter_map(|item| match item {
+            SelectItem::Expr {
+                expr: Expr::Identifier(name) | Expr::CompoundIdentifier { ident: name, .. },
+                label,
+            } if name == label => None,
+            SelectItem::Expr { label, .. } => Some(label.clone()),
+            SelectItem::QualifiedWildcard(_) | SelectItem::Wildcard => None,
+        })
+        .collect()
+}
+
+/// Parse `--rename`'s `old=new,old2=new2` into a map from old names to new
+pub fn parse_renames(spec: &str) -> anyhow::Result<HashMap<String, String>> {
+    spec.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+                Ok((old.trim().to_string(), new.trim().to_string()))
+            }
+            _ => bail!("expected old=new, not {:?}", pair),
+        })
+        .collect()
+}
+
+/// Parse the delimiter of CSV output: a single ASCII character other
+/// than the quote `"` or a line break, or `\t` for a tab
+pub fn parse_out_delimiter(spec: &str) -> anyhow::Result<u8> {
+    let mut chars = spec.chars();
+    match (chars.next(), chars.next()) {
+        _ if spec == "\\t" => delimiter_byte('\t'),
+        (Some(c), None) => delimiter_byte(c),
+        _ => bail!("expected a single character, not {:?}", spec),
+    }
+}
+
+/// A CSV delimiter as a byte, failing unless it's an ASCII
+/// character other than the quote `"` or a line break
+pub fn delimiter_byte(c: char) -> anyhow::Result<u8> {
+    match u8::try_from(c) {
+        Ok(b'"') => bail!("the delimiter can't be the quote character"),
+        Ok(b'\n' | b'\r') => bail!("the delimiter can't be a line break"),
+        Ok(byte) if byte.is_ascii() => Ok(byte),
+        _ => bail!("the delimiter must be an ASCII character, not {:?}", c),
+    }
+}
+
+/// Show the selected columns named in `renames` by their new names.
+/// Names which aren't selected are ignored with a warning.
+/// Other payloads are returned unchanged.
+pub fn rename_columns(payload: Payload, renames: &HashMap<String, String>) -> Payload {
+    let (labels, rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        other => return other,
+    };
+
+    for old in renames.keys() {
+        if !labels.contains(old) {
+            eprintln!("warning: renamed column {:?} not selected", old);
+        }
+    }
+    relabel_columns(Payload::Select { labels, rows }, renames)
+}
+
+/// Whether a statement selects every column of its table, in order,
+/// with a bare `SELECT *`
+pub fn selects_all(statement: &Statement) -> bool {
+    match statement {
+        Statement::Query(query) => match &query.body {
+            SetExpr::Select(select) => matches!(select.projection[..], [SelectItem::Wildcard]),
+            SetExpr::Values(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Move the columns named in `order` to the front of selected rows,
+/// leaving the rest in their original order. Unknown names are ignored,
+/// with a single warning. Other payloads are returned unchanged.
+pub fn reorder_columns(payload: Payload, order: &[String]) -> Payload {
+    let (labels, rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        other => return other,
+    };
+
+    let mut indices = Vec::new();
+    let mut unknown = Vec::new();
+    for name in order {
+        match labels.iter().position(|label| label == name) {
+            Some(i) if !indices.contains(&i) => indices.push(i),
+            Some(_) => {}
+            None => unknown.push(name),
+        }
+    }
+    if !unknown.is_empty() {
+        eprintln!("warning: display_order columns {:?} not selected", unknown);
+    }
+    let rest: Vec<_> = (0..labels.len()).filter(|i| !indices.contains(i)).collect();
+    indices.extend(rest);
+
+    let labels = indices.iter().map(|&i| labels[i].clone()).collect();
+    let rows = rows
+        .into_iter()
+        .map(|row| indices.iter().map(|&i| row[i].clone()).collect())
+        .collect();
+
+    Payload::Select { labels, rows }
+}
+
+/// A copy of a query which also selects each row's id, if it selects
+/// individual rows from exactly one table (i.e. it doesn't aggregate).
+pub fn with_rowid_gutter(statement: &Statement) -> Option<Statement> {
+    source_table(statement)?;
+
+    let mut statement = statement.clone();
+    let select = match &mut statement {
+        Statement::Query(query) => match &mut query.body {
+            SetExpr::Select(select) => select,
+            SetExpr::Values(_) => return None,
+        },
+        _ => return None,
+    };
+    let aggregates = select.projection.iter().any(|item| {
+        matches!(
+            item,
+            SelectItem::Expr {
+                expr: Expr::Aggregate(_),
+                ..
+            }
+        )
+    });
+    if aggregates || !select.group_by.is_empty() || select.having.is_some() {
+        return None;
+    }
+
+    let rowid = SelectItem::Expr {
+        expr: Expr::Identifier(ROWID_COLUMN.to_string()),
+        label: ROWID_COLUMN.to_string(),
+    };
+    select.projection.insert(0, rowid);
+
+    Some(statement)
+}
+
+/// Move the row id column selected by `with_rowid_gutter` to the front
+/// as a gutter, dropping any other copies (e.g. from a `*`).
+pub fn gutter_payload(payload: Payload) -> Payload {
+    let (labels, rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        other => return other,
+    };
+
+    let rowid_indices: Vec<_> = (0..labels.len())
+        .filter(|&i| labels[i] == ROWID_COLUMN)
+        .collect();
+    let gutter = match rowid_indices.first() {
+        Some(&i) => i,
+        None => return Payload::Select { labels, rows },
+    };
+    let mut indices = vec![gutter];
+    indices.extend((0..labels.len()).filter(|i| !rowid_indices.contains(i)));
+
+    let labels = indices
+        .iter()
+        .map(|&i| match i == gutter {
+            true => GUTTER_LABEL.to_string(),
+            false => labels[i].clone(),
+        })
+        .collect();
+    let rows = rows
+        .into_iter()
+        .map(|row| indices.iter().map(|&i| row[i].clone()).collect())
+        .collect();
+
+    Payload::Select { labels, rows }
+}
+
+/// The types of a select's columns which come straight from its table's
+/// `columns`, by label: every column of a wildcard, those selected by
+/// name, and values cast to a type
+pub fn declared_types(
+    statement: &Statement,
+    columns: &[(String, DataType)],
+) -> HashMap<String, DataType> {
+    let select = match statement {
+        Statement::Query(query) => match &query.body {
+            SetExpr::Select(select) => select,
+            SetExpr::Values(_) => return HashMap::new(),
+        },
+        _ => return HashMap::new(),
+    };
+    let column_type = |name: &str| {
+        columns
+            .iter()
+            .find(|(column, _)| column.eq_ignore_ascii_case(name))
+            .map(|(_, typ)| typ.clone())
+    };
+
+    let mut types = HashMap::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => {
+                types.extend(columns.iter().cloned());
+            }
+            SelectItem::Expr {
+                expr: Expr::Identifier(name) | Expr::CompoundIdentifier { ident: name, .. },
+                label,
+            } => {
+                if let Some(typ) = column_type(name) {
+                    types.insert(label.clone(), typ);
+                }
+            }
+            SelectItem::Expr {
+                expr: Expr::Cast { data_type, .. },
+                label,
+            } => {
+                types.insert(label.clone(), data_type.clone());
+            }
+            SelectItem::Expr { .. } => {}
+        }
+    }
+    types
+}
+
+/// Represent a statement's result as JSON, listing the name and type of
+/// each selected column before the rows. A column's type is given by
+/// `types`, in the same order as the labels, or else is that of its first
+/// non-null value (or null, if there is none).
+pub fn payload_json_typed(payload: Payload, types: &[Option<DataType>]) -> JsonValue {
+    let (labels, rows) = match payload {
+        Payload::Select { labels, rows } => (labels, rows),
+        other => return payload_json(other),
+    };
+
+    let columns: Vec<_> = labels
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let data_type = types
+                .get(i)
+                .cloned()
+                .flatten()
+                .or_else(|| {
+                    rows.iter()
+                        .find_map(|row| row.get(i).and_then(Value::get_type))
+                })
+                .map(|typ| format!("{:?}", typ));
+            json!({ "name": name, "type": data_type })
+        })
+        .collect();
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|row| JsonValue::Array(row.into_iter().map(value_json).collect()))
+        .collect();
+
+    json!({ "columns": columns, "rows": rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder_columns() {
+        let payload = Payload::Select {
+            labels: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![vec![Value::I32(1), Value::I32(2), Value::I32(3)]],
+        };
+        let order = vec!["c".to_string(), "missing".to_string(), "a".to_string()];
+
+        let reordered = reorder_columns(payload, &order);
+        assert_eq!(
+            reordered,
+            Payload::Select {
+                labels: vec!["c".to_string(), "a".to_string(), "b".to_string()],
+                rows: vec![vec![Value::I32(3), Value::I32(1), Value::I32(2)]],
+            }
+        );
+    }
+
+    #[test]
+    fn test_typed_json() {
+        let payload = Payload::Select {
+            labels: vec!["id".to_string(), "name".to_string()],
+            rows: vec![
+                vec![Value::I32(123), Value::Null],
+                vec![Value::I32(4), Value::Str("a".to_string())],
+            ],
+        };
+
+        let json = payload_json_typed(payload, &[]);
+        assert_eq!(
+            json,
+            json!({
+                "columns": [
+                    { "name": "id", "type": "Int32" },
+                    { "name": "name", "type": "Text" },
+                ],
+                "rows": [[123, null], [4, "a"]],
+            })
+        );
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        let payload = || Payload::Select {
+            labels: vec!["id".to_string(), "name".to_string()],
+            rows: vec![vec![Value::I32(1), Value::Str("a".to_string())]],
+        };
+
+        let compact = Output::default().json_string(&payload_json(payload()));
+        assert_eq!(compact, r#"[{"id":1,"name":"a"}]"#);
+
+        let pretty = Output {
+            pretty: true,
+            ..Output::default()
+        }
+        .json_string(&payload_json(payload()));
+        assert!(pretty.contains('\n'));
+        assert!(pretty.contains("\n    \"id\": 1"), "{}", pretty);
+    }
+}
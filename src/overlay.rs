@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use gluesql::core::data::{Row, Schema};
+
+/// Table edits held in memory until they're flushed to disk
+#[derive(Debug, Default)]
+pub struct Overlay {
+    /// Pending contents of each modified table, or `None` if it was dropped
+    tables: HashMap<String, Option<OverlayTable>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OverlayTable {
+    pub schema: Schema,
+    pub rows: Vec<Row>,
+}
+
+impl Overlay {
+    /// Pending state of a table, if it's been modified.
+    /// `Some(None)` means the table was dropped.
+    pub fn get(&self, table_name: &str) -> Option<Option<&OverlayTable>> {
+        self.tables.get(table_name).map(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, table_name: &str) -> Option<&mut Option<OverlayTable>> {
+        self.tables.get_mut(table_name)
+    }
+
+    pub fn set(&mut self, table_name: String, table: Option<OverlayTable>) {
+        self.tables.insert(table_name, table);
+    }
+
+    /// Each modified table's name and pending state
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&OverlayTable>)> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.as_str(), table.as_ref()))
+    }
+
+    /// Number of tables with pending edits
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Remove and return all pending edits
+    pub fn take(&mut self) -> HashMap<String, Option<OverlayTable>> {
+        std::mem::take(&mut self.tables)
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Context;
+use gluesql::prelude::Value;
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+
+use crate::format_value;
+
+/// How a column is stored: as the narrowest kind all its non-NULL
+/// values fit, or else as text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Bool,
+    Int,
+    Float,
+    Text,
+}
+
+fn value_kind(value: &Value) -> Option<Kind> {
+    match value {
+        Value::Null => None,
+        Value::Bool(_) => Some(Kind::Bool),
+        Value::I8(_) | Value::I16(_) | Value::I32(_) | Value::I64(_) => Some(Kind::Int),
+        Value::F64(_) => Some(Kind::Float),
+        _ => Some(Kind::Text),
+    }
+}
+
+fn column_kind<'a>(values: impl Iterator<Item = &'a Value>) -> Kind {
+    let mut column = None;
+    for kind in values.filter_map(value_kind) {
+        column = Some(match (column, kind) {
+            (None, kind) => kind,
+            (Some(prev), kind) if prev == kind => kind,
+            (Some(Kind::Int | Kind::Float), Kind::Int | Kind::Float) => Kind::Float,
+            _ => return Kind::Text,
+        });
+    }
+    column.unwrap_or(Kind::Text)
+}
+
+fn as_i64(value: &Value) -> i64 {
+    match value {
+        Value::I8(n) => (*n).into(),
+        Value::I16(n) => (*n).into(),
+        Value::I32(n) => (*n).into(),
+        Value::I64(n) => *n,
+        _ => unreachable!("not an integer: {:?}", value),
+    }
+}
+
+fn as_f64(value: &Value) -> f64 {
+    match value {
+        Value::F64(x) => *x,
+        // Integers beyond 2^53 lose precision, as they would in JSON
+        other => as_i64(other) as f64,
+    }
+}
+
+/// Write rows to `out` as a Parquet file with a single row group.
+/// Every column is optional, with NULLs as missing values.
+pub fn write_rows<W: Write + Send>(
+    out: W,
+    labels: &[String],
+    rows: &[Vec<Value>],
+) -> anyhow::Result<()> {
+    let kinds: Vec<_> = (0..labels.len())
+        .map(|i| column_kind(rows.iter().map(|row| &row[i])))
+        .collect();
+    let fields = labels
+        .iter()
+        .zip(&kinds)
+        .map(|(label, kind)| {
+            let (physical, logical) = match kind {
+                Kind::Bool => (PhysicalType::BOOLEAN, None),
+                Kind::Int => (PhysicalType::INT64, None),
+                Kind::Float => (PhysicalType::DOUBLE, None),
+                Kind::Text => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+            };
+            let field = Type::primitive_type_builder(label, physical)
+                .with_repetition(Repetition::OPTIONAL)
+                .with_logical_type(logical)
+                .build()
+                .with_context(|| format!("column {:?}", label))?;
+            Ok(Arc::new(field))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let schema = Type::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?;
+
+    let properties = WriterProperties::builder().build();
+    let mut writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for (i, kind) in kinds.iter().enumerate() {
+        let mut column = row_group
+            .next_column()?
+            .context("no writer for the next column")?;
+        let values = rows.iter().map(|row| &row[i]);
+        let levels: Vec<i16> = values
+            .clone()
+            .map(|value| i16::from(!matches!(value, Value::Null)))
+            .collect();
+        let present = values.filter(|value| !matches!(value, Value::Null));
+        match kind {
+            Kind::Bool => {
+                let data: Vec<_> = present.map(|value| value == &Value::Bool(true)).collect();
+                column
+                    .typed::<BoolType>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+            Kind::Int => {
+                let data: Vec<_> = present.map(as_i64).collect();
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+            Kind::Float => {
+                let data: Vec<_> = present.map(as_f64).collect();
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+            Kind::Text => {
+                let data: Vec<_> = present
+                    .map(|value| ByteArray::from(format_value(value.clone()).into_bytes()))
+                    .collect();
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+        }
+        column.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn test_write_rows() {
+        let labels = vec!["n".to_string(), "x".to_string(), "name".to_string()];
+        let rows = vec![
+            vec![
+                Value::I32(1),
+                Value::F64(0.5),
+                Value::Str("ann".to_string()),
+            ],
+            vec![Value::Null, Value::I64(2), Value::Null],
+        ];
+        let tmpdir = tempdir::TempDir::new("feet-parquet").expect("tmpdir");
+        let path = tmpdir.path().join("t.parquet");
+        let file = std::fs::File::create(&path).expect("create");
+        write_rows(file, &labels, &rows).expect("write");
+
+        let file = std::fs::File::open(&path).expect("open");
+        let reader = SerializedFileReader::new(file).expect("read");
+        let fields = reader.metadata().file_metadata().schema().get_fields();
+        let types: Vec<_> = fields
+            .iter()
+            .map(|field| (field.name(), field.get_physical_type()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("n", PhysicalType::INT64),
+                ("x", PhysicalType::DOUBLE),
+                ("name", PhysicalType::BYTE_ARRAY)
+            ]
+        );
+
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .expect("rows")
+            .map(|row| row.expect("row").to_string())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "{n: 1, x: 0.5, name: \"ann\"}",
+                "{n: null, x: 2.0, name: null}"
+            ]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use gluesql::core::ast::{AstLiteral, BinaryOperator, Expr, SetExpr, Statement, TableFactor};
+
+/// Comparison of a column against a string literal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cmp {
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Eq,
+}
+
+impl Cmp {
+    fn from_op(op: &BinaryOperator) -> Option<Self> {
+        match op {
+            BinaryOperator::Lt => Some(Cmp::Lt),
+            BinaryOperator::LtEq => Some(Cmp::LtEq),
+            BinaryOperator::Gt => Some(Cmp::Gt),
+            BinaryOperator::GtEq => Some(Cmp::GtEq),
+            BinaryOperator::Eq => Some(Cmp::Eq),
+            _ => None,
+        }
+    }
+
+    /// The same comparison with its operands swapped
+    fn flip(self) -> Self {
+        match self {
+            Cmp::Lt => Cmp::Gt,
+            Cmp::LtEq => Cmp::GtEq,
+            Cmp::Gt => Cmp::Lt,
+            Cmp::GtEq => Cmp::LtEq,
+            Cmp::Eq => Cmp::Eq,
+        }
+    }
+}
+
+/// Constraints which every matching value of a column must satisfy
+#[derive(Clone, Debug, Default)]
+pub struct Bounds {
+    constraints: Vec<(Cmp, String)>,
+}
+
+impl Bounds {
+    pub fn contains(&self, value: &str) -> bool {
+        self.constraints.iter().all(|(cmp, bound)| {
+            let bound = bound.as_str();
+            match cmp {
+                Cmp::Lt => value < bound,
+                Cmp::LtEq => value <= bound,
+                Cmp::Gt => value > bound,
+                Cmp::GtEq => value >= bound,
+                Cmp::Eq => value == bound,
+            }
+        })
+    }
+}
+
+/// Bounds on the partition column (by lowercased name) implied by the WHERE
+/// clause of a SELECT from a single partitioned table, whose partition
+/// columns are given by `partitions`. Only comparisons with string literals
+/// joined by AND are considered, so every row the query can return lies
+/// within the bounds. Joins and subqueries, which might read the table
+/// without those conditions, get no bounds.
+pub fn where_bounds(
+    statement: &Statement,
+    partitions: &HashMap<String, String>,
+) -> HashMap<String, Bounds> {
+    let mut bounds = HashMap::new();
+
+    let select = match statement {
+        Statement::Query(query) => match &query.body {
+            SetExpr::Select(select) => select,
+            SetExpr::Values(_) => return bounds,
+        },
+        _ => return bounds,
+    };
+    let (table, alias) = match &select.from.relation {
+        TableFactor::Table { name, alias, .. } if select.from.joins.is_empty() => {
+            match name.0.last() {
+                Some(table) => (table, alias),
+                None => return bounds,
+            }
+        }
+        _ => return bounds,
+    };
+    let (column, selection) = match (partitions.get(table), &select.selection) {
+        (Some(column), Some(selection)) => (column.to_lowercase(), selection),
+        _ => return bounds,
+    };
+    if has_subquery(statement) {
+        return bounds;
+    }
+
+    // Columns may be qualified by the table's alias, or else its name
+    let qualifier = alias.as_ref().map_or(table, |alias| &alias.name);
+    collect_bounds(selection, qualifier, &mut bounds);
+    bounds.retain(|name, _bounds| *name == column);
+
+    bounds
+}
+
+/// Whether a statement has any subqueries, e.g. `WHERE n IN (SELECT ...)`
+fn has_subquery(statement: &Statement) -> bool {
+    fn walk(json: &serde_json::Value) -> bool {
+        match json {
+            serde_json::Value::Object(fields) => fields.iter().any(|(key, value)| {
+                matches!(key.as_str(), "Subquery" | "InSubquery" | "Exists") || walk(value)
+            }),
+            serde_json::Value::Array(items) => items.iter().any(walk),
+            _ => false,
+        }
+    }
+
+    // If it can't be checked, assume the worst
+    serde_json::to_value(statement).map_or(true, |json| walk(&json))
+}
+
+fn add_bound(bounds: &mut HashMap<String, Bounds>, column: String, cmp: Cmp, value: &str) {
+    bounds
+        .entry(column)
+        .or_default()
+        .constraints
+        .push((cmp, value.to_owned()));
+}
+
+fn collect_bounds(expr: &Expr, table: &str, bounds: &mut HashMap<String, Bounds>) {
+    match expr {
+        Expr::Nested(inner) => collect_bounds(inner, table, bounds),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            collect_bounds(left, table, bounds);
+            collect_bounds(right, table, bounds);
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let cmp = match Cmp::from_op(op) {
+                Some(cmp) => cmp,
+                None => return,
+            };
+            match (column_name(left, table), string_literal(right)) {
+                (Some(column), Some(value)) => add_bound(bounds, column, cmp, value),
+                _ => {
+                    if let (Some(value), Some(column)) =
+                        (string_literal(left), column_name(right, table))
+                    {
+                        add_bound(bounds, column, cmp.flip(), value);
+                    }
+                }
+            }
+        }
+        Expr::Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        } => {
+            if let (Some(column), Some(low), Some(high)) = (
+                column_name(expr, table),
+                string_literal(low),
+                string_literal(high),
+            ) {
+                add_bound(bounds, column.clone(), Cmp::GtEq, low);
+                add_bound(bounds, column, Cmp::LtEq, high);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The lowercased name of a column of `table`, if that's what `expr` is
+fn column_name(expr: &Expr, table: &str) -> Option<String> {
+    match expr {
+        Expr::Identifier(name) => Some(name.to_lowercase()),
+        Expr::CompoundIdentifier { alias, ident } if alias == table => Some(ident.to_lowercase()),
+        _ => None,
+    }
+}
+
+fn string_literal(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Literal(AstLiteral::QuotedString(s)) => Some(s),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gluesql::core::parse_sql::parse;
+    use gluesql::core::translate::translate;
+
+    use super::*;
+
+    fn bounds_for(sql: &str) -> HashMap<String, Bounds> {
+        let parsed = parse(sql).expect("parse");
+        let statement = translate(&parsed[0]).expect("translate");
+        let partitions = [("events".to_string(), "month".to_string())].into();
+        where_bounds(&statement, &partitions)
+    }
+
+    #[test]
+    fn test_where_bounds() {
+        let bounds = bounds_for("SELECT * FROM events WHERE month BETWEEN '2023-01' AND '2023-03'");
+        let month = &bounds["month"];
+        assert!(!month.contains("2022-12"));
+        assert!(month.contains("2023-01"));
+        assert!(month.contains("2023-02"));
+        assert!(month.contains("2023-03"));
+        assert!(!month.contains("2023-04"));
+
+        let bounds = bounds_for("SELECT * FROM events WHERE '2023-02' < Month");
+        assert!(!bounds["month"].contains("2023-02"));
+        assert!(bounds["month"].contains("2023-03"));
+
+        // Nothing can be pruned across an OR
+        let bounds = bounds_for("SELECT * FROM events WHERE month = '2023-01' OR n > 1");
+        assert!(bounds.is_empty());
+
+        // Only the partition column of the table selected from is bounded
+        let bounds = bounds_for("SELECT * FROM events e WHERE e.month = '2023-01' AND k = 'x'");
+        assert_eq!(bounds.keys().collect::<Vec<_>>(), vec!["month"]);
+        assert!(bounds_for("SELECT * FROM other WHERE month = '2023-01'").is_empty());
+        assert!(bounds_for("SELECT * FROM events e WHERE events.month = '2023-01'").is_empty());
+    }
+
+    #[test]
+    fn test_where_bounds_join() {
+        // `month` here might be a column of either table, and the condition
+        // on `o.month` says nothing about the rows of `events`
+        for sql in [
+            "SELECT * FROM events JOIN other ON events.n = other.n WHERE month = '2023-01'",
+            "SELECT * FROM events e JOIN other o ON e.n = o.n WHERE o.month = '2023-01'",
+            "SELECT * FROM other WHERE n IN (SELECT n FROM events) AND month = '2023-01'",
+            "SELECT * FROM events WHERE month = '2023-01' AND n IN (SELECT n FROM events)",
+        ] {
+            assert!(bounds_for(sql).is_empty(), "{}", sql);
+        }
+    }
+}
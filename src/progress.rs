@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Files smaller than this are read without a progress bar
+pub const MIN_BYTES: u64 = 16 << 20;
+
+/// Width of the bar, in characters
+const BAR_WIDTH: u64 = 30;
+
+/// A single bar showing how much of the files being read have been,
+/// drawn on `out`. Files read at once (e.g. in parallel by exports)
+/// share the bar, so their progress doesn't garble the line. Each file
+/// gets a bar only once until `reset`, as queries may scan it repeatedly.
+pub struct Progress<W> {
+    state: Arc<Mutex<State<W>>>,
+}
+
+struct State<W> {
+    out: W,
+    /// Names of the files being read
+    active: Vec<String>,
+    total: u64,
+    read: u64,
+    shown_percent: Option<u64>,
+    /// Files that have had a bar since the last reset
+    shown: HashSet<PathBuf>,
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(out: W) -> Self {
+        let state = State {
+            out,
+            active: Vec::new(),
+            total: 0,
+            read: 0,
+            shown_percent: None,
+            shown: HashSet::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Wrap a reader of the file at `path`, of `size` bytes, to show its
+    /// progress, or return it as is if it's already had a bar
+    pub fn reader<R: Read>(
+        &self,
+        inner: R,
+        path: &Path,
+        size: u64,
+    ) -> Result<ProgressReader<R, W>, R> {
+        let mut state = lock(&self.state);
+        if !state.shown.insert(path.to_owned()) {
+            return Err(inner);
+        }
+
+        let label = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        state.active.push(label.clone());
+        state.total += size;
+        Ok(ProgressReader {
+            inner,
+            state: Arc::clone(&self.state),
+            label,
+            size,
+            read: 0,
+            finished: false,
+        })
+    }
+
+    /// Let every file have a bar again, e.g. for the next query
+    pub fn reset(&self) {
+        lock(&self.state).shown.clear();
+    }
+
+    #[cfg(test)]
+    fn output(&self) -> String
+    where
+        W: AsRef<[u8]>,
+    {
+        String::from_utf8_lossy(lock(&self.state).out.as_ref()).into_owned()
+    }
+}
+
+/// Drawing progress is best-effort, so a panic elsewhere shouldn't stop it
+fn lock<W>(state: &Mutex<State<W>>) -> MutexGuard<'_, State<W>> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<W: Write> State<W> {
+    /// Redraw the bar, if the percentage has changed
+    fn draw(&mut self) -> std::io::Result<()> {
+        let percent = match self.total {
+            0 => 100,
+            total => (self.read * 100 / total).min(100),
+        };
+        if self.shown_percent == Some(percent) {
+            return Ok(());
+        }
+        self.shown_percent = Some(percent);
+
+        let label = match &self.active[..] {
+            [label] => label.clone(),
+            active => format!("{} files", active.len()),
+        };
+        let filled = (percent * BAR_WIDTH / 100) as usize;
+        write!(
+            self.out,
+            "\r{} [{:<width$}] {:>3}%",
+            label,
+            "#".repeat(filled),
+            percent,
+            width = BAR_WIDTH as usize
+        )?;
+        self.out.flush()
+    }
+
+    /// Stop counting a file. Once none are left, the bar's line is ended.
+    fn finish(&mut self, label: &str, unread: u64) -> std::io::Result<()> {
+        if let Some(i) = self.active.iter().position(|active| active == label) {
+            self.active.remove(i);
+        }
+        if !self.active.is_empty() {
+            // Files stopped early (e.g. by a LIMIT) count as read
+            self.read += unread;
+            return self.draw();
+        }
+
+        let drawn = self.shown_percent.is_some();
+        self.total = 0;
+        self.read = 0;
+        self.shown_percent = None;
+        if drawn {
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps a reader, adding what's read to a `Progress` bar. The bar is
+/// only redrawn when the percentage changes, and its line is ended once
+/// every file's been read, or stopped being read early.
+pub struct ProgressReader<R, W: Write> {
+    inner: R,
+    state: Arc<Mutex<State<W>>>,
+    label: String,
+    size: u64,
+    read: u64,
+    finished: bool,
+}
+
+impl<R, W: Write> ProgressReader<R, W> {
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            let unread = self.size.saturating_sub(self.read);
+            lock(&self.state).finish(&self.label, unread).ok();
+        }
+    }
+}
+
+impl<R: Read, W: Write> Read for ProgressReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if !self.finished {
+            self.read += n as u64;
+            let mut state = lock(&self.state);
+            state.read += n as u64;
+            // Progress is best-effort, so failing to show it isn't an error
+            state.draw().ok();
+        }
+        if n == 0 && !buf.is_empty() {
+            self.finish();
+        }
+
+        Ok(n)
+    }
+}
+
+impl<R, W: Write> Drop for ProgressReader<R, W> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_reader() {
+        let data = "a,b\n1,2\n".repeat(100);
+        let progress = Progress::new(Vec::new());
+        let path = Path::new("dir/t.csv");
+
+        let mut read = String::new();
+        progress
+            .reader(data.as_bytes(), path, data.len() as u64)
+            .expect("first reader")
+            .read_to_string(&mut read)
+            .expect("read");
+
+        // The data is untouched, and the bar goes to its own stream
+        assert_eq!(read, data);
+        let output = progress.output();
+        assert!(output.starts_with("\rt.csv ["), "{:?}", output);
+        assert!(output.ends_with(&format!("[{}] 100%\n", "#".repeat(30))));
+
+        // Scanning the file again draws nothing until a reset
+        assert!(progress.reader(data.as_bytes(), path, 1).is_err());
+        progress.reset();
+        assert!(progress.reader(data.as_bytes(), path, 1).is_ok());
+    }
+
+    #[test]
+    fn test_progress_stopped_early() {
+        let data = "a,b\n1,2\n".repeat(100);
+        let progress = Progress::new(Vec::new());
+
+        // e.g. a LIMIT stops reading partway through
+        let mut reader = progress
+            .reader(data.as_bytes(), Path::new("t.csv"), data.len() as u64)
+            .expect("reader");
+        reader.read_exact(&mut [0; 100]).expect("read");
+        drop(reader);
+
+        let output = progress.output();
+        assert!(output.ends_with("%\n"), "{:?}", output);
+        assert_eq!(output.matches('\n').count(), 1);
+    }
+
+    #[test]
+    fn test_progress_shared() {
+        let data = "a,b\n1,2\n".repeat(100);
+        let progress = Progress::new(Vec::new());
+        let size = data.len() as u64;
+
+        let mut first = progress
+            .reader(data.as_bytes(), Path::new("t.csv"), size)
+            .expect("reader");
+        let mut second = progress
+            .reader(data.as_bytes(), Path::new("u.csv"), size)
+            .expect("reader");
+        first.read_to_end(&mut Vec::new()).expect("read");
+        second.read_to_end(&mut Vec::new()).expect("read");
+        drop((first, second));
+
+        // Both files are shown on one line, which ends when they're done
+        let output = progress.output();
+        assert!(output.starts_with("\r2 files ["), "{:?}", output);
+        assert!(output.ends_with("100%\n"), "{:?}", output);
+        assert_eq!(output.matches('\n').count(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use csv::StringRecord;
+
+/// A file to which rows whose values can't be read are diverted
+/// while scanning, so a query can go on with the rest
+pub struct Quarantine {
+    path: PathBuf,
+    /// Opened on the first diverted row, and counting them since
+    /// the last `take_count`
+    state: Mutex<(Option<csv::Writer<File>>, usize)>,
+}
+
+impl Quarantine {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            state: Mutex::new((None, 0)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a row to the file as CSV: the table, the row's line
+    /// number, the error, and then the row's fields as they were read
+    pub fn divert(
+        &self,
+        table_name: &str,
+        line: u64,
+        record: &StringRecord,
+        err: &anyhow::Error,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.lock().expect("quarantine lock poisoned");
+        let (writer, count) = &mut *state;
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .with_context(|| format!("opening quarantine file {:?}", self.path))?;
+                writer.insert(csv::WriterBuilder::new().flexible(true).from_writer(file))
+            }
+        };
+
+        let prefix = [
+            table_name.to_string(),
+            line.to_string(),
+            format!("{:#}", err),
+        ];
+        let fields = prefix.iter().map(String::as_str).chain(record.iter());
+        writer.write_record(fields)?;
+        writer.flush()?;
+        *count += 1;
+
+        Ok(())
+    }
+
+    /// The number of rows diverted since this was last called
+    pub fn take_count(&self) -> usize {
+        let mut state = self.state.lock().expect("quarantine lock poisoned");
+        std::mem::take(&mut state.1)
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::Context;
+use csv::StringRecord;
+use regex::Regex;
+
+/// Open a file whose fields are separated by matches of `delimiter`,
+/// returning the header fields and an iterator over the remaining lines.
+/// Fields are taken verbatim: quoting and escaping aren't supported,
+/// so a field can't contain the delimiter or a line break.
+pub fn open(
+    path: &Path,
+    delimiter: &Regex,
+) -> anyhow::Result<(
+    Vec<String>,
+    impl Iterator<Item = anyhow::Result<StringRecord>>,
+)> {
+    let file = File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let headers = match lines.next() {
+        Some(line) => delimiter.split(&line?).map(ToOwned::to_owned).collect(),
+        None => Vec::new(),
+    };
+
+    let delimiter = delimiter.clone();
+    let records = lines.map(move |line_res| {
+        let line = line_res?;
+        Ok(delimiter.split(&line).collect())
+    });
+
+    Ok((headers, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_regex_delimited() {
+        let tmpdir = tempdir::TempDir::new("feet-regex").expect("tmpdir");
+        let path = tmpdir.path().join("t.csv");
+        std::fs::write(&path, "a :: b::c\n1::2 :: x y\n").expect("write");
+
+        let delimiter = Regex::new(r"\s*::\s*").expect("regex");
+        let (headers, records) = open(&path, &delimiter).expect("open");
+        let records: Vec<_> = records.collect::<anyhow::Result<_>>().expect("records");
+
+        assert_eq!(headers, vec!["a", "b", "c"]);
+        assert_eq!(records, vec![StringRecord::from(vec!["1", "2", "x y"])]);
+    }
+}
@@ -0,0 +1,122 @@
+// GlueSQL's error type is large, but it's what its parser returns
+#![allow(clippy::result_large_err)]
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use gluesql::core::parse_sql::parse_data_type;
+use gluesql::core::translate::translate_data_type;
+use gluesql::prelude::DataType;
+use serde::{Deserialize, Serialize};
+
+/// Suffix of the file beside a table's CSV file which fixes its column
+/// types, e.g. `orders.schema.toml` for `orders.csv`
+pub const SUFFIX: &str = ".schema.toml";
+
+/// Column names and SQL types, e.g.
+///
+/// ```toml
+/// columns = [
+///     { name = "id", type = "INT32" },
+///     { name = "placed", type = "DATE" },
+/// ]
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaFile {
+    pub columns: Vec<SchemaColumn>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaColumn {
+    pub name: String,
+    /// SQL data type
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+impl SchemaFile {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading {:?}", path))?;
+        let schema: SchemaFile =
+            toml::from_slice(&bytes).with_context(|| format!("parsing {:?}", path))?;
+        if schema.columns.is_empty() {
+            bail!("{:?} has no columns", path);
+        }
+        // Check the types while reading, rather than on each use
+        schema
+            .data_types()
+            .with_context(|| format!("invalid schema {:?}", path))?;
+
+        Ok(schema)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = toml::to_string(self)?;
+        std::fs::write(path, contents).with_context(|| format!("writing {:?}", path))
+    }
+
+    /// Column names
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().map(|col| col.name.clone()).collect()
+    }
+
+    /// Column names with their parsed SQL types
+    pub fn data_types(&self) -> anyhow::Result<Vec<(String, DataType)>> {
+        self.columns
+            .iter()
+            .map(|col| {
+                let data_type = parse_type(&col.data_type)
+                    .with_context(|| format!("type of column {:?}", col.name))?;
+                Ok((col.name.clone(), data_type))
+            })
+            .collect()
+    }
+}
+
+/// Parse a SQL type, also accepting the names of sized integers as
+/// `SHOW COLUMNS` displays them (e.g. `INT32`, rather than `INT(32)`)
+fn parse_type(name: &str) -> anyhow::Result<DataType> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "INT8" => Ok(DataType::Int8),
+        "INT16" => Ok(DataType::Int16),
+        "INT32" => Ok(DataType::Int32),
+        "INT128" => Ok(DataType::Int128),
+        _ => parse_data_type(name)
+            .and_then(|sql_type| translate_data_type(&sql_type))
+            .map_err(|err| anyhow!("{}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_schema_file() {
+        let tmpdir = tempdir::TempDir::new("feet-schema").expect("tmpdir");
+        let path = tmpdir.path().join(format!("t{}", SUFFIX));
+
+        std::fs::write(
+            &path,
+            "columns = [{ name = \"id\", type = \"INT32\" }, { name = \"day\", type = \"date\" }]\n",
+        )
+        .expect("write");
+        let schema = SchemaFile::read(&path).expect("read");
+        assert_eq!(
+            schema.data_types().expect("types"),
+            vec![
+                ("id".to_string(), DataType::Int32),
+                ("day".to_string(), DataType::Date),
+            ]
+        );
+
+        std::fs::write(
+            &path,
+            "columns = [{ name = \"id\", type = \"NUMBERISH\" }]\n",
+        )
+        .expect("write");
+        assert!(SchemaFile::read(&path).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+
+use anyhow::{bail, Context};
+use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+
+/// HyperLogLog precision used for approximate distinct counts,
+/// using 2^14 registers.
+const APPROX_PRECISION: u8 = 14;
+
+/// Relative standard error of approximate distinct counts
+pub fn approx_error_rate() -> f64 {
+    1.04 / f64::from(1u32 << APPROX_PRECISION).sqrt()
+}
+
+/// Count occurrences of each distinct value,
+/// most frequent first (ties broken by value).
+pub fn tally_values<I>(values: I) -> anyhow::Result<Vec<(String, usize)>>
+where
+    I: Iterator<Item = anyhow::Result<String>>,
+{
+    let mut counts = HashMap::new();
+    for value in values {
+        *counts.entry(value?).or_insert(0) += 1;
+    }
+
+    let mut tally: Vec<_> = counts.into_iter().collect();
+    tally.sort_by(|(v1, n1), (v2, n2)| n2.cmp(n1).then_with(|| v1.cmp(v2)));
+
+    Ok(tally)
+}
+
+/// Estimate the number of distinct values in bounded memory
+/// using HyperLogLog++, with relative error `approx_error_rate()`.
+pub fn approx_distinct<I>(values: I) -> anyhow::Result<f64>
+where
+    I: Iterator<Item = anyhow::Result<String>>,
+{
+    let hasher = BuildHasherDefault::<DefaultHasher>::default();
+    let mut hll: HyperLogLogPlus<String, _> = HyperLogLogPlus::new(APPROX_PRECISION, hasher)
+        .map_err(|err| anyhow::anyhow!("creating HyperLogLog: {}", err))?;
+    for value in values {
+        hll.insert(&value?);
+    }
+
+    Ok(hll.count())
+}
+
+/// Counts of numeric values in equal-width bins spanning `min..=max`
+#[derive(Debug, PartialEq)]
+pub struct Histogram {
+    pub min: f64,
+    pub max: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// The lower edge of each bin
+    pub fn bin_starts(&self) -> impl Iterator<Item = f64> + '_ {
+        let width = (self.max - self.min) / self.counts.len() as f64;
+        (0..self.counts.len()).map(move |i| self.min + width * i as f64)
+    }
+}
+
+/// Bin numeric values into `bins` equal-width bins, reading them twice:
+/// once for the range and once to count them. Empty values are skipped,
+/// and any other non-numeric value is an error.
+/// Returns `None` if there are no values.
+pub fn histogram<F, I>(values: F, bins: usize) -> anyhow::Result<Option<Histogram>>
+where
+    F: Fn() -> anyhow::Result<I>,
+    I: Iterator<Item = anyhow::Result<String>>,
+{
+    if bins == 0 {
+        bail!("a histogram needs at least one bin");
+    }
+
+    let mut range: Option<(f64, f64)> = None;
+    for x in numbers(values()?) {
+        let x = x?;
+        range = Some(match range {
+            Some((min, max)) => (min.min(x), max.max(x)),
+            None => (x, x),
+        });
+    }
+    let Some((min, max)) = range else {
+        return Ok(None);
+    };
+
+    let mut counts = vec![0; bins];
+    for x in numbers(values()?) {
+        let bin = if max > min {
+            ((x? - min) / (max - min) * bins as f64) as usize
+        } else {
+            0
+        };
+        // The maximum goes in the last bin
+        counts[bin.min(bins - 1)] += 1;
+    }
+
+    Ok(Some(Histogram { min, max, counts }))
+}
+
+fn numbers<I>(values: I) -> impl Iterator<Item = anyhow::Result<f64>>
+where
+    I: Iterator<Item = anyhow::Result<String>>,
+{
+    values
+        .filter(|value| !matches!(value, Ok(value) if value.is_empty()))
+        .map(|value| {
+            let value = value?;
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .with_context(|| format!("{:?} is not a number", value))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tally_values() {
+        let values = ["a", "b", "a", "c", "a", "b"]
+            .into_iter()
+            .map(|v| Ok(v.to_string()));
+
+        let tally = tally_values(values).expect("tally");
+        let expected = vec![
+            ("a".to_string(), 3),
+            ("b".to_string(), 2),
+            ("c".to_string(), 1),
+        ];
+        assert_eq!(tally, expected);
+    }
+
+    #[test]
+    fn test_approx_distinct_within_tolerance() {
+        // 200000 rows with 50000 distinct values
+        let values = || (0..200000).map(|i| Ok(format!("value-{}", i % 50000)));
+
+        let exact = tally_values(values()).expect("tally").len() as f64;
+        let approx = approx_distinct(values()).expect("approx");
+
+        let tolerance = 3.0 * approx_error_rate() * exact;
+        assert_eq!(exact, 50000.0);
+        assert!(
+            (approx - exact).abs() <= tolerance,
+            "estimate {} not within {} of {}",
+            approx,
+            tolerance,
+            exact
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let values = || {
+            Ok(["1", "2", "", "2.5", "4", "9", "10", "3"]
+                .into_iter()
+                .map(|v| Ok(v.to_string())))
+        };
+
+        let hist = histogram(values, 3).expect("histogram").expect("values");
+        assert_eq!(hist.min, 1.0);
+        assert_eq!(hist.max, 10.0);
+        assert_eq!(hist.counts, vec![4, 1, 2]);
+        assert_eq!(hist.bin_starts().collect::<Vec<_>>(), vec![1.0, 4.0, 7.0]);
+
+        let single = || Ok(["5", "5"].into_iter().map(|v| Ok(v.to_string())));
+        let hist = histogram(single, 4).expect("histogram").expect("values");
+        assert_eq!(hist.counts, vec![2, 0, 0, 0]);
+
+        let empty = || Ok([""].into_iter().map(|v| Ok(v.to_string())));
+        assert!(histogram(empty, 4).expect("histogram").is_none());
+
+        let text = || Ok(["1", "abc"].into_iter().map(|v| Ok(v.to_string())));
+        assert!(histogram(text, 4).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use gluesql::core::sqlparser::ast::{Query, SetExpr, Statement, TableFactor, TableWithJoins};
+
+/// Something done to each table factor of a statement, which may
+/// replace it
+pub type Visit<'a> = dyn FnMut(&mut TableFactor) -> anyhow::Result<()> + 'a;
+
+/// Call `visit` on each table factor in the FROM clauses of a query, or
+/// of the query of an INSERT, including those in subqueries and nested
+/// joins. A subquery's factors are visited before the factor holding
+/// it, so whatever `visit` replaces a factor with isn't visited itself.
+pub fn visit_statement(statement: &mut Statement, visit: &mut Visit) -> anyhow::Result<()> {
+    match statement {
+        Statement::Query(query) => visit_query(query, visit),
+        Statement::Insert { source, .. } => visit_query(source, visit),
+        _ => Ok(()),
+    }
+}
+
+/// Call `visit` on each table factor of a query, as by `visit_statement`
+pub fn visit_query(query: &mut Query, visit: &mut Visit) -> anyhow::Result<()> {
+    visit_set_expr(&mut query.body, visit)
+}
+
+fn visit_set_expr(body: &mut SetExpr, visit: &mut Visit) -> anyhow::Result<()> {
+    match body {
+        SetExpr::Select(select) => select
+            .from
+            .iter_mut()
+            .try_for_each(|table| visit_joins(table, visit)),
+        SetExpr::Query(query) => visit_query(query, visit),
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, visit)?;
+            visit_set_expr(right, visit)
+        }
+        SetExpr::Values(_) | SetExpr::Insert(_) => Ok(()),
+    }
+}
+
+fn visit_joins(table: &mut TableWithJoins, visit: &mut Visit) -> anyhow::Result<()> {
+    visit_factor(&mut table.relation, visit)?;
+    table
+        .joins
+        .iter_mut()
+        .try_for_each(|join| visit_factor(&mut join.relation, visit))
+}
+
+fn visit_factor(factor: &mut TableFactor, visit: &mut Visit) -> anyhow::Result<()> {
+    match factor {
+        TableFactor::Derived { subquery, .. } => visit_query(subquery, visit)?,
+        TableFactor::NestedJoin(table) => visit_joins(table, visit)?,
+        _ => (),
+    }
+    visit(factor)
+}
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, bail, Context};
+use gluesql::core::parse_sql::parse;
+use gluesql::core::sqlparser::ast::{Ident, Query, Statement, TableAlias, TableFactor};
+use gluesql::core::translate::translate;
+
+use crate::table_factors;
+
+/// Named queries from `Config::views`, which can be selected from like
+/// tables. GlueSQL has no views, so each use of one in a FROM clause is
+/// replaced by its query, as an aliased subquery, before translation.
+#[derive(Debug, Default)]
+pub struct Views {
+    /// Each view's SQL, as configured
+    definitions: BTreeMap<String, String>,
+    /// Each view's query, with any views it uses already expanded
+    queries: HashMap<String, Query>,
+}
+
+impl Views {
+    /// Parse and expand the views, failing if one isn't a single query
+    /// or is defined in terms of itself
+    pub fn parse(definitions: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let parsed = definitions
+            .iter()
+            .map(|(name, sql)| {
+                let query = parse_query(sql).with_context(|| format!("view {:?}", name))?;
+                Ok((name.clone(), query))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut expander = Expander {
+            parsed,
+            expanded: HashMap::new(),
+            stack: Vec::new(),
+        };
+        for name in definitions.keys() {
+            expander.view(name)?;
+        }
+        for (name, query) in &expander.expanded {
+            translate(&Statement::Query(Box::new(query.clone())))
+                .map_err(|err| anyhow!("{}", err))
+                .with_context(|| format!("view {:?}", name))?;
+        }
+
+        Ok(Self {
+            definitions: definitions
+                .iter()
+                .map(|(name, sql)| (name.clone(), sql.clone()))
+                .collect(),
+            queries: expander.expanded,
+        })
+    }
+
+    /// Each view's name and SQL, in order of name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.definitions
+            .iter()
+            .map(|(name, sql)| (name.as_str(), sql.as_str()))
+    }
+
+    /// Replace each view in a statement's FROM clauses with its query,
+    /// returning whether there were any
+    pub fn rewrite(&self, statement: &mut Statement) -> anyhow::Result<bool> {
+        if self.queries.is_empty() {
+            return Ok(false);
+        }
+        let mut expanded = false;
+        let mut lookup = |name: &str| {
+            let query = self.queries.get(name).cloned();
+            expanded |= query.is_some();
+            Ok(query)
+        };
+        table_factors::visit_statement(statement, &mut |factor| {
+            rewrite_factor(factor, &mut lookup)
+        })?;
+        Ok(expanded)
+    }
+}
+
+fn parse_query(sql: &str) -> anyhow::Result<Query> {
+    let mut statements = parse(sql).map_err(|err| anyhow!("{}", err))?;
+    match (statements.pop(), statements.is_empty()) {
+        (Some(Statement::Query(query)), true) => Ok(*query),
+        _ => bail!("a view must be a single SELECT query"),
+    }
+}
+
+/// Expands views used by other views, keeping track of the views
+/// being expanded to catch cycles
+struct Expander {
+    parsed: HashMap<String, Query>,
+    expanded: HashMap<String, Query>,
+    stack: Vec<String>,
+}
+
+impl Expander {
+    /// The expanded query of the view with this name, if there is one
+    fn view(&mut self, name: &str) -> anyhow::Result<Option<Query>> {
+        if let Some(query) = self.expanded.get(name) {
+            return Ok(Some(query.clone()));
+        }
+        let mut query = match self.parsed.get(name) {
+            Some(query) => query.clone(),
+            None => return Ok(None),
+        };
+        if let Some(start) = self.stack.iter().position(|view| view == name) {
+            let mut cycle = self.stack[start..].to_vec();
+            cycle.push(name.to_string());
+            bail!(
+                "view {:?} is defined in terms of itself: {}",
+                name,
+                cycle.join(" -> ")
+            );
+        }
+
+        self.stack.push(name.to_string());
+        rewrite_query(&mut query, &mut |name| self.view(name))?;
+        self.stack.pop();
+
+        self.expanded.insert(name.to_string(), query.clone());
+        Ok(Some(query))
+    }
+}
+
+type Lookup<'a> = dyn FnMut(&str) -> anyhow::Result<Option<Query>> + 'a;
+
+/// Replace each view in a query's FROM clauses with its query
+fn rewrite_query(query: &mut Query, lookup: &mut Lookup) -> anyhow::Result<()> {
+    table_factors::visit_query(query, &mut |factor| rewrite_factor(factor, lookup))
+}
+
+fn rewrite_factor(factor: &mut TableFactor, lookup: &mut Lookup) -> anyhow::Result<()> {
+    let (name, alias) = match factor {
+        TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } => (name, alias),
+        _ => return Ok(()),
+    };
+    let view = match name.0.as_slice() {
+        [ident] => ident.value.clone(),
+        _ => return Ok(()),
+    };
+    if let Some(subquery) = lookup(&view)? {
+        // GlueSQL requires subqueries to be aliased
+        let alias = alias.take().unwrap_or_else(|| TableAlias {
+            name: Ident::new(view),
+            columns: Vec::new(),
+        });
+        *factor = TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(subquery),
+            alias: Some(alias),
+        };
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Directory within the data directory holding the log
+pub const WAL_DIR: &str = ".feet-wal";
+
+/// Written once a mutation's pre-images are saved
+const INTENT_FILE: &str = "intent.json";
+
+/// A file a mutation is about to modify
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    path: PathBuf,
+    /// Name of the saved copy of the file within the mutation's
+    /// directory, or `None` if it didn't exist yet
+    pre_image: Option<String>,
+}
+
+/// A write-ahead log of mutations in progress. Before any files are
+/// modified, copies of them are saved in a directory for the mutation,
+/// which is removed once it completes. Anything left over after a
+/// crash is rolled back by `recover`.
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+}
+
+/// A mutation recorded in the log, which must be committed once it's done
+#[must_use]
+#[derive(Debug)]
+pub struct Transaction {
+    dir: Option<PathBuf>,
+}
+
+impl Wal {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join(WAL_DIR),
+        }
+    }
+
+    /// Save copies of `paths` before they're modified
+    pub fn begin(&self, paths: &[PathBuf]) -> anyhow::Result<Transaction> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let txn_dir = self.dir.join(format!("{}-{}", nanos, std::process::id()));
+        std::fs::create_dir_all(&txn_dir)
+            .with_context(|| format!("creating log directory {:?}", txn_dir))?;
+
+        let mut entries = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let pre_image = match path.exists() {
+                true => {
+                    let name = format!("{}.pre", i);
+                    std::fs::copy(path, txn_dir.join(&name))
+                        .with_context(|| format!("saving a copy of {:?}", path))?;
+                    Some(name)
+                }
+                false => None,
+            };
+            entries.push(Entry {
+                path: path.clone(),
+                pre_image,
+            });
+        }
+
+        // Until the intent is in place, nothing has been modified
+        let tmp_path = txn_dir.join(format!("{}.tmp", INTENT_FILE));
+        std::fs::write(&tmp_path, serde_json::to_vec(&entries)?)?;
+        std::fs::rename(&tmp_path, txn_dir.join(INTENT_FILE))?;
+
+        Ok(Transaction { dir: Some(txn_dir) })
+    }
+
+    /// Number of mutations which were never committed
+    pub fn pending(&self) -> anyhow::Result<usize> {
+        Ok(self.transaction_dirs()?.len())
+    }
+
+    /// Roll back every mutation which was never committed,
+    /// returning how many there were
+    pub fn recover(&self) -> anyhow::Result<usize> {
+        let txn_dirs = self.transaction_dirs()?;
+        for txn_dir in &txn_dirs {
+            rollback(txn_dir).with_context(|| format!("rolling back {:?}", txn_dir))?;
+        }
+
+        Ok(txn_dirs.len())
+    }
+
+    fn transaction_dirs(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut dirs = std::fs::read_dir(&self.dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Undo the most recent mutations first
+        dirs.sort();
+        dirs.reverse();
+
+        Ok(dirs)
+    }
+}
+
+impl Transaction {
+    /// A transaction for when there's no log
+    pub fn none() -> Self {
+        Self { dir: None }
+    }
+
+    /// Mark the mutation as complete, discarding its pre-images
+    pub fn commit(self) -> anyhow::Result<()> {
+        if let Some(dir) = self.dir {
+            std::fs::remove_dir_all(&dir)
+                .with_context(|| format!("removing log directory {:?}", dir))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Restore the files a mutation recorded to their pre-images
+fn rollback(txn_dir: &Path) -> anyhow::Result<()> {
+    let intent_path = txn_dir.join(INTENT_FILE);
+    // Without an intent, the mutation never started
+    if intent_path.exists() {
+        let entries: Vec<Entry> = serde_json::from_slice(&std::fs::read(&intent_path)?)?;
+        for entry in entries {
+            match entry.pre_image {
+                Some(name) => {
+                    std::fs::copy(txn_dir.join(name), &entry.path)
+                        .with_context(|| format!("restoring {:?}", entry.path))?;
+                }
+                None if entry.path.exists() => std::fs::remove_file(&entry.path)?,
+                None => {}
+            }
+        }
+    }
+
+    std::fs::remove_dir_all(txn_dir)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover_interrupted_mutation() {
+        let tmpdir = tempdir::TempDir::new("feet-wal").expect("tmpdir");
+        let a = tmpdir.path().join("a.csv");
+        let b = tmpdir.path().join("b.csv");
+        let c = tmpdir.path().join("c.csv");
+        std::fs::write(&a, "n\n1\n").expect("write a");
+        std::fs::write(&b, "n\n2\n").expect("write b");
+
+        let wal = Wal::new(tmpdir.path());
+        let txn = wal
+            .begin(&[a.clone(), b.clone(), c.clone()])
+            .expect("begin");
+        // The process dies after writing a and c, but before b
+        std::fs::write(&a, "n\n10\n").expect("modify a");
+        std::fs::write(&c, "n\n30\n").expect("create c");
+        drop(txn);
+
+        assert_eq!(wal.pending().expect("pending"), 1);
+        assert_eq!(wal.recover().expect("recover"), 1);
+
+        let read = |path| std::fs::read_to_string(path).expect("read");
+        assert_eq!(read(&a), "n\n1\n");
+        assert_eq!(read(&b), "n\n2\n");
+        assert!(!c.exists());
+        assert_eq!(wal.pending().expect("pending"), 0);
+
+        // Committed mutations are kept
+        let txn = wal.begin(std::slice::from_ref(&a)).expect("begin");
+        std::fs::write(&a, "n\n100\n").expect("modify a");
+        txn.commit().expect("commit");
+        assert_eq!(wal.recover().expect("recover"), 0);
+        assert_eq!(read(&a), "n\n100\n");
+    }
+}